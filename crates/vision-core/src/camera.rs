@@ -1,57 +1,177 @@
-use ndarray::{Array2, ArrayView2};
+use ndarray::{Array2, ArrayView2, Zip};
 use nokhwa::pixel_format::RgbFormat;
-use nokhwa::utils::{CameraIndex, RequestedFormat, RequestedFormatType};
+use nokhwa::utils::{
+    CameraFormat, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType, Resolution,
+};
 use nokhwa::Camera;
+use std::time::{Duration, Instant};
+use vision_detection::color::{rgb_to_yuv, yuv_to_rgb};
+use vision_detection::synthetic::Scene;
 
-pub fn get_camera(device_id: u32) -> anyhow::Result<Camera> {
-    let index = CameraIndex::Index(device_id);
-    let format_type = RequestedFormatType::AbsoluteHighestFrameRate;
+use crate::config::{CameraConfig, ColorSpace, Config, FrameSourceKind, PixelFormat};
+
+// Where frames come from: a physical camera or a rendered synthetic scene
+pub enum FrameSource {
+    Device(Camera),
+    Synthetic {
+        scene: Scene,
+        frame_index: u64,
+        frame_interval: Duration,
+        next_frame: Instant,
+    },
+}
+
+impl FrameSource {
+    pub fn open(config: &Config) -> anyhow::Result<Self> {
+        match config.camera.source {
+            FrameSourceKind::Device => {
+                let mut camera = get_camera(&config.camera)?;
+                camera.open_stream()?;
+                Ok(Self::Device(camera))
+            }
+            FrameSourceKind::Synthetic => {
+                let width = config.camera.width as usize;
+                let height = config.camera.height as usize;
+                tracing::info!("Rendering synthetic scene at {}x{}", width, height);
+                Ok(Self::Synthetic {
+                    scene: config.synthetic.scene(width, height),
+                    frame_index: 0,
+                    frame_interval: Duration::from_secs_f64(1.0 / config.camera.fps.max(1) as f64),
+                    next_frame: Instant::now(),
+                })
+            }
+        }
+    }
+
+    // Fills `frame` with the next frame in `color_space` and returns its capture timestamp
+    pub fn capture(
+        &mut self,
+        frame: &mut Array2<[u8; 3]>,
+        color_space: ColorSpace,
+    ) -> anyhow::Result<Instant> {
+        match self {
+            Self::Device(camera) => capture_frame(camera, frame, color_space),
+            Self::Synthetic {
+                scene,
+                frame_index,
+                frame_interval,
+                next_frame,
+            } => {
+                // Pace rendering to the configured camera fps
+                let now = Instant::now();
+                if *next_frame > now {
+                    std::thread::sleep(*next_frame - now);
+                }
+                *next_frame = Instant::now() + *frame_interval;
+
+                scene.render(*frame_index, frame);
+                let captured_at = Instant::now();
+                *frame_index += 1;
+
+                if color_space == ColorSpace::Yuv {
+                    convert_to_yuv(frame);
+                }
+                Ok(captured_at)
+            }
+        }
+    }
+}
+
+pub fn get_camera(config: &CameraConfig) -> anyhow::Result<Camera> {
+    let index = CameraIndex::Index(config.device_id);
+    let format_type = match config.pixel_format {
+        PixelFormat::Any => RequestedFormatType::AbsoluteHighestFrameRate,
+        PixelFormat::Yuyv => closest_format(config, FrameFormat::YUYV),
+        PixelFormat::Mjpeg => closest_format(config, FrameFormat::MJPEG),
+    };
     let requested = RequestedFormat::new::<RgbFormat>(format_type);
 
     let camera = Camera::new(index, requested)?;
+    tracing::info!("Camera format: {}", camera.camera_format());
     Ok(camera)
 }
 
-pub fn capture_frame(camera: &mut Camera, rgb_frame: &mut Array2<[u8; 3]>) -> anyhow::Result<()> {
-    let frame = camera.frame()?;
-    let decoded = frame.decode_image::<RgbFormat>()?;
+fn closest_format(config: &CameraConfig, format: FrameFormat) -> RequestedFormatType {
+    let resolution = Resolution::new(config.width, config.height);
+    RequestedFormatType::Closest(CameraFormat::new(resolution, format, config.fps))
+}
+
+// Captures a frame into `frame` and returns its capture timestamp. nokhwa does not
+// expose driver timestamps, so the monotonic clock is read as soon as the frame is dequeued.
+//
+// Pixels are written in `color_space`: YUV frames are unpacked straight from YUYV without
+// an RGB round trip, everything else is decoded to RGB directly into the frame's memory.
+pub fn capture_frame(
+    camera: &mut Camera,
+    frame: &mut Array2<[u8; 3]>,
+    color_space: ColorSpace,
+) -> anyhow::Result<Instant> {
+    let buffer = camera.frame()?;
+    let captured_at = Instant::now();
 
-    let width = decoded.width() as usize;
-    let height = decoded.height() as usize;
+    let resolution = buffer.resolution();
+    let width = resolution.width() as usize;
+    let height = resolution.height() as usize;
 
-    if rgb_frame.shape() != [height, width] {
-        *rgb_frame = Array2::from_elem((height, width), [0u8; 3]);
+    if frame.dim() != (height, width) {
+        *frame = Array2::from_elem((height, width), [0u8; 3]);
     }
 
-    let raw_data = decoded.as_raw();
-    for (i, chunk) in raw_data.chunks_exact(3).enumerate() {
-        let row = i / width;
-        let col = i % width;
-        rgb_frame[(row, col)] = [chunk[0], chunk[1], chunk[2]];
+    match (buffer.source_frame_format(), color_space) {
+        (FrameFormat::YUYV, ColorSpace::Yuv) => {
+            decode_yuyv(buffer.buffer(), frame, |y, u, v| [y, u, v])?;
+        }
+        (FrameFormat::YUYV, _) => {
+            decode_yuyv(buffer.buffer(), frame, |y, u, v| {
+                let (r, g, b) = yuv_to_rgb(y, u, v);
+                [r, g, b]
+            })?;
+        }
+        (_, color_space) => {
+            let pixels = frame
+                .as_slice_mut()
+                .expect("frame buffers are allocated contiguous");
+            buffer.decode_image_to_buffer::<RgbFormat>(pixels.as_flattened_mut())?;
+
+            if color_space == ColorSpace::Yuv {
+                convert_to_yuv(frame);
+            }
+        }
     }
 
-    Ok(())
+    Ok(captured_at)
 }
 
-pub fn resize_array<T: Copy>(
-    arr: ArrayView2<T>,
-    resized: &mut Array2<T>,
-    new_height: usize,
-    new_width: usize,
-) {
-    let (old_height, old_width) = arr.dim();
-
-    let y_ratio = old_height as f32 / new_height as f32;
-    let x_ratio = old_width as f32 / new_width as f32;
-
-    for y in 0..new_height {
-        for x in 0..new_width {
-            let src_y = (y as f32 * y_ratio) as usize;
-            let src_x = (x as f32 * x_ratio) as usize;
-
-            let src_y = src_y.min(old_height - 1);
-            let src_x = src_x.min(old_width - 1);
-            resized[(y, x)] = arr[(src_y, src_x)]
-        }
-    }
+fn convert_to_yuv(frame: &mut Array2<[u8; 3]>) {
+    frame.par_map_inplace(|pixel| {
+        let (y, u, v) = rgb_to_yuv(pixel[0], pixel[1], pixel[2]);
+        *pixel = [y, u, v];
+    });
+}
+
+// Unpacks packed YUYV 4:2:2 rows in parallel, sharing each chroma pair across two pixels
+fn decode_yuyv(
+    data: &[u8],
+    frame: &mut Array2<[u8; 3]>,
+    convert: fn(u8, u8, u8) -> [u8; 3],
+) -> anyhow::Result<()> {
+    let (height, width) = frame.dim();
+    anyhow::ensure!(
+        width % 2 == 0,
+        "YUYV frames need an even width, got {width}"
+    );
+    let src = ArrayView2::from_shape((height, width * 2), data)?;
+
+    Zip::from(frame.rows_mut())
+        .and(src.rows())
+        .par_for_each(|mut dst, src| {
+            for x in (0..width).step_by(2) {
+                let i = x * 2;
+                let (u, v) = (src[i + 1], src[i + 3]);
+                dst[x] = convert(src[i], u, v);
+                dst[x + 1] = convert(src[i + 2], u, v);
+            }
+        });
+
+    Ok(())
 }
@@ -1,50 +1,585 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use vision_detection::ball::HoughVoting;
+use vision_detection::color::{ColorRange, ColorRanges};
+use vision_detection::filter::BlurOp;
+use vision_detection::morphology::{Kernel, KernelShape, MorphOp};
+use vision_detection::pipeline::PipelineError;
+use vision_detection::resize::ResizeMode;
+use vision_detection::synthetic::{Occluder, Scene, SyntheticBall};
+use vision_detection::undistort::CameraIntrinsics;
+
+use crate::pipeline::check_stages;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct Config {
     pub system: SystemConfig,
     pub networktables: NetworkTablesConfig,
     pub camera: CameraConfig,
-    pub detection: DetectionConfig,
+    pub synthetic: SyntheticConfig,
+    pub calibration: CalibrationConfig,
+    pub processing: ProcessingConfig,
+    // The first pipeline is the main one; the others share its frames without adding to
+    // its latency
+    pub pipelines: Vec<PipelineConfig>,
     pub web: WebConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct SystemConfig {
     pub log_level: String,
     pub telemetry_enabled: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct NetworkTablesConfig {
+    // NetworkTables 4 server, as host or host:port
     pub server: String,
+    // Client name, also the table every pipeline publishes under
     pub identity: String,
     pub publish_rate_hz: u32,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct CameraConfig {
+    pub source: FrameSourceKind,
     pub device_id: u32,
     pub width: u32,
     pub height: u32,
     pub fps: u32,
+    pub pixel_format: PixelFormat,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameSourceKind {
+    // Physical camera at `device_id`
+    Device,
+    // Rendered scene from the `[synthetic]` section
+    Synthetic,
+}
+
+// Pixel format requested from the camera driver
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    // Whatever format gives the highest frame rate
+    Any,
+    Yuyv,
+    Mjpeg,
+}
+
+// Scene rendered when `camera.source = "synthetic"`, at the camera resolution and fps
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SyntheticConfig {
+    pub seed: u64,
+    pub background: [u8; 3],
+    pub noise: u8,
+    pub gradient: f32,
+    pub balls: Vec<SyntheticBallConfig>,
+    pub occluders: Vec<OccluderConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SyntheticBallConfig {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub color: [u8; 3],
+    // Pixels per frame
+    #[serde(default)]
+    pub velocity: [f32; 2],
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OccluderConfig {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: [u8; 3],
+}
+
+impl SyntheticConfig {
+    pub fn scene(&self, width: usize, height: usize) -> Scene {
+        let mut scene = Scene::new(width, height);
+        scene.seed = self.seed;
+        scene.background = self.background;
+        scene.noise = self.noise;
+        scene.gradient = self.gradient;
+        scene.balls = self
+            .balls
+            .iter()
+            .map(|ball| SyntheticBall {
+                x: ball.x,
+                y: ball.y,
+                radius: ball.radius,
+                color: ball.color,
+                velocity: (ball.velocity[0], ball.velocity[1]),
+            })
+            .collect();
+        scene.occluders = self
+            .occluders
+            .iter()
+            .map(|occluder| Occluder {
+                x: occluder.x,
+                y: occluder.y,
+                width: occluder.width,
+                height: occluder.height,
+                color: occluder.color,
+            })
+            .collect();
+        scene
+    }
+}
+
+// Lens intrinsics measured at `width` x `height`. Only read at startup.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    // k1, k2, p1, p2, k3
+    pub dist: [f32; 5],
+    pub undistort: UndistortMode,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UndistortMode {
+    Off,
+    // Remap the whole processing frame before masking
+    Image,
+    // Only correct the centers of detected circles
+    Points,
+}
+
+impl CalibrationConfig {
+    pub fn intrinsics(&self) -> CameraIntrinsics {
+        CameraIntrinsics {
+            fx: self.fx,
+            fy: self.fy,
+            cx: self.cx,
+            cy: self.cy,
+            dist: self.dist,
+        }
+    }
+}
+
+// Resolution the pipeline runs at, as a fraction of the camera resolution
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ProcessingConfig {
+    pub scale: f32,
+    pub resize: ResizeFilter,
+    // Adjust `scale` between `min_scale` and `max_scale` to hold `target_fps`, counting
+    // processing time only, not capture
+    pub adaptive: bool,
+    pub target_fps: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Worker threads shared by the secondary pipelines. They compete with the main
+    // pipeline's threads for cores, so leave it spare ones.
+    pub secondary_threads: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    Area,
+}
+
+impl ResizeFilter {
+    pub fn mode(self) -> ResizeMode {
+        match self {
+            ResizeFilter::Nearest => ResizeMode::Nearest,
+            ResizeFilter::Bilinear => ResizeMode::Bilinear,
+            ResizeFilter::Area => ResizeMode::Area,
+        }
+    }
+}
+
+// Detection thresholds are expressed in full camera resolution units
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct DetectionConfig {
-    pub color_lower: [u8; 3],
-    pub color_upper: [u8; 3],
+    pub color_space: ColorSpace,
+    // Pixels in any include range and no exclude range are kept
+    pub include: Vec<ColorRangeConfig>,
+    pub exclude: Vec<ColorRangeConfig>,
+    pub min_contour_length: u32,
+    pub min_area: f32,
+    // Contour shape filters, 0 to disable: 4πA/P² and area over convex hull area
+    pub min_circularity: f32,
+    pub min_solidity: f32,
+    // Douglas–Peucker tolerance for published contour outlines, 0 to disable
+    pub polygon_epsilon: f32,
+    pub min_radius: u32,
+    pub max_radius: u32,
+    pub radius_step: u32,
+    // Votes a circle needs, as a fraction of what a whole ball of its radius collects
+    pub min_vote_fraction: f32,
+    pub voting: VotingMode,
+    // Half-width in degrees of the arc each edge pixel votes on in gradient voting
+    pub gradient_spread: f32,
+    // Edge pixels within this distance of a detected circle are used to refit it with
+    // subpixel precision in the refine stage
+    pub refine_band: f32,
+    // Candidate circles tried per fit, and how far contour points may lie from a circle
+    // to support it, for RANSAC detection
+    pub ransac_iterations: u32,
+    pub ransac_band: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VotingMode {
+    // Vote on the full circle around every edge pixel
+    Full,
+    // Vote only along the mask gradient at every edge pixel; much cheaper on noisy masks
+    Gradient,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KernelShapeConfig {
+    Rect,
+    Cross,
+    Ellipse,
+}
+
+// Bounds in the detection color space. The first channel (hue) wraps when lower > upper.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct ColorRangeConfig {
+    pub lower: [u8; 3],
+    pub upper: [u8; 3],
+}
+
+// Color space the include/exclude ranges are expressed in
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    Hsv,
+    // Camera-native BT.601 YUV, thresholded without converting to RGB
+    Yuv,
+    // Full-range BT.601 YCbCr computed from RGB
+    YCbCr,
+    // CIELab in OpenCV's 8-bit scaling
+    Lab,
+    // Normalized (r, g) chromaticity plus intensity, robust to lighting changes
+    Chromaticity,
+}
+
+// Detection thresholds converted to processing resolution units
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScaledThresholds {
     pub min_contour_length: u32,
     pub min_area: f32,
+    pub polygon_epsilon: f32,
     pub min_radius: u32,
     pub max_radius: u32,
     pub radius_step: u32,
-    pub vote_thresh: u32,
+    pub refine_band: f32,
+    pub ransac_band: f32,
+}
+
+impl DetectionConfig {
+    pub fn color_ranges(&self) -> ColorRanges {
+        let to_range = |range: &ColorRangeConfig| ColorRange {
+            lower: range.lower,
+            upper: range.upper,
+        };
+        ColorRanges {
+            include: self.include.iter().map(to_range).collect(),
+            exclude: self.exclude.iter().map(to_range).collect(),
+        }
+    }
+
+    pub fn voting(&self) -> HoughVoting {
+        match self.voting {
+            VotingMode::Full => HoughVoting::Full,
+            VotingMode::Gradient => HoughVoting::Gradient {
+                spread: self.gradient_spread.to_radians(),
+            },
+        }
+    }
+
+    // The single place full-resolution thresholds are converted for a processing scale
+    pub fn scaled(&self, scale: f32) -> ScaledThresholds {
+        let length = |v: u32| (v as f32 * scale).round() as u32;
+        ScaledThresholds {
+            min_contour_length: length(self.min_contour_length),
+            min_area: self.min_area * scale * scale,
+            polygon_epsilon: self.polygon_epsilon * scale,
+            min_radius: length(self.min_radius),
+            max_radius: length(self.max_radius),
+            radius_step: length(self.radius_step).max(1),
+            refine_band: self.refine_band * scale,
+            ransac_band: self.ransac_band * scale,
+        }
+    }
+}
+
+// A named stage chain run on every frame. Stage settings that are tuned live from the
+// dashboard live in `detection`; `stage` only describes the structure.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PipelineConfig {
+    // Unique; keys the pipeline's results and dashboard routes
+    pub name: String,
+    #[serde(default)]
+    pub detection: DetectionConfig,
+    #[serde(default = "default_stages")]
+    pub stage: Vec<StageConfig>,
+}
+
+impl PipelineConfig {
+    // The label `class` is reported with by this pipeline's yolo stages
+    pub fn label(&self, class: u32) -> Option<&str> {
+        self.stage.iter().find_map(|stage| match stage {
+            StageConfig::Yolo(yolo) => yolo
+                .classes
+                .iter()
+                .find(|c| c.id == class)
+                .map(|c| c.label.as_str()),
+            _ => None,
+        })
+    }
+}
+
+fn default_stages() -> Vec<StageConfig> {
+    vec![
+        StageConfig::ColorMask,
+        StageConfig::Contours,
+        StageConfig::Hough,
+        StageConfig::Refine,
+    ]
+}
+
+// One pipeline stage. `sigma` and `size` are in full camera resolution pixels; kernel
+// sizes are diameters.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StageConfig {
+    // Smoothing of the frame
+    Gaussian { sigma: f32 },
+    BoxBlur { size: u32 },
+    Median { size: u32 },
+    // Thresholds the frame with the `[detection]` color ranges
+    ColorMask,
+    // Mask cleanup
+    Erode { shape: KernelShapeConfig, size: u32 },
+    Dilate { shape: KernelShapeConfig, size: u32 },
+    MorphOpen { shape: KernelShapeConfig, size: u32 },
+    MorphClose { shape: KernelShapeConfig, size: u32 },
+    FillHoles,
+    // Edge map from the mask. All filter blobs by `min_area`; `min_contour_length` and
+    // the shape filters only apply to traced contours.
+    Contours,
+    // Boundary pixels of connected components, without tracing
+    Components,
+    // Component boundaries with touching balls split apart by a distance-transform
+    // watershed; also estimates how many balls each pile holds
+    Watershed,
+    // Hough transform over the whole edge map, see `voting`
+    Hough,
+    // RANSAC circle fits on each edge contour; ignores background edges and handles
+    // partial arcs of occluded balls
+    Ransac,
+    // Subpixel refit of the detected circles, see `refine_band`
+    Refine,
+    // YOLO model run on the frame in place of the mask and edge stages. Needs the `yolo`
+    // build feature, and RGB frames, so not the yuv color space.
+    Yolo(YoloStageConfig),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct YoloStageConfig {
+    // ONNX model file
+    pub model: String,
+    // Side of the square input the model was exported for
+    #[serde(default = "default_input_size")]
+    pub input_size: usize,
+    #[serde(default)]
+    pub layout: YoloLayoutConfig,
+    // Model classes reported as balls; the others are ignored
+    pub classes: Vec<YoloClassConfig>,
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+    // Boxes overlapping a stronger one by more than this intersection over union are dropped
+    #[serde(default = "default_max_iou")]
+    pub max_iou: f32,
+}
+
+fn default_input_size() -> usize {
+    640
+}
+
+fn default_min_score() -> f32 {
+    0.5
+}
+
+fn default_max_iou() -> f32 {
+    0.45
+}
+
+// How the model lays out its detection output
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum YoloLayoutConfig {
+    // One column per box, without objectness (YOLOv8 and later)
+    #[default]
+    Transposed,
+    // One row per box, with objectness (YOLOv5, YOLOv7)
+    Objectness,
+}
+
+// A model class id and the label its detections are reported with
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct YoloClassConfig {
+    pub id: u32,
+    pub label: String,
+}
+
+impl StageConfig {
+    // The `type` the stage is configured with, also its stream and latency label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StageConfig::Gaussian { .. } => "gaussian",
+            StageConfig::BoxBlur { .. } => "box_blur",
+            StageConfig::Median { .. } => "median",
+            StageConfig::ColorMask => "color_mask",
+            StageConfig::Erode { .. } => "erode",
+            StageConfig::Dilate { .. } => "dilate",
+            StageConfig::MorphOpen { .. } => "morph_open",
+            StageConfig::MorphClose { .. } => "morph_close",
+            StageConfig::FillHoles => "fill_holes",
+            StageConfig::Contours => "contours",
+            StageConfig::Components => "components",
+            StageConfig::Watershed => "watershed",
+            StageConfig::Hough => "hough",
+            StageConfig::Ransac => "ransac",
+            StageConfig::Refine => "refine",
+            StageConfig::Yolo(_) => "yolo",
+        }
+    }
+
+    // Smoothing at processing resolution, for blur stages
+    pub fn blur_op(&self, scale: f32) -> Option<BlurOp> {
+        match self {
+            StageConfig::Gaussian { sigma } => Some(BlurOp::Gaussian {
+                sigma: sigma * scale,
+            }),
+            StageConfig::BoxBlur { size } => Some(BlurOp::Box {
+                radius: kernel_radius(*size, scale),
+            }),
+            StageConfig::Median { size } => Some(BlurOp::Median {
+                radius: kernel_radius(*size, scale),
+            }),
+            _ => None,
+        }
+    }
+
+    // Mask operation at processing resolution, for morphology stages
+    pub fn morph_op(&self, scale: f32) -> Option<MorphOp> {
+        let kernel = |shape: KernelShapeConfig, size: u32| Kernel {
+            shape: match shape {
+                KernelShapeConfig::Rect => KernelShape::Rect,
+                KernelShapeConfig::Cross => KernelShape::Cross,
+                KernelShapeConfig::Ellipse => KernelShape::Ellipse,
+            },
+            radius: kernel_radius(size, scale),
+        };
+        match self {
+            StageConfig::Erode { shape, size } => Some(MorphOp::Erode(kernel(*shape, *size))),
+            StageConfig::Dilate { shape, size } => Some(MorphOp::Dilate(kernel(*shape, *size))),
+            StageConfig::MorphOpen { shape, size } => Some(MorphOp::Open(kernel(*shape, *size))),
+            StageConfig::MorphClose { shape, size } => Some(MorphOp::Close(kernel(*shape, *size))),
+            StageConfig::FillHoles => Some(MorphOp::FillHoles),
+            _ => None,
+        }
+    }
+}
+
+// Kernel diameter in full-resolution pixels to radius at processing resolution
+fn kernel_radius(size: u32, scale: f32) -> usize {
+    ((size as f32 * scale - 1.0) / 2.0).round().max(0.0) as usize
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // Processing scales must be positive, with `min_scale` <= `max_scale`
+    InvalidScale {
+        scale: f32,
+        min_scale: f32,
+        max_scale: f32,
+    },
+    // The frame budget is 1000 / target_fps ms, so it must be positive
+    InvalidTargetFps(f32),
+    NoPipelines,
+    // Names end up in URLs, so they are limited to letters, digits, '-' and '_'
+    InvalidName(String),
+    DuplicateName(String),
+    // All pipelines see the same captured frame, which is YUV when the main one uses it
+    MixedYuv,
+    // Models are trained on RGB, which yuv pipelines do not capture
+    YoloOnYuv(String),
+    Stages {
+        pipeline: String,
+        error: PipelineError,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::InvalidScale {
+                scale,
+                min_scale,
+                max_scale,
+            } => write!(
+                f,
+                "processing scales must be positive with min_scale <= max_scale, got \
+                 scale {scale}, min_scale {min_scale}, max_scale {max_scale}"
+            ),
+            ConfigError::InvalidTargetFps(fps) => {
+                write!(f, "target_fps must be positive, got {fps}")
+            }
+            ConfigError::NoPipelines => f.write_str("at least one pipeline is required"),
+            ConfigError::InvalidName(name) => write!(
+                f,
+                "pipeline name {name:?} may only use letters, digits, '-' and '_'"
+            ),
+            ConfigError::DuplicateName(name) => write!(f, "pipeline {name} is defined twice"),
+            ConfigError::MixedYuv => {
+                f.write_str("either all pipelines or none may use the yuv color space")
+            }
+            ConfigError::YoloOnYuv(name) => write!(
+                f,
+                "pipeline {name}: yolo stages need RGB frames, which the yuv color space does \
+                 not capture"
+            ),
+            ConfigError::Stages { pipeline, error } => write!(f, "pipeline {pipeline}: {error}"),
+        }
+    }
 }
 
+impl std::error::Error for ConfigError {}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct WebConfig {
     pub port: u32,
 }
@@ -53,44 +588,428 @@ impl Config {
     // Load config from file
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
         let contents = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&contents)?;
+        let config = Self::parse(&contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Parses a config file. Files from before pipelines were named, without
+    // `[[pipelines]]`, load their single pipeline as "main".
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        let legacy = if table.contains_key("pipelines") {
+            None
+        } else {
+            legacy_pipeline(&mut table)?
+        };
+        let mut config: Config = toml::Value::Table(table).try_into()?;
+        if let Some(main) = legacy {
+            config.pipelines = vec![main];
+        }
         Ok(config)
     }
 
+    // Checks the processing scales and frame rate are usable and the pipelines can be built and share a
+    // captured frame. Stage inputs are checked by building each pipeline's stages.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let ProcessingConfig {
+            scale,
+            min_scale,
+            max_scale,
+            target_fps,
+            ..
+        } = self.processing;
+        let positive = |v: f32| v > 0.0 && v.is_finite();
+        if !(positive(scale) && positive(min_scale) && positive(max_scale)) || min_scale > max_scale
+        {
+            return Err(ConfigError::InvalidScale {
+                scale,
+                min_scale,
+                max_scale,
+            });
+        }
+        if !positive(target_fps) {
+            return Err(ConfigError::InvalidTargetFps(target_fps));
+        }
+        let Some(main) = self.pipelines.first() else {
+            return Err(ConfigError::NoPipelines);
+        };
+        for (i, pipeline) in self.pipelines.iter().enumerate() {
+            let name = &pipeline.name;
+            let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if name.is_empty() || !name.chars().all(valid_name) {
+                return Err(ConfigError::InvalidName(name.clone()));
+            }
+            if self.pipelines[..i].iter().any(|other| &other.name == name) {
+                return Err(ConfigError::DuplicateName(name.clone()));
+            }
+            if (pipeline.detection.color_space == ColorSpace::Yuv)
+                != (main.detection.color_space == ColorSpace::Yuv)
+            {
+                return Err(ConfigError::MixedYuv);
+            }
+            if pipeline.detection.color_space == ColorSpace::Yuv
+                && pipeline
+                    .stage
+                    .iter()
+                    .any(|stage| matches!(stage, StageConfig::Yolo(_)))
+            {
+                return Err(ConfigError::YoloOnYuv(name.clone()));
+            }
+            check_stages(pipeline).map_err(|error| ConfigError::Stages {
+                pipeline: name.clone(),
+                error,
+            })?;
+        }
+        Ok(())
+    }
+
     // Load default config
     pub fn load_default() -> Result<Self, Box<dyn std::error::Error>> {
         Self::from_file("config/default.toml")
     }
+}
+
+// `[pipeline]` from the layout with one unnamed pipeline
+#[derive(Deserialize)]
+struct LegacyPipeline {
+    stage: Vec<StageConfig>,
+}
+
+// `[detection]` from the layouts with one unnamed pipeline. Before `[[pipeline.stage]]`
+// existed, the stages were picked by the last four keys.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct LegacyDetection {
+    #[serde(flatten)]
+    detection: DetectionConfig,
+    // Blur and morphology steps, tagged by `op` instead of `type`
+    denoise: Vec<toml::Table>,
+    morphology: Vec<toml::Table>,
+    edges: Option<String>,
+    detector: Option<String>,
+    // The single HSV range from before `include` and `exclude`
+    color_lower: Option<[u8; 3]>,
+    color_upper: Option<[u8; 3]>,
+    // Absolute Hough vote count, replaced by `min_vote_fraction`
+    vote_thresh: Option<u32>,
+}
+
+impl LegacyDetection {
+    // The stage chain the pre-stage keys described
+    fn stages(&self) -> Result<Vec<StageConfig>, toml::de::Error> {
+        let stage = |kind: &str, mut table: toml::Table| -> Result<StageConfig, toml::de::Error> {
+            table.insert("type".to_string(), kind.into());
+            toml::Value::Table(table).try_into()
+        };
+        let step = |table: &toml::Table| {
+            let mut table = table.clone();
+            let op = table.remove("op");
+            let kind = match op
+                .as_ref()
+                .and_then(toml::Value::as_str)
+                .unwrap_or_default()
+            {
+                "box" => "box_blur",
+                "open" => "morph_open",
+                "close" => "morph_close",
+                op => op,
+            };
+            stage(kind, table)
+        };
+        let edges = self.edges.as_deref().unwrap_or("contours");
+        let detector = self.detector.as_deref().unwrap_or("hough");
+
+        self.denoise
+            .iter()
+            .map(step)
+            .chain([stage("color_mask", toml::Table::new())])
+            .chain(self.morphology.iter().map(step))
+            .chain([edges, detector, "refine"].map(|kind| stage(kind, toml::Table::new())))
+            .collect()
+    }
 
+    // Detection settings with the single color range moved into `include`
+    fn detection(&self) -> DetectionConfig {
+        let mut detection = self.detection.clone();
+        if self.color_lower.is_some() || self.color_upper.is_some() {
+            // Either bound alone kept the other at its old default
+            detection.color_space = ColorSpace::Hsv;
+            detection.include = vec![ColorRangeConfig {
+                lower: self.color_lower.unwrap_or([20, 100, 100]),
+                upper: self.color_upper.unwrap_or([30, 255, 255]),
+            }];
+            detection.exclude.clear();
+        }
+        if let Some(votes) = self.vote_thresh {
+            // A vote count has no fixed fraction: a whole ball's votes grow with its radius
+            tracing::warn!(
+                vote_thresh = votes,
+                min_vote_fraction = detection.min_vote_fraction,
+                "vote_thresh is no longer used, set min_vote_fraction instead"
+            );
+        }
+        detection
+    }
+}
+
+// Takes the single pipeline out of a config file from before pipelines were named:
+// `[detection]` with `[[pipeline.stage]]`, or `[detection]` alone, possibly with the
+// older stage keys. None if the file has neither table.
+fn legacy_pipeline(table: &mut toml::Table) -> Result<Option<PipelineConfig>, toml::de::Error> {
+    let detection: Option<LegacyDetection> = table
+        .remove("detection")
+        .map(toml::Value::try_into)
+        .transpose()?;
+    let pipeline: Option<LegacyPipeline> = table
+        .remove("pipeline")
+        .map(toml::Value::try_into)
+        .transpose()?;
+    if detection.is_none() && pipeline.is_none() {
+        return Ok(None);
+    }
+
+    let legacy = detection.unwrap_or_default();
+    let stage = match pipeline {
+        Some(pipeline) => pipeline.stage,
+        None => legacy.stages()?,
+    };
+    Ok(Some(PipelineConfig {
+        name: "main".to_string(),
+        detection: legacy.detection(),
+        stage,
+    }))
+}
+
+// Defaults fill in any section or field missing from the config file, so configs written
+// before a setting existed keep loading
+
+impl Default for Config {
     // Default config in memory if file doesn't exist
-    pub fn default() -> Self {
+    fn default() -> Self {
         Config {
-            system: SystemConfig {
-                log_level: "info".to_string(),
-                telemetry_enabled: true,
-            },
-            networktables: NetworkTablesConfig {
-                server: "10.0.0.2".to_string(),
-                identity: "vision-coprocessor".to_string(),
-                publish_rate_hz: 50,
-            },
-            camera: CameraConfig {
-                device_id: 0,
-                width: 1280,
-                height: 720,
-                fps: 30,
-            },
-            detection: DetectionConfig {
-                color_lower: [20, 100, 100],
-                color_upper: [30, 255, 255],
-                min_contour_length: 100,
-                min_area: 100.0,
-                radius_step: 4,
-                min_radius: 100,
-                max_radius: 300,
-                vote_thresh: 20,
-            },
-            web: WebConfig { port: 5800 },
+            system: SystemConfig::default(),
+            networktables: NetworkTablesConfig::default(),
+            camera: CameraConfig::default(),
+            synthetic: SyntheticConfig::default(),
+            calibration: CalibrationConfig::default(),
+            processing: ProcessingConfig::default(),
+            pipelines: vec![PipelineConfig {
+                name: "main".to_string(),
+                detection: DetectionConfig::default(),
+                stage: default_stages(),
+            }],
+            web: WebConfig::default(),
         }
     }
 }
+
+impl Default for SystemConfig {
+    fn default() -> Self {
+        SystemConfig {
+            log_level: "info".to_string(),
+            telemetry_enabled: true,
+        }
+    }
+}
+
+impl Default for NetworkTablesConfig {
+    fn default() -> Self {
+        NetworkTablesConfig {
+            server: "10.0.0.2".to_string(),
+            identity: "vision-coprocessor".to_string(),
+            publish_rate_hz: 50,
+        }
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            source: FrameSourceKind::Device,
+            device_id: 0,
+            width: 1280,
+            height: 720,
+            fps: 30,
+            pixel_format: PixelFormat::Any,
+        }
+    }
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        SyntheticConfig {
+            seed: 0,
+            background: [40, 40, 40],
+            noise: 0,
+            gradient: 0.0,
+            balls: Vec::new(),
+            occluders: Vec::new(),
+        }
+    }
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            width: 1280,
+            height: 720,
+            fx: 1000.0,
+            fy: 1000.0,
+            cx: 640.0,
+            cy: 360.0,
+            dist: [0.0; 5],
+            undistort: UndistortMode::Off,
+        }
+    }
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        ProcessingConfig {
+            scale: 0.5,
+            resize: ResizeFilter::Area,
+            adaptive: false,
+            target_fps: 30.0,
+            min_scale: 0.25,
+            max_scale: 1.0,
+            secondary_threads: 2,
+        }
+    }
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            color_space: ColorSpace::Hsv,
+            include: vec![ColorRangeConfig {
+                lower: [20, 100, 100],
+                upper: [30, 255, 255],
+            }],
+            exclude: Vec::new(),
+            min_contour_length: 100,
+            min_area: 100.0,
+            min_circularity: 0.0,
+            min_solidity: 0.0,
+            polygon_epsilon: 0.0,
+            radius_step: 8,
+            min_radius: 100,
+            max_radius: 300,
+            min_vote_fraction: 0.4,
+            voting: VotingMode::Full,
+            gradient_spread: 4.0,
+            refine_band: 8.0,
+            ransac_iterations: 200,
+            ransac_band: 2.0,
+        }
+    }
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig { port: 5800 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // config/default.toml as first released, with one unnamed pipeline and its stages
+    // picked by the old defaults
+    const BASELINE: &str = r#"
+[system]
+log_level = "info"
+telemetry_enabled = true
+
+[networktables]
+server = "10.TE.AM.2"  # Robot IP
+identity = "rusty-vision"
+publish_rate_hz = 50
+
+[camera]
+device_id = 1
+width = 1280
+height = 800
+fps = 100
+
+[detection]
+color_lower = [10, 150, 115]  # HSV
+color_upper = [130, 255, 255]
+min_contour_length = 100
+min_area = 100.0
+min_radius = 40
+max_radius = 200
+radius_step = 4
+vote_thresh = 15
+
+[web]
+port = 5800
+"#;
+
+    fn only_pipeline(config: &Config) -> &PipelineConfig {
+        assert_eq!(config.pipelines.len(), 1);
+        &config.pipelines[0]
+    }
+
+    #[test]
+    fn loads_the_baseline_config() {
+        let config = Config::parse(BASELINE).unwrap();
+        config.validate().unwrap();
+
+        let pipeline = only_pipeline(&config);
+        assert_eq!(pipeline.name, "main");
+        let detection = &pipeline.detection;
+        assert_eq!(detection.color_space, ColorSpace::Hsv);
+        assert_eq!(
+            detection.include,
+            [ColorRangeConfig {
+                lower: [10, 150, 115],
+                upper: [130, 255, 255],
+            }]
+        );
+        assert!(detection.exclude.is_empty());
+        assert_eq!(detection.min_contour_length, 100);
+        assert_eq!(detection.min_area, 100.0);
+        assert_eq!(
+            (
+                detection.min_radius,
+                detection.max_radius,
+                detection.radius_step
+            ),
+            (40, 200, 4)
+        );
+        let kinds: Vec<_> = pipeline.stage.iter().map(StageConfig::kind).collect();
+        assert_eq!(kinds, ["color_mask", "contours", "hough", "refine"]);
+    }
+
+    #[test]
+    fn keeps_the_old_default_for_a_missing_color_bound() {
+        let config = Config::parse("[detection]\ncolor_upper = [40, 255, 255]\n").unwrap();
+        assert_eq!(
+            only_pipeline(&config).detection.include,
+            [ColorRangeConfig {
+                lower: [20, 100, 100],
+                upper: [40, 255, 255],
+            }]
+        );
+    }
+
+    #[test]
+    fn keeps_include_ranges_without_old_color_keys() {
+        let config = Config::parse(
+            "[detection]\ncolor_space = \"ycbcr\"\n\n\
+             [[detection.include]]\nlower = [0, 0, 150]\nupper = [255, 120, 255]\n",
+        )
+        .unwrap();
+        let detection = &only_pipeline(&config).detection;
+        assert_eq!(detection.color_space, ColorSpace::YCbCr);
+        assert_eq!(
+            detection.include,
+            [ColorRangeConfig {
+                lower: [0, 0, 150],
+                upper: [255, 120, 255],
+            }]
+        );
+    }
+}
@@ -1,35 +1,107 @@
-use std::collections::HashMap;
-
 use ndarray::{Array2, ArrayView2, Zip};
-use vision_detection::ball::hough_transform;
-use vision_detection::color::{rgb_to_hsv, ColorRange};
-use vision_detection::contour::find_contours;
-
-pub fn run_color_mask(
-    rgb_frame: ArrayView2<[u8; 3]>,
-    mask: &mut Array2<u8>,
-    color_lower: [u8; 3],
-    color_upper: [u8; 3],
-) {
-    let range = ColorRange {
-        lower: color_lower,
-        upper: color_upper,
-    };
-    Zip::from(mask).and(rgb_frame).for_each(|m, &[r, g, b]| {
-        let (h, s, v) = rgb_to_hsv(r, g, b);
-        *m = if range.in_range(h, s, v) { 255 } else { 0 };
-    })
+use serde::Serialize;
+use vision_detection::cluster::{
+    distance_transform, find_markers, find_piles, watershed, Marker, Pile,
+};
+use vision_detection::color::{
+    rgb_to_chromaticity, rgb_to_hsv, rgb_to_lab, rgb_to_ycbcr, rgb_to_yuv, ColorConversion,
+    ColorLut, ColorRanges,
+};
+use vision_detection::components::{label_components, Components, Connectivity};
+use vision_detection::contour::{
+    approx_polygon, circularity, find_contours, solidity, Contour, ContourKind, Point,
+};
+use vision_detection::draw::{draw_circle, draw_polygon};
+use vision_detection::pipeline::{Slot, StageData};
+use vision_detection::refine::RefinedCircle;
+use vision_detection::undistort::CameraIntrinsics;
+
+use crate::config::{ColorSpace, DetectionConfig, PipelineConfig};
+
+// Color thresholding state, rebuilt only when the color settings change
+pub struct ColorMask {
+    color_space: ColorSpace,
+    ranges: ColorRanges,
+    // RGB lookup table for color spaces that need a per-pixel conversion
+    lut: Option<ColorLut>,
+}
+
+impl ColorMask {
+    pub fn new(detection: &DetectionConfig) -> Self {
+        let ranges = detection.color_ranges();
+        let convert: Option<ColorConversion> = match detection.color_space {
+            ColorSpace::Hsv => Some(rgb_to_hsv),
+            ColorSpace::Yuv => None,
+            ColorSpace::YCbCr => Some(rgb_to_ycbcr),
+            ColorSpace::Lab => Some(rgb_to_lab),
+            ColorSpace::Chromaticity => Some(rgb_to_chromaticity),
+        };
+        let lut = convert.map(|convert| {
+            ColorLut::new(|r, g, b| {
+                let (c0, c1, c2) = convert(r, g, b);
+                ranges.in_range(c0, c1, c2)
+            })
+        });
+
+        Self {
+            color_space: detection.color_space,
+            ranges,
+            lut,
+        }
+    }
+
+    // Mask that selects nothing, without building a lookup table
+    pub fn empty() -> Self {
+        Self {
+            color_space: ColorSpace::Yuv,
+            ranges: ColorRanges::default(),
+            lut: None,
+        }
+    }
+
+    // Whether this mask still reflects the color settings in `detection`
+    pub fn matches(&self, detection: &DetectionConfig) -> bool {
+        self.color_space == detection.color_space && self.ranges == detection.color_ranges()
+    }
 }
 
+pub fn run_color_mask(frame: ArrayView2<[u8; 3]>, mask: &mut Array2<u8>, color_mask: &ColorMask) {
+    match &color_mask.lut {
+        Some(lut) => Zip::from(mask).and(frame).par_for_each(|m, &[r, g, b]| {
+            *m = if lut.contains(r, g, b) { 255 } else { 0 };
+        }),
+        // Frame was captured in YUV, so threshold the pixels as they are
+        None => Zip::from(mask).and(frame).par_for_each(|m, &[y, u, v]| {
+            *m = if color_mask.ranges.in_range(y, u, v) {
+                255
+            } else {
+                0
+            };
+        }),
+    }
+}
+
+// Draws the kept contours and returns their outer outlines simplified with
+// `polygon_epsilon`, or nothing when it is 0
 pub fn detect_contours(
     mask: ArrayView2<u8>,
     contour_arr: &mut Array2<u8>,
     min_length: u32,
     min_area: f32,
-) {
+    min_circularity: f32,
+    min_solidity: f32,
+    polygon_epsilon: f32,
+) -> Vec<Vec<Point>> {
     contour_arr.fill(0);
     let (height, width) = mask.dim();
     let contours = find_contours(mask, min_length, min_area);
+    let contours: Vec<&Contour> = contours
+        .iter()
+        .filter(|contour| {
+            (min_circularity <= 0.0 || circularity(&contour.points) >= min_circularity)
+                && (min_solidity <= 0.0 || solidity(&contour.points) >= min_solidity)
+        })
+        .collect();
 
     for contour in &contours {
         for point in &contour.points {
@@ -38,27 +110,227 @@ pub fn detect_contours(
             }
         }
     }
+
+    if polygon_epsilon <= 0.0 {
+        return Vec::new();
+    }
+    contours
+        .iter()
+        .filter(|contour| contour.kind == ContourKind::Outer)
+        .map(|contour| approx_polygon(&contour.points, polygon_epsilon))
+        .collect()
+}
+
+// Marks the boundary pixels of connected components whose area exceeds `min_area`, as a
+// cheaper edge map than traced contours
+pub fn detect_component_edges(mask: ArrayView2<u8>, contour_arr: &mut Array2<u8>, min_area: f32) {
+    let Components { labels, components } = label_components(mask, Connectivity::Eight);
+    let keep: Vec<bool> = std::iter::once(false)
+        .chain(components.iter().map(|c| c.area > min_area))
+        .collect();
+    mark_boundaries(labels.view(), &keep, contour_arr);
+}
+
+// Splits blobs of touching balls into one region per ball with a watershed on the mask's
+// distance transform, and marks the region boundaries so each ball gets its own closed
+// edge. Blobs whose area exceeds `min_area` are kept, and balls need a point at least
+// `min_depth` from the background to be seeded. Returns the blobs holding several balls.
+pub fn detect_cluster_edges(
+    mask: ArrayView2<u8>,
+    contour_arr: &mut Array2<u8>,
+    min_area: f32,
+    min_depth: f32,
+) -> Vec<Pile> {
+    let components = label_components(mask, Connectivity::Eight);
+    let distance = distance_transform(mask);
+    let markers: Vec<Marker> = find_markers(distance.view(), min_depth)
+        .into_iter()
+        .filter(|marker| {
+            let label = components.labels[(marker.y, marker.x)];
+            components.components[label as usize - 1].area > min_area
+        })
+        .collect();
+    let regions = watershed(distance.view(), &markers);
+
+    let mut keep = vec![true; markers.len() + 1];
+    keep[0] = false;
+    mark_boundaries(regions.view(), &keep, contour_arr);
+    find_piles(&components, &markers)
+}
+
+// Marks the pixels of regions with `keep[label]` set that border another region or the
+// frame edge
+fn mark_boundaries(labels: ArrayView2<u32>, keep: &[bool], contour_arr: &mut Array2<u8>) {
+    let (height, width) = labels.dim();
+    Zip::indexed(contour_arr).par_for_each(|(y, x), out| {
+        let label = labels[(y, x)];
+        let boundary = keep[label as usize]
+            && (y == 0
+                || x == 0
+                || y + 1 == height
+                || x + 1 == width
+                || labels[(y - 1, x)] != label
+                || labels[(y + 1, x)] != label
+                || labels[(y, x - 1)] != label
+                || labels[(y, x + 1)] != label);
+        *out = if boundary { 255 } else { 0 };
+    });
 }
 
-pub fn detect_circles(
-    contour_arr: ArrayView2<u8>,
+// Renders circles and contour outlines into `circle_arr` for the dashboard
+pub fn draw_detections(
     circle_arr: &mut Array2<u8>,
-    circle_cache: &HashMap<u32, Vec<(i32, i32)>>,
-    vote_thresh: u32,
+    circles: &[RefinedCircle],
+    polygons: &[Vec<Point>],
 ) {
     circle_arr.fill(0);
-    let (height, width) = contour_arr.dim();
-    let circles = hough_transform(contour_arr.view(), circle_cache, vote_thresh);
-
-    for circle in &circles {
-        if let Some(circle_points) = circle_cache.get(&circle.radius) {
-            for &(c_x, c_y) in circle_points {
-                let x = circle.x as i32 + c_x;
-                let y = circle.y as i32 + c_y;
-                if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
-                    circle_arr[(y as usize, x as usize)] = 255;
-                }
-            }
-        }
+    for circle in circles {
+        let center = Point {
+            x: circle.x.round() as i32,
+            y: circle.y.round() as i32,
+        };
+        draw_circle(circle_arr, center, circle.radius.round() as i32, 255);
+    }
+    for polygon in polygons {
+        draw_polygon(circle_arr, polygon, 255);
+    }
+}
+
+// Grayscale view of one stage output for its dashboard stream. Frames show their luma,
+// circles are drawn with the contour outlines.
+pub fn render_output(slot: Slot, data: &StageData, color_space: ColorSpace, dst: &mut Array2<u8>) {
+    if dst.dim() != data.dim() {
+        *dst = Array2::zeros(data.dim());
     }
+    match slot {
+        Slot::Image => Zip::from(dst).and(&data.image).for_each(|out, &[r, g, b]| {
+            *out = match color_space {
+                ColorSpace::Yuv => r,
+                _ => rgb_to_yuv(r, g, b).0,
+            };
+        }),
+        Slot::Mask => dst.assign(&data.mask),
+        Slot::Edges => dst.assign(&data.edges),
+        Slot::Circles => draw_detections(dst, &data.circles, &data.polygons),
+    }
+}
+
+// A detected ball in full-resolution camera pixels, as reported to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct Detection {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub votes: u32,
+    // Votes relative to a complete circle of this radius, or the model's score for yolo
+    // stages, 0 to 1
+    pub confidence: f32,
+    // RMS edge distance from the refined circle in camera pixels; None if not refined
+    pub residual: Option<f32>,
+    // Model class id and the label configured for it, from yolo stages
+    pub class: Option<u32>,
+    pub label: Option<String>,
+}
+
+// Maps a processing-resolution position to camera pixels, treating coordinates as pixel
+// centers, and undistorts it when `intrinsics` is given
+fn to_camera(x: f32, y: f32, scale: f32, intrinsics: Option<&CameraIntrinsics>) -> (f32, f32) {
+    let x = (x + 0.5) * scale - 0.5;
+    let y = (y + 0.5) * scale - 0.5;
+    match intrinsics {
+        Some(intrinsics) => intrinsics.undistort_point(x, y),
+        None => (x, y),
+    }
+}
+
+// Scales processing-resolution circles back to camera pixels, undistorting their centers
+// when `intrinsics` is given, and labels classified ones with `pipeline`'s class labels
+pub fn to_detections(
+    circles: &[RefinedCircle],
+    scale: f32,
+    intrinsics: Option<&CameraIntrinsics>,
+    pipeline: &PipelineConfig,
+) -> Vec<Detection> {
+    circles
+        .iter()
+        .map(|circle| {
+            let (x, y) = to_camera(circle.x, circle.y, scale, intrinsics);
+            Detection {
+                x,
+                y,
+                radius: circle.radius * scale,
+                votes: circle.votes,
+                confidence: circle.confidence,
+                residual: circle.residual.map(|residual| residual * scale),
+                class: circle.class,
+                label: circle
+                    .class
+                    .and_then(|class| pipeline.label(class))
+                    .map(str::to_owned),
+            }
+        })
+        .collect()
+}
+
+// Simplified contour outline in full-resolution camera pixels, as reported to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct Outline {
+    pub points: Vec<[f32; 2]>,
+}
+
+// Scales processing-resolution polygons back to camera pixels, undistorting their
+// vertices when `intrinsics` is given
+pub fn to_outlines(
+    polygons: &[Vec<Point>],
+    scale: f32,
+    intrinsics: Option<&CameraIntrinsics>,
+) -> Vec<Outline> {
+    polygons
+        .iter()
+        .map(|polygon| Outline {
+            points: polygon
+                .iter()
+                .map(|p| {
+                    let (x, y) = to_camera(p.x as f32, p.y as f32, scale, intrinsics);
+                    [x, y]
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// Blob of several balls in full-resolution camera pixels, as reported to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct PileEstimate {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    // Balls separated and fitted individually
+    pub resolved: u32,
+    // Estimated number of balls in the blob, at least `resolved`
+    pub estimate: u32,
+}
+
+// Scales processing-resolution piles back to camera pixels, undistorting their centroids
+// when `intrinsics` is given
+pub fn to_pile_estimates(
+    piles: &[Pile],
+    scale: f32,
+    intrinsics: Option<&CameraIntrinsics>,
+) -> Vec<PileEstimate> {
+    piles
+        .iter()
+        .map(|pile| {
+            let (x, y) = to_camera(pile.centroid.0, pile.centroid.1, scale, intrinsics);
+            PileEstimate {
+                x,
+                y,
+                width: pile.bbox.width as f32 * scale,
+                height: pile.bbox.height as f32 * scale,
+                resolved: pile.resolved,
+                estimate: pile.estimate,
+            }
+        })
+        .collect()
 }
@@ -1,18 +1,29 @@
 mod camera;
 mod config;
 mod detection;
+mod pipeline;
+mod processing;
+mod runner;
 mod streaming;
+mod timing;
 
-use config::Config;
+use config::{Config, UndistortMode};
 use ndarray::Array2;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::sync_channel;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::Instant;
-use vision_detection::circle::precompute_circle_points;
+use vision_detection::resize::resize;
+use vision_detection::undistort::RemapTable;
+use vision_nt::client::NtClient;
 
 use crate::{
-    camera::{capture_frame, get_camera, resize_array},
-    detection::{detect_circles, detect_contours, run_color_mask},
-    streaming::{array_to_jpeg, run_dashboard_server, FrameHub},
+    camera::FrameSource,
+    processing::{processing_dims, AdaptiveScale, ProcessingBuffers},
+    runner::{run_secondary, PipelineRunner, Secondary, SharedFrame},
+    streaming::run_dashboard_server,
+    timing::FrameTimer,
 };
 
 #[tokio::main]
@@ -29,127 +40,201 @@ async fn main() -> anyhow::Result<()> {
         Config::default()
     });
 
-    // Create FrameHubs for streaming
-
-    let mask_hub = FrameHub::new();
-    let contour_hub = FrameHub::new();
-    let circle_hub = FrameHub::new();
-
-    let state = run_dashboard_server(
-        mask_hub.clone(),
-        contour_hub.clone(),
-        circle_hub.clone(),
-        config.clone(),
-    )
-    .await?;
+    let state = run_dashboard_server(config.clone()).await?;
+    let nt_config = config.networktables.clone();
+    let nt = NtClient::start(
+        &nt_config.server,
+        &nt_config.identity,
+        nt_config.publish_rate_hz,
+    );
     let vision_state = state.clone();
 
     // Read constants from config
-    let resize_factor: usize = 2;
     let width = config.camera.width as usize;
     let height = config.camera.height as usize;
-    let proc_width = width / resize_factor;
-    let proc_height = height / resize_factor;
 
     // Run vision processing in blocking task
     tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
-        let mut camera = get_camera(vision_state.config.blocking_read().camera.device_id)?;
-        camera.open_stream()?;
-
-        let mut current_detection = vision_state.config.blocking_read().detection.clone();
-
-        let mut circle_cache = precompute_circle_points(
-            current_detection.min_radius / resize_factor as u32,
-            current_detection.max_radius / resize_factor as u32,
-            current_detection.radius_step,
+        let mut source = FrameSource::open(&vision_state.config.blocking_read())?;
+
+        let processing = vision_state.config.blocking_read().processing.clone();
+        let mut adaptive_scale = AdaptiveScale::new(&processing);
+        let mut scale = adaptive_scale.scale();
+
+        let pipelines = vision_state.config.blocking_read().pipelines.clone();
+        let mut runners = Vec::with_capacity(pipelines.len());
+        for (pipeline, pipeline_state) in pipelines.into_iter().zip(vision_state.pipelines.iter()) {
+            runners.push(PipelineRunner::new(
+                pipeline,
+                scale,
+                pipeline_state.clone(),
+                nt.clone(),
+                &nt_config.identity,
+            )?);
+        }
+        let mut main_runner = runners.remove(0);
+
+        // Undistortion: full remap table at processing resolution, or per-point at camera resolution
+        let calibration = vision_state.config.blocking_read().calibration.clone();
+        let intrinsics = calibration.intrinsics();
+        let build_remap_table = |proc_height: usize, proc_width: usize| {
+            (calibration.undistort == UndistortMode::Image).then(|| {
+                let sx = proc_width as f32 / calibration.width as f32;
+                let sy = proc_height as f32 / calibration.height as f32;
+                RemapTable::new(&intrinsics.scaled(sx, sy), proc_height, proc_width)
+            })
+        };
+        let camera_intrinsics = intrinsics.scaled(
+            width as f32 / calibration.width as f32,
+            height as f32 / calibration.height as f32,
         );
+        let point_intrinsics =
+            (calibration.undistort == UndistortMode::Points).then_some(&camera_intrinsics);
+
+        // Secondary pipelines each get a thread, and share a small rayon pool so they
+        // cannot take the main pipeline's workers. Both pools still run on the same
+        // cores with no priority between them, so a busy secondary pipeline slows the
+        // main one unless `secondary_threads` leaves it spare cores. A frame is offered
+        // only when a pipeline is waiting for one, so a slow pipeline skips frames
+        // instead of falling behind.
+        let mut secondaries: Vec<Secondary> = Vec::new();
+        if !runners.is_empty() {
+            let pool = Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(processing.secondary_threads.max(1))
+                    .thread_name(|i| format!("secondary-{i}"))
+                    .build()?,
+            );
+            for runner in runners {
+                let (tx, rx) = sync_channel(0);
+                let idle = Arc::new(AtomicBool::new(false));
+                let secondary_idle = idle.clone();
+                let pool = pool.clone();
+                let config = vision_state.config.clone();
+                let point_intrinsics = point_intrinsics.cloned();
+                std::thread::Builder::new()
+                    .name(format!("pipeline-{}", runner.name()))
+                    .spawn(move || {
+                        run_secondary(runner, rx, secondary_idle, pool, config, point_intrinsics)
+                    })?;
+                secondaries.push(Secondary { frames: tx, idle });
+            }
+        }
 
         // Buffers
-        let mut rgb_frame: Array2<[u8; 3]> = Array2::from_elem((height, width), [0u8; 3]);
-        let mut rgb_resized: Array2<[u8; 3]> =
-            Array2::from_elem((proc_height, proc_width), [0u8; 3]);
-        let mut mask_arr: Array2<u8> = Array2::zeros((proc_height, proc_width));
-        let mut contour_arr: Array2<u8> = Array2::zeros((proc_height, proc_width));
-        let mut circle_arr: Array2<u8> = Array2::zeros((proc_height, proc_width));
+        let (proc_height, proc_width) = processing_dims(height, width, scale);
+        let mut frame: Array2<[u8; 3]> = Array2::from_elem((height, width), [0u8; 3]);
+        let mut buffers = ProcessingBuffers::new(proc_height, proc_width);
+        let mut remap_table = build_remap_table(proc_height, proc_width);
 
         let mut frame_counter = 0u32;
+        let mut frame_id = 0u64;
+        let mut latency_sum_ms = 0.0;
+        let mut processing_sum_ms = 0.0;
         let mut last_log = Instant::now();
 
         loop {
             // --- CONFIG UPDATE CHECK ---
-            {
-                let latest_config = vision_state.config.blocking_read();
-                let latest_det = &latest_config.detection;
-
-                if latest_det != &current_detection {
-                    tracing::info!("Config update detected, applying new settings...");
-
-                    // Radii change requires recomputing circles (expensive)
-                    if latest_det.min_radius != current_detection.min_radius
-                        || latest_det.max_radius != current_detection.max_radius
-                        || latest_det.radius_step != current_detection.radius_step
-                    {
-                        circle_cache = precompute_circle_points(
-                            latest_det.min_radius / resize_factor as u32,
-                            latest_det.max_radius / resize_factor as u32,
-                            latest_det.radius_step,
-                        );
-                    }
-
-                    current_detection = latest_det.clone();
-                }
-            }
+            main_runner.update(&vision_state.config.blocking_read());
 
             // --- VISION PIPELINE ---
-            // Camera capture into RGB buf
-            capture_frame(&mut camera, &mut rgb_frame)?;
-            resize_array(rgb_frame.view(), &mut rgb_resized, proc_height, proc_width);
-
-            run_color_mask(
-                rgb_resized.view(),
-                &mut mask_arr,
-                current_detection.color_lower,
-                current_detection.color_upper,
-            );
-
-            detect_contours(
-                mask_arr.view(),
-                &mut contour_arr,
-                current_detection.min_contour_length / resize_factor as u32,
-                current_detection.min_area / (resize_factor * resize_factor) as f32,
-            );
-
-            detect_circles(
-                contour_arr.view(),
-                &mut circle_arr,
-                &circle_cache,
-                current_detection.vote_thresh,
-            );
-
-            // --- PUBLISH TO DASHBOARD ---
-
-            if vision_state.mask_frames.has_subscribers() {
-                if let Some(jpeg) = array_to_jpeg(mask_arr.view()) {
-                    vision_state.mask_frames.publish(jpeg);
-                }
-            }
-            if vision_state.contour_frames.has_subscribers() {
-                if let Some(jpeg) = array_to_jpeg(contour_arr.view()) {
-                    vision_state.contour_frames.publish(jpeg);
+            // Camera capture into RGB/YUV buf
+            let captured_at = source.capture(&mut frame, main_runner.color_space())?;
+            let mut timer = FrameTimer::new(captured_at);
+            timer.mark("decode");
+
+            resize(frame.view(), &mut buffers.frame, processing.resize.mode());
+            timer.mark("resize");
+
+            if let Some(table) = &remap_table {
+                if table.apply(buffers.frame.view(), &mut buffers.frame_undistorted) {
+                    std::mem::swap(&mut buffers.frame, &mut buffers.frame_undistorted);
+                    timer.mark("undistort");
+                } else {
+                    // Only a missed rebuild gets here; this frame goes through distorted
+                    let (proc_height, proc_width) = buffers.dim();
+                    let (table_height, table_width) = table.dim();
+                    tracing::warn!(
+                        "Undistortion table is for {}x{}, not {}x{}; rebuilding it",
+                        table_width,
+                        table_height,
+                        proc_width,
+                        proc_height
+                    );
+                    remap_table = build_remap_table(proc_height, proc_width);
                 }
             }
-            if vision_state.circle_frames.has_subscribers() {
-                if let Some(jpeg) = array_to_jpeg(circle_arr.view()) {
-                    vision_state.circle_frames.publish(jpeg);
+
+            let to_camera = frame.ncols() as f32 / buffers.dim().1 as f32;
+            // The main pipeline overwrites the frame as it runs, so the others get a copy,
+            // made only when one of them is waiting to take it
+            let shared = secondaries.iter().any(Secondary::is_idle).then(|| {
+                let shared = Arc::new(SharedFrame {
+                    image: buffers.frame.clone(),
+                    captured_at,
+                    frame_id,
+                    scale,
+                    to_camera,
+                });
+                timer.mark("share");
+                shared
+            });
+
+            main_runner.swap_frame(&mut buffers.frame);
+            main_runner.run(&mut timer, to_camera, point_intrinsics);
+
+            // --- LATENCY REPORT ---
+            let (latency_ms, processing_ms) = main_runner.report(&timer, frame_id);
+            latency_sum_ms += latency_ms;
+            processing_sum_ms += processing_ms;
+            frame_id += 1;
+
+            // Secondary pipelines start once the main one is done with the frame
+            if let Some(shared) = shared {
+                for secondary in &secondaries {
+                    secondary.offer(&shared);
                 }
             }
 
+            // --- PUBLISH TO DASHBOARD ---
+            main_runner.publish_streams();
+
             // --- FPS Logging ---
             frame_counter += 1;
             if last_log.elapsed() >= Duration::from_secs(1) {
                 let fps = frame_counter as f64 / last_log.elapsed().as_secs_f64();
-                tracing::info!("Stream FPS: {:.1}", fps);
+                let avg_latency = latency_sum_ms / frame_counter as f64;
+                let avg_processing = processing_sum_ms / frame_counter as f64;
+                tracing::info!(
+                    "Stream FPS: {:.1}, latency: {:.1}ms, processing: {:.1}ms, scale: {:.2}",
+                    fps,
+                    avg_latency,
+                    avg_processing,
+                    scale
+                );
+
+                // --- ADAPTIVE SCALE ---
+                // Driven by processing time alone, so a slow camera does not shrink the scale
+                if processing.adaptive {
+                    if let Some(new_scale) = adaptive_scale.update(avg_processing) {
+                        scale = new_scale;
+                        let (proc_height, proc_width) = processing_dims(height, width, scale);
+                        tracing::info!(
+                            "Processing scale now {:.2} ({}x{})",
+                            scale,
+                            proc_width,
+                            proc_height
+                        );
+
+                        buffers = ProcessingBuffers::new(proc_height, proc_width);
+                        remap_table = build_remap_table(proc_height, proc_width);
+                        main_runner.set_scale(scale);
+                    }
+                }
+
                 frame_counter = 0;
+                latency_sum_ms = 0.0;
+                processing_sum_ms = 0.0;
                 last_log = Instant::now();
             }
         }
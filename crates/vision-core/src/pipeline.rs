@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+use vision_detection::pipeline::{
+    Blur, Detect, Hough, Morphology, Pipeline, PipelineError, Ransac, Refine, Slot, Stage,
+    StageData,
+};
+use vision_detection::ransac::RansacParams;
+#[cfg(feature = "yolo")]
+use vision_detection::yolo::{Yolo, YoloLayout, YoloModel, YoloParams};
+
+#[cfg(feature = "yolo")]
+use crate::config::YoloLayoutConfig;
+use crate::config::{PipelineConfig, ScaledThresholds, StageConfig, YoloStageConfig};
+use crate::detection::{
+    detect_cluster_edges, detect_component_edges, detect_contours, run_color_mask, ColorMask,
+};
+
+// Builds the stage chain described by `config` at `scale`. The color lookup table, circle
+// cache and yolo models are shared with the caller, which rebuilds or reloads them only
+// when their settings change.
+pub fn build_pipeline(
+    config: &PipelineConfig,
+    thresholds: &ScaledThresholds,
+    scale: f32,
+    color_mask: &Arc<ColorMask>,
+    circle_cache: &Arc<HashMap<u32, Vec<(i32, i32)>>>,
+    models: &Models,
+) -> Result<Pipeline, PipelineError> {
+    let detection = &config.detection;
+    let stages = config
+        .stage
+        .iter()
+        .enumerate()
+        .map(|(index, stage)| -> Result<Box<dyn Stage>, PipelineError> {
+            Ok(match stage {
+                StageConfig::Gaussian { .. }
+                | StageConfig::BoxBlur { .. }
+                | StageConfig::Median { .. } => {
+                    Box::new(Blur::new(stage.blur_op(scale).into_iter().collect()))
+                }
+                StageConfig::Erode { .. }
+                | StageConfig::Dilate { .. }
+                | StageConfig::MorphOpen { .. }
+                | StageConfig::MorphClose { .. }
+                | StageConfig::FillHoles => {
+                    Box::new(Morphology::new(stage.morph_op(scale).into_iter().collect()))
+                }
+                StageConfig::ColorMask => Box::new(ColorMaskStage(color_mask.clone())),
+                StageConfig::Contours => Box::new(ContourEdges {
+                    min_length: thresholds.min_contour_length,
+                    min_area: thresholds.min_area,
+                    min_circularity: detection.min_circularity,
+                    min_solidity: detection.min_solidity,
+                    polygon_epsilon: thresholds.polygon_epsilon,
+                }),
+                StageConfig::Components => Box::new(ComponentEdges {
+                    min_area: thresholds.min_area,
+                }),
+                // A ball partly hidden behind others still shows at least half its radius
+                StageConfig::Watershed => Box::new(ClusterEdges {
+                    min_area: thresholds.min_area,
+                    min_depth: thresholds.min_radius as f32 / 2.0,
+                }),
+                StageConfig::Hough => Box::new(Detect(Hough::new(
+                    circle_cache.clone(),
+                    detection.voting(),
+                    detection.min_vote_fraction,
+                ))),
+                StageConfig::Ransac => Box::new(Detect(Ransac(RansacParams {
+                    iterations: detection.ransac_iterations,
+                    band: thresholds.ransac_band,
+                    min_radius: thresholds.min_radius as f32,
+                    max_radius: thresholds.max_radius as f32,
+                    min_fraction: detection.min_vote_fraction,
+                }))),
+                StageConfig::Refine => Box::new(Refine::new(thresholds.refine_band)),
+                StageConfig::Yolo(yolo) => yolo_stage(index, yolo, models)?,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Pipeline::new(stages)
+}
+
+// Builds `config`'s stages without their color lookup table, circle cache or models, only
+// to check that every stage's inputs are produced by an earlier stage. Model files are
+// only checked to exist, since loading one takes seconds.
+pub fn check_stages(config: &PipelineConfig) -> Result<(), PipelineError> {
+    let thresholds = config.detection.scaled(1.0);
+    build_pipeline(
+        config,
+        &thresholds,
+        1.0,
+        &Arc::new(ColorMask::empty()),
+        &Arc::default(),
+        &Models::default(),
+    )?;
+    for (index, stage) in config.stage.iter().enumerate() {
+        if let StageConfig::Yolo(yolo) = stage {
+            fs::metadata(&yolo.model).map_err(|e| PipelineError::Model {
+                index,
+                stage: "yolo",
+                error: format!("{}: {e}", yolo.model),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// Models for the yolo stages by file and input size, kept between rebuilds because
+// loading and optimizing one takes seconds
+#[derive(Default)]
+pub struct Models {
+    #[cfg(feature = "yolo")]
+    loaded: HashMap<(String, usize), Arc<YoloModel>>,
+}
+
+#[cfg(feature = "yolo")]
+impl Models {
+    // Loads the models `config`'s yolo stages use that are not loaded yet, and drops the
+    // ones none of them use anymore. Blocks the calling pipeline while loading.
+    pub fn load(&mut self, config: &PipelineConfig) -> Result<(), PipelineError> {
+        let mut loaded = HashMap::new();
+        for (index, stage) in config.stage.iter().enumerate() {
+            let StageConfig::Yolo(yolo) = stage else {
+                continue;
+            };
+            let key = (yolo.model.clone(), yolo.input_size);
+            if loaded.contains_key(&key) {
+                continue;
+            }
+            let model = match self.loaded.get(&key) {
+                Some(model) => model.clone(),
+                None => {
+                    tracing::info!(model = %yolo.model, "Loading YOLO model");
+                    let model = YoloModel::load(&yolo.model, yolo.input_size).map_err(|e| {
+                        PipelineError::Model {
+                            index,
+                            stage: "yolo",
+                            error: format!("{}: {e:#}", yolo.model),
+                        }
+                    })?;
+                    Arc::new(model)
+                }
+            };
+            loaded.insert(key, model);
+        }
+        self.loaded = loaded;
+        Ok(())
+    }
+
+    // The stage's model, or one that detects nothing if it is not loaded
+    fn get(&self, yolo: &YoloStageConfig) -> Arc<YoloModel> {
+        self.loaded
+            .get(&(yolo.model.clone(), yolo.input_size))
+            .cloned()
+            .unwrap_or_else(|| Arc::new(YoloModel::empty(yolo.input_size)))
+    }
+}
+
+#[cfg(not(feature = "yolo"))]
+impl Models {
+    // Without the yolo feature `build_pipeline` rejects yolo stages, so there is nothing
+    // to load
+    pub fn load(&mut self, _config: &PipelineConfig) -> Result<(), PipelineError> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "yolo")]
+fn yolo_stage(
+    _index: usize,
+    yolo: &YoloStageConfig,
+    models: &Models,
+) -> Result<Box<dyn Stage>, PipelineError> {
+    let params = YoloParams {
+        layout: match yolo.layout {
+            YoloLayoutConfig::Transposed => YoloLayout::Transposed,
+            YoloLayoutConfig::Objectness => YoloLayout::Objectness,
+        },
+        classes: yolo.classes.iter().map(|class| class.id).collect(),
+        min_score: yolo.min_score,
+        max_iou: yolo.max_iou,
+    };
+    Ok(Box::new(Detect(Yolo::new(models.get(yolo), params))))
+}
+
+#[cfg(not(feature = "yolo"))]
+fn yolo_stage(
+    index: usize,
+    _yolo: &YoloStageConfig,
+    _models: &Models,
+) -> Result<Box<dyn Stage>, PipelineError> {
+    Err(PipelineError::MissingFeature {
+        index,
+        stage: "yolo",
+        feature: "yolo",
+    })
+}
+
+// Color thresholding of the frame
+struct ColorMaskStage(Arc<ColorMask>);
+
+impl Stage for ColorMaskStage {
+    fn name(&self) -> &'static str {
+        "mask"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Image]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Mask
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        run_color_mask(data.image.view(), &mut data.mask, &self.0);
+    }
+}
+
+// Traced contours that pass the shape filters, plus their simplified outlines
+struct ContourEdges {
+    min_length: u32,
+    min_area: f32,
+    min_circularity: f32,
+    min_solidity: f32,
+    polygon_epsilon: f32,
+}
+
+impl Stage for ContourEdges {
+    fn name(&self) -> &'static str {
+        "contours"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Mask]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Edges
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        data.polygons = detect_contours(
+            data.mask.view(),
+            &mut data.edges,
+            self.min_length,
+            self.min_area,
+            self.min_circularity,
+            self.min_solidity,
+            self.polygon_epsilon,
+        );
+    }
+}
+
+struct ComponentEdges {
+    min_area: f32,
+}
+
+impl Stage for ComponentEdges {
+    fn name(&self) -> &'static str {
+        "components"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Mask]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Edges
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        detect_component_edges(data.mask.view(), &mut data.edges, self.min_area);
+    }
+}
+
+// Watershed-split blob boundaries, plus the piles they came from
+struct ClusterEdges {
+    min_area: f32,
+    min_depth: f32,
+}
+
+impl Stage for ClusterEdges {
+    fn name(&self) -> &'static str {
+        "watershed"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Mask]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Edges
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        data.piles = detect_cluster_edges(
+            data.mask.view(),
+            &mut data.edges,
+            self.min_area,
+            self.min_depth,
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use ndarray::Array2;
+use vision_detection::pipeline::{Pipeline, StageData};
+
+use crate::config::{ColorSpace, ProcessingConfig, StageConfig};
+use crate::detection::render_output;
+use crate::streaming::{array_to_jpeg, FrameHub, StageStream};
+
+// Per-frame buffers at processing resolution, before the frame is handed to the pipelines
+pub struct ProcessingBuffers {
+    pub frame: Array2<[u8; 3]>,
+    pub frame_undistorted: Array2<[u8; 3]>,
+}
+
+impl ProcessingBuffers {
+    pub fn new(height: usize, width: usize) -> Self {
+        Self {
+            frame: Array2::from_elem((height, width), [0u8; 3]),
+            frame_undistorted: Array2::from_elem((height, width), [0u8; 3]),
+        }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        self.frame.dim()
+    }
+}
+
+// Latency label and dashboard stream of one pipeline stage. Later stages overwrite the
+// stage data, so the stream frame is rendered as soon as the stage finishes and encoded
+// once the whole pipeline has run.
+pub struct StageView {
+    pub name: &'static str,
+    pub stream: StageStream,
+    frame: Array2<u8>,
+    rendered: bool,
+}
+
+impl StageView {
+    // Views for `stages` as built into `pipeline`, reusing the hubs of stages that kept
+    // their position and type so open streams survive a rebuild
+    pub fn for_stages(
+        stages: &[StageConfig],
+        pipeline: &Pipeline,
+        previous: &[StageView],
+    ) -> Vec<StageView> {
+        stages
+            .iter()
+            .zip(pipeline.stages())
+            .enumerate()
+            .map(|(i, (stage, built))| {
+                let id = format!("{i}-{}", stage.kind());
+                let hub = previous
+                    .iter()
+                    .find(|view| view.stream.id == id)
+                    .map(|view| view.stream.hub.clone())
+                    .unwrap_or_else(FrameHub::new);
+                StageView {
+                    name: stage.kind(),
+                    stream: StageStream {
+                        id,
+                        output: built.output(),
+                        hub,
+                    },
+                    frame: Array2::zeros((0, 0)),
+                    rendered: false,
+                }
+            })
+            .collect()
+    }
+
+    // Renders the stage's output if its stream has viewers
+    pub fn capture(&mut self, data: &StageData, color_space: ColorSpace) {
+        self.rendered = self.stream.hub.has_subscribers();
+        if self.rendered {
+            render_output(self.stream.output, data, color_space, &mut self.frame);
+        }
+    }
+
+    pub fn publish(&mut self) {
+        if std::mem::take(&mut self.rendered) {
+            if let Some(jpeg) = array_to_jpeg(self.frame.view()) {
+                self.stream.hub.publish(jpeg);
+            }
+        }
+    }
+}
+
+// Processing resolution for a camera resolution at `scale`
+pub fn processing_dims(height: usize, width: usize, scale: f32) -> (usize, usize) {
+    let proc_height = ((height as f32 * scale).round() as usize).max(1);
+    let proc_width = ((width as f32 * scale).round() as usize).max(1);
+    (proc_height, proc_width)
+}
+
+// Lowers the processing scale when frames take longer than the target frame time and
+// raises it again once there is comfortable headroom
+pub struct AdaptiveScale {
+    scale: f32,
+    min_scale: f32,
+    max_scale: f32,
+    target_frame_ms: f64,
+}
+
+impl AdaptiveScale {
+    const STEP_DOWN: f32 = 0.9;
+    const STEP_UP: f32 = 1.05;
+    // Fraction of the frame budget below which the scale is raised
+    const HEADROOM: f64 = 0.7;
+
+    // Starts at `scale`, pulled into the adaptive bounds only when adapting
+    pub fn new(config: &ProcessingConfig) -> Self {
+        let scale = if config.adaptive {
+            config.scale.clamp(config.min_scale, config.max_scale)
+        } else {
+            config.scale
+        };
+        Self {
+            scale,
+            min_scale: config.min_scale,
+            max_scale: config.max_scale,
+            target_frame_ms: 1000.0 / config.target_fps as f64,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // Feed the average per-frame processing time, from the decoded frame to published
+    // results; returns the new scale if it changed
+    pub fn update(&mut self, avg_frame_ms: f64) -> Option<f32> {
+        let new_scale = if avg_frame_ms > self.target_frame_ms {
+            (self.scale * Self::STEP_DOWN).max(self.min_scale)
+        } else if avg_frame_ms < self.target_frame_ms * Self::HEADROOM {
+            (self.scale * Self::STEP_UP).min(self.max_scale)
+        } else {
+            self.scale
+        };
+
+        if (new_scale - self.scale).abs() > f32::EPSILON {
+            self.scale = new_scale;
+            Some(new_scale)
+        } else {
+            None
+        }
+    }
+}
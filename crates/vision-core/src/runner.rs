@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Arc;
+use std::time::Instant;
+
+use ndarray::{Array2, ArrayView2};
+use rayon::ThreadPool;
+use tokio::sync::RwLock;
+use vision_detection::circle::precompute_circle_points;
+use vision_detection::pipeline::{Pipeline, PipelineError, StageData};
+use vision_detection::undistort::CameraIntrinsics;
+use vision_nt::client::NtClient;
+use vision_nt::message::Value;
+
+use crate::config::{ColorSpace, Config, PipelineConfig, ScaledThresholds};
+use crate::detection::{to_detections, to_outlines, to_pile_estimates, ColorMask};
+use crate::pipeline::{build_pipeline, Models};
+use crate::processing::StageView;
+use crate::streaming::PipelineState;
+use crate::timing::{FrameTimer, LatencyReport};
+
+// One named pipeline with the lookup tables, buffers and streams it keeps between frames
+pub struct PipelineRunner {
+    config: PipelineConfig,
+    scale: f32,
+    thresholds: ScaledThresholds,
+    color_mask: Arc<ColorMask>,
+    circle_cache: Arc<HashMap<u32, Vec<(i32, i32)>>>,
+    models: Models,
+    pipeline: Pipeline,
+    views: Vec<StageView>,
+    data: StageData,
+    state: PipelineState,
+    nt: NtClient,
+    // `/<identity>/<pipeline>`, the NetworkTables table the pipeline publishes under
+    nt_table: String,
+}
+
+impl PipelineRunner {
+    pub fn new(
+        config: PipelineConfig,
+        scale: f32,
+        state: PipelineState,
+        nt: NtClient,
+        identity: &str,
+    ) -> Result<Self, PipelineError> {
+        let nt_table = format!("/{}/{}", identity, config.name);
+        let thresholds = config.detection.scaled(scale);
+        let color_mask = Arc::new(ColorMask::new(&config.detection));
+        let circle_cache = Arc::new(precompute_circle_points(
+            thresholds.min_radius,
+            thresholds.max_radius,
+            thresholds.radius_step,
+        ));
+        let mut models = Models::default();
+        models.load(&config)?;
+        let pipeline = build_pipeline(
+            &config,
+            &thresholds,
+            scale,
+            &color_mask,
+            &circle_cache,
+            &models,
+        )?;
+        let views = StageView::for_stages(&config.stage, &pipeline, &[]);
+        *state.streams.blocking_write() = views.iter().map(|view| view.stream.clone()).collect();
+
+        Ok(Self {
+            config,
+            scale,
+            thresholds,
+            color_mask,
+            circle_cache,
+            models,
+            pipeline,
+            views,
+            data: StageData::new(0, 0),
+            state,
+            nt,
+            nt_table,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.config.detection.color_space
+    }
+
+    // Picks up this pipeline's entry in `config` if it changed
+    pub fn update(&mut self, config: &Config) {
+        let Some(latest) = config.pipelines.iter().find(|p| p.name == self.config.name) else {
+            return;
+        };
+        if latest == &self.config {
+            return;
+        }
+        tracing::info!(pipeline = %self.config.name, "Config update detected, applying new settings...");
+
+        // Color changes require rebuilding the lookup table
+        if !self.color_mask.matches(&latest.detection) {
+            self.color_mask = Arc::new(ColorMask::new(&latest.detection));
+        }
+        self.config = latest.clone();
+        self.rebuild();
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale != self.scale {
+            self.scale = scale;
+            self.rebuild();
+        }
+    }
+
+    // Rebuilds the pipeline for the current config and scale, keeping the running one if
+    // that fails
+    fn rebuild(&mut self) {
+        let thresholds = self.config.detection.scaled(self.scale);
+
+        // Radii change requires recomputing circles (expensive)
+        if thresholds.min_radius != self.thresholds.min_radius
+            || thresholds.max_radius != self.thresholds.max_radius
+            || thresholds.radius_step != self.thresholds.radius_step
+        {
+            self.circle_cache = Arc::new(precompute_circle_points(
+                thresholds.min_radius,
+                thresholds.max_radius,
+                thresholds.radius_step,
+            ));
+        }
+        self.thresholds = thresholds;
+
+        let built = self.models.load(&self.config).and_then(|()| {
+            build_pipeline(
+                &self.config,
+                &self.thresholds,
+                self.scale,
+                &self.color_mask,
+                &self.circle_cache,
+                &self.models,
+            )
+        });
+        match built {
+            Ok(rebuilt) => {
+                self.pipeline = rebuilt;
+                self.views = StageView::for_stages(&self.config.stage, &self.pipeline, &self.views);
+                *self.state.streams.blocking_write() =
+                    self.views.iter().map(|view| view.stream.clone()).collect();
+            }
+            Err(e) => {
+                tracing::warn!(pipeline = %self.config.name, error = %e, "Keeping previous pipeline")
+            }
+        }
+    }
+
+    // Takes `frame` as the next input without copying, handing back a buffer of the
+    // same size to fill with the frame after
+    pub fn swap_frame(&mut self, frame: &mut Array2<[u8; 3]>) {
+        if self.data.dim() != frame.dim() {
+            self.data = StageData::new(frame.nrows(), frame.ncols());
+        }
+        std::mem::swap(&mut self.data.image, frame);
+    }
+
+    pub fn load_frame(&mut self, frame: ArrayView2<[u8; 3]>) {
+        if self.data.dim() != frame.dim() {
+            self.data = StageData::new(frame.nrows(), frame.ncols());
+        }
+        self.data.image.assign(&frame);
+    }
+
+    // Runs every stage on the loaded frame and publishes the results. `to_camera` scales
+    // processing pixels to camera pixels.
+    pub fn run(
+        &mut self,
+        timer: &mut FrameTimer,
+        to_camera: f32,
+        point_intrinsics: Option<&CameraIntrinsics>,
+    ) {
+        let color_space = self.color_space();
+        let mut views = self.views.iter_mut();
+        self.pipeline.run(&mut self.data, |_, data| {
+            if let Some(view) = views.next() {
+                timer.mark(view.name);
+                view.capture(data, color_space);
+            }
+        });
+
+        let data = &self.data;
+        *self.state.detections.blocking_write() =
+            to_detections(&data.circles, to_camera, point_intrinsics, &self.config);
+        self.state
+            .publish_outlines(to_outlines(&data.polygons, to_camera, point_intrinsics));
+        *self.state.piles.blocking_write() =
+            to_pile_estimates(&data.piles, to_camera, point_intrinsics);
+    }
+
+    // Publishes the frame's latency report to the dashboard and NetworkTables, and returns
+    // its total and processing times in milliseconds
+    pub fn report(&self, timer: &FrameTimer, frame_id: u64) -> (f64, f64) {
+        let report = timer.report(frame_id);
+        let times = (report.total_ms, report.processing_ms);
+        self.publish_latency(timer, &report);
+        *self.state.latency.blocking_write() = report;
+        times
+    }
+
+    // Sets `<nt_table>/latency/*` so robot code can latency-compensate: the capture time on
+    // the server's clock, and the timings the dashboard shows. Skipped until the clocks are
+    // synced, so the capture time is always sent with the timings of its own frame.
+    fn publish_latency(&self, timer: &FrameTimer, report: &LatencyReport) {
+        let Some(capture_time_us) = self.nt.server_time_us(timer.captured_at()) else {
+            return;
+        };
+        let stage_names = report.stages.iter().map(|stage| stage.name.to_string());
+        let stage_ms = report.stages.iter().map(|stage| stage.ms);
+        self.nt.set_all(
+            [
+                ("capture_time_us", Value::Int(capture_time_us)),
+                ("frame_id", Value::Int(report.frame_id as i64)),
+                ("total_ms", Value::Double(report.total_ms)),
+                ("processing_ms", Value::Double(report.processing_ms)),
+                ("stage_names", Value::StringArray(stage_names.collect())),
+                ("stage_ms", Value::DoubleArray(stage_ms.collect())),
+            ]
+            .map(|(name, value)| (format!("{}/latency/{}", self.nt_table, name), value)),
+        );
+    }
+
+    // Encodes the stage outputs captured this frame for the dashboard
+    pub fn publish_streams(&mut self) {
+        for view in &mut self.views {
+            view.publish();
+        }
+    }
+}
+
+// A processing-resolution frame handed from the main pipeline to the others
+pub struct SharedFrame {
+    pub image: Array2<[u8; 3]>,
+    pub captured_at: Instant,
+    pub frame_id: u64,
+    pub scale: f32,
+    pub to_camera: f32,
+}
+
+// The main loop's end of a secondary pipeline
+pub struct Secondary {
+    pub frames: SyncSender<Arc<SharedFrame>>,
+    // Set while the pipeline waits for a frame
+    pub idle: Arc<AtomicBool>,
+}
+
+impl Secondary {
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Acquire)
+    }
+
+    // Hands `frame` over if the pipeline is waiting for one; otherwise it skips the frame
+    pub fn offer(&self, frame: &Arc<SharedFrame>) {
+        let _ = self.frames.try_send(frame.clone());
+    }
+}
+
+// Runs a secondary pipeline on the frames it is offered until the sender goes away,
+// raising `idle` while it waits. Its stages use `pool` rather than the global rayon pool
+// the main pipeline runs on.
+pub fn run_secondary(
+    mut runner: PipelineRunner,
+    frames: Receiver<Arc<SharedFrame>>,
+    idle: Arc<AtomicBool>,
+    pool: Arc<ThreadPool>,
+    config: Arc<RwLock<Config>>,
+    point_intrinsics: Option<CameraIntrinsics>,
+) {
+    loop {
+        idle.store(true, Ordering::Release);
+        let Ok(frame) = frames.recv() else {
+            break;
+        };
+        idle.store(false, Ordering::Release);
+
+        runner.update(&config.blocking_read());
+        runner.set_scale(frame.scale);
+
+        let mut timer = FrameTimer::new(frame.captured_at);
+        runner.load_frame(frame.image.view());
+        // Time since capture, including the main pipeline
+        timer.mark("queue");
+
+        pool.install(|| runner.run(&mut timer, frame.to_camera, point_intrinsics.as_ref()));
+        runner.report(&timer, frame.frame_id);
+        runner.publish_streams();
+    }
+    tracing::info!(pipeline = %runner.name(), "Pipeline stopped");
+}
@@ -6,4 +6,4 @@ mod ui;
 
 pub use image::array_to_jpeg;
 pub use server::run_dashboard_server;
-pub use state::FrameHub;
+pub use state::{FrameHub, PipelineState, StageStream};
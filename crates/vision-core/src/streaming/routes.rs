@@ -1,24 +1,70 @@
-use super::state::{AppState, FrameHub};
-use crate::config::DetectionConfig;
+use super::state::{AppState, FrameHub, PipelineState, StreamInfo};
+use crate::config::{DetectionConfig, PipelineConfig, StageConfig};
+use crate::detection::{Detection, Outline, PileEstimate};
+use crate::timing::LatencyReport;
 use axum::{
-    extract::State,
-    http::{header, StatusCode},
-    response::IntoResponse,
+    extract::{rejection::PathRejection, FromRequestParts, Path, State},
+    http::{header, request::Parts, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
 use bytes::BytesMut;
-use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 
-pub async fn stream_mask(State(state): State<AppState>) -> impl IntoResponse {
-    stream_mjpeg_internal(state.mask_frames).await
+// The pipeline a request is for: the `{name}` segment of its path, or the main (first)
+// pipeline on the unprefixed routes kept from before pipelines were named
+pub struct PipelineName(String);
+
+impl FromRequestParts<AppState> for PipelineName {
+    type Rejection = PathRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Path(mut params) =
+            Path::<HashMap<String, String>>::from_request_parts(parts, state).await?;
+        let name = params
+            .remove("name")
+            .unwrap_or_else(|| state.pipelines[0].name.clone());
+        Ok(Self(name))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StreamId {
+    id: String,
+}
+
+fn find_pipeline<'a>(state: &'a AppState, name: &str) -> Result<&'a PipelineState, StatusCode> {
+    state.pipeline(name).ok_or(StatusCode::NOT_FOUND)
 }
 
-pub async fn stream_contours(State(state): State<AppState>) -> impl IntoResponse {
-    stream_mjpeg_internal(state.contour_frames).await
+pub async fn stream_stage(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+    Path(StreamId { id }): Path<StreamId>,
+) -> Response {
+    let hub = match find_pipeline(&state, &name) {
+        Ok(pipeline) => pipeline.get_stream(&id).await,
+        Err(status) => return status.into_response(),
+    };
+    match hub {
+        Some(hub) => stream_mjpeg_internal(hub).await.into_response(),
+        None => (StatusCode::NOT_FOUND, format!("no stream {id}")).into_response(),
+    }
 }
 
-pub async fn stream_circles(State(state): State<AppState>) -> impl IntoResponse {
-    stream_mjpeg_internal(state.circle_frames).await
+pub async fn get_streams_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<Vec<StreamInfo>>, StatusCode> {
+    Ok(Json(find_pipeline(&state, &name)?.get_streams().await))
 }
 
 async fn stream_mjpeg_internal(hub: FrameHub) -> impl IntoResponse {
@@ -45,19 +91,147 @@ async fn stream_mjpeg_internal(hub: FrameHub) -> impl IntoResponse {
     )
 }
 
-pub async fn get_config_handler(State(state): State<AppState>) -> Json<DetectionConfig> {
+pub async fn get_pipelines_handler(State(state): State<AppState>) -> Json<Vec<String>> {
+    Json(
+        state
+            .pipelines
+            .iter()
+            .map(|pipeline| pipeline.name.clone())
+            .collect(),
+    )
+}
+
+pub async fn get_config_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<DetectionConfig>, StatusCode> {
     tracing::info!("getting config");
-    Json(state.get_detection().await)
+    let pipeline = state.get_pipeline_config(&name).await;
+    pipeline
+        .map(|pipeline| Json(pipeline.detection))
+        .ok_or(StatusCode::NOT_FOUND)
 }
 
 pub async fn update_config_handler(
     State(state): State<AppState>,
+    PipelineName(name): PipelineName,
     Json(new_detection_cfg): Json<DetectionConfig>,
-) -> impl IntoResponse {
+) -> Response {
     tracing::info!("Received configuration update request");
-    let mut config = state.config.write().await;
     tracing::debug!("New Config Values: {:?}", new_detection_cfg);
-    config.detection = new_detection_cfg;
+    update_pipeline(&state, &name, |pipeline| {
+        pipeline.detection = new_detection_cfg
+    })
+    .await
+}
+
+pub async fn get_stages_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<Vec<StageConfig>>, StatusCode> {
+    let pipeline = state.get_pipeline_config(&name).await;
+    pipeline
+        .map(|pipeline| Json(pipeline.stage))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Swaps in a new stage chain; the pipeline is rebuilt before its next frame
+pub async fn update_stages_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+    Json(new_stages): Json<Vec<StageConfig>>,
+) -> Response {
+    tracing::info!("Received pipeline update request");
+    update_pipeline(&state, &name, |pipeline| pipeline.stage = new_stages).await
+}
+
+// Stage chain of the main pipeline, in the shape of the old `/pipeline` route
+#[derive(Serialize, Deserialize)]
+pub struct LegacyStages {
+    stage: Vec<StageConfig>,
+}
+
+pub async fn get_legacy_stages_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<LegacyStages>, StatusCode> {
+    let pipeline = state.get_pipeline_config(&name).await;
+    pipeline
+        .map(|pipeline| {
+            Json(LegacyStages {
+                stage: pipeline.stage,
+            })
+        })
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn update_legacy_stages_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+    Json(new_stages): Json<LegacyStages>,
+) -> Response {
+    tracing::info!("Received pipeline update request");
+    update_pipeline(&state, &name, |pipeline| pipeline.stage = new_stages.stage).await
+}
+
+// Applies `update` to the named pipeline if the whole config stays valid
+async fn update_pipeline(
+    state: &AppState,
+    name: &str,
+    update: impl FnOnce(&mut PipelineConfig),
+) -> Response {
+    let mut config = state.config.write().await;
+    let mut updated = config.clone();
+    let Some(pipeline) = updated.pipelines.iter_mut().find(|p| p.name == name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    update(pipeline);
+    if let Err(e) = updated.validate() {
+        tracing::warn!(error = %e, "Rejected configuration update");
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    *config = updated;
     tracing::info!("Configuration successfully updated in AppState");
-    StatusCode::OK
+    StatusCode::OK.into_response()
+}
+
+pub async fn get_latency_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<LatencyReport>, StatusCode> {
+    Ok(Json(find_pipeline(&state, &name)?.get_latency().await))
+}
+
+pub async fn get_detections_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<Vec<Detection>>, StatusCode> {
+    Ok(Json(find_pipeline(&state, &name)?.get_detections().await))
+}
+
+pub async fn get_outlines_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<Vec<Outline>>, StatusCode> {
+    Ok(Json(find_pipeline(&state, &name)?.get_outlines().await))
+}
+
+// Server-sent event stream with the outlines of every processed frame as a JSON array.
+// Clients that fall behind skip frames.
+pub async fn outline_events_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let rx = find_pipeline(&state, &name)?.outline_events.subscribe();
+    let stream = BroadcastStream::new(rx)
+        .filter_map(|result| result.ok())
+        .map(|outlines| Event::default().json_data(&*outlines));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+pub async fn get_piles_handler(
+    State(state): State<AppState>,
+    PipelineName(name): PipelineName,
+) -> Result<Json<Vec<PileEstimate>>, StatusCode> {
+    Ok(Json(find_pipeline(&state, &name)?.get_piles().await))
 }
@@ -1,31 +1,57 @@
 use super::routes::{
-    get_config_handler, stream_circles, stream_contours, stream_mask, update_config_handler,
+    get_config_handler, get_detections_handler, get_latency_handler, get_legacy_stages_handler,
+    get_outlines_handler, get_piles_handler, get_pipelines_handler, get_stages_handler,
+    get_streams_handler, outline_events_handler, stream_stage, update_config_handler,
+    update_legacy_stages_handler, update_stages_handler,
 };
-use super::state::{AppState, FrameHub};
+use super::state::AppState;
 use super::ui::index_page;
 use crate::Config;
 use axum::routing::get;
 use std::net::SocketAddr;
 
-pub async fn run_dashboard_server(
-    mask_hub: FrameHub,
-    contour_hub: FrameHub,
-    circle_hub: FrameHub,
-    config: Config,
-) -> anyhow::Result<AppState> {
+pub async fn run_dashboard_server(config: Config) -> anyhow::Result<AppState> {
     let port = config.web.port;
-    let state = AppState::new(mask_hub, contour_hub, circle_hub, config);
+    let state = AppState::new(config);
     let state_for_axum = state.clone();
 
     let app = axum::Router::new()
         .route("/", get(index_page))
+        .route("/pipelines", get(get_pipelines_handler))
+        .route(
+            "/pipelines/{name}/config",
+            get(get_config_handler).post(update_config_handler),
+        )
+        .route(
+            "/pipelines/{name}/stages",
+            get(get_stages_handler).post(update_stages_handler),
+        )
+        .route("/pipelines/{name}/latency", get(get_latency_handler))
+        .route("/pipelines/{name}/detections", get(get_detections_handler))
+        .route("/pipelines/{name}/outlines", get(get_outlines_handler))
+        .route(
+            "/pipelines/{name}/outlines/events",
+            get(outline_events_handler),
+        )
+        .route("/pipelines/{name}/piles", get(get_piles_handler))
+        .route("/pipelines/{name}/streams", get(get_streams_handler))
+        .route("/pipelines/{name}/stream/{id}", get(stream_stage))
+        // Routes from before pipelines were named, serving the main pipeline
         .route(
             "/config",
             get(get_config_handler).post(update_config_handler),
         )
-        .route("/stream/mask", get(stream_mask))
-        .route("/stream/contours", get(stream_contours))
-        .route("/stream/circles", get(stream_circles))
+        .route(
+            "/pipeline",
+            get(get_legacy_stages_handler).post(update_legacy_stages_handler),
+        )
+        .route("/latency", get(get_latency_handler))
+        .route("/detections", get(get_detections_handler))
+        .route("/outlines", get(get_outlines_handler))
+        .route("/outlines/events", get(outline_events_handler))
+        .route("/piles", get(get_piles_handler))
+        .route("/streams", get(get_streams_handler))
+        .route("/stream/{id}", get(stream_stage))
         .with_state(state_for_axum);
 
     let addr: SocketAddr = format!("0.0.0.0:{}", port).parse()?;
@@ -1,6 +1,10 @@
-use crate::config::{Config, DetectionConfig};
+use crate::config::{Config, PipelineConfig};
+use crate::detection::{Detection, Outline, PileEstimate};
+use crate::timing::LatencyReport;
+use serde::Serialize;
 use std::sync::Arc;
 use tokio::sync::{broadcast, RwLock};
+use vision_detection::pipeline::Slot;
 
 pub type Frame = Vec<u8>;
 
@@ -25,30 +29,126 @@ impl FrameHub {
     }
 }
 
+// Output of one pipeline stage, streamed at /pipelines/{name}/stream/{id}
+#[derive(Clone)]
+pub struct StageStream {
+    // Stage position and type, e.g. "2-morph_open"
+    pub id: String,
+    pub output: Slot,
+    pub hub: FrameHub,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub id: String,
+    pub output: String,
+}
+
+// Results of one named pipeline
+#[derive(Clone)]
+pub struct PipelineState {
+    pub name: String,
+    // One per stage of the running pipeline, replaced when it is rebuilt
+    pub streams: Arc<RwLock<Vec<StageStream>>>,
+    pub latency: Arc<RwLock<LatencyReport>>,
+    pub detections: Arc<RwLock<Vec<Detection>>>,
+    pub outlines: Arc<RwLock<Vec<Outline>>>,
+    // Outlines of every frame as they are produced, for /pipelines/{name}/outlines/events
+    pub outline_events: broadcast::Sender<Arc<Vec<Outline>>>,
+    pub piles: Arc<RwLock<Vec<PileEstimate>>>,
+}
+
+impl PipelineState {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            streams: Arc::new(RwLock::new(Vec::new())),
+            latency: Arc::new(RwLock::new(LatencyReport::default())),
+            detections: Arc::new(RwLock::new(Vec::new())),
+            outlines: Arc::new(RwLock::new(Vec::new())),
+            outline_events: broadcast::channel(16).0,
+            piles: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub async fn get_streams(&self) -> Vec<StreamInfo> {
+        self.streams
+            .read()
+            .await
+            .iter()
+            .map(|stream| StreamInfo {
+                id: stream.id.clone(),
+                output: stream.output.to_string(),
+            })
+            .collect()
+    }
+
+    pub async fn get_stream(&self, id: &str) -> Option<FrameHub> {
+        self.streams
+            .read()
+            .await
+            .iter()
+            .find(|stream| stream.id == id)
+            .map(|stream| stream.hub.clone())
+    }
+
+    pub async fn get_latency(&self) -> LatencyReport {
+        self.latency.read().await.clone()
+    }
+
+    pub async fn get_detections(&self) -> Vec<Detection> {
+        self.detections.read().await.clone()
+    }
+
+    pub async fn get_outlines(&self) -> Vec<Outline> {
+        self.outlines.read().await.clone()
+    }
+
+    // Stores the latest outlines and pushes them to any event stream subscribers. Must
+    // not be called from async code.
+    pub fn publish_outlines(&self, outlines: Vec<Outline>) {
+        if self.outline_events.receiver_count() > 0 {
+            let _ = self.outline_events.send(Arc::new(outlines.clone()));
+        }
+        *self.outlines.blocking_write() = outlines;
+    }
+
+    pub async fn get_piles(&self) -> Vec<PileEstimate> {
+        self.piles.read().await.clone()
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
-    pub mask_frames: FrameHub,
-    pub contour_frames: FrameHub,
-    pub circle_frames: FrameHub,
     pub config: Arc<RwLock<Config>>,
+    // In config order, fixed at startup
+    pub pipelines: Arc<Vec<PipelineState>>,
 }
 
 impl AppState {
-    pub fn new(
-        mask_hub: FrameHub,
-        contour_hub: FrameHub,
-        circle_hub: FrameHub,
-        config: Config,
-    ) -> Self {
+    pub fn new(config: Config) -> Self {
+        let pipelines = config
+            .pipelines
+            .iter()
+            .map(|pipeline| PipelineState::new(pipeline.name.clone()))
+            .collect();
         Self {
-            mask_frames: mask_hub,
-            contour_frames: contour_hub,
-            circle_frames: circle_hub,
             config: Arc::new(RwLock::new(config)),
+            pipelines: Arc::new(pipelines),
         }
     }
 
-    pub async fn get_detection(&self) -> DetectionConfig {
-        self.config.read().await.detection.clone()
+    pub fn pipeline(&self, name: &str) -> Option<&PipelineState> {
+        self.pipelines.iter().find(|pipeline| pipeline.name == name)
+    }
+
+    pub async fn get_pipeline_config(&self, name: &str) -> Option<PipelineConfig> {
+        self.config
+            .read()
+            .await
+            .pipelines
+            .iter()
+            .find(|pipeline| pipeline.name == name)
+            .cloned()
     }
 }
@@ -111,7 +111,7 @@ pub async fn index_page() -> impl IntoResponse {
 
                 .field { display: flex; flex-direction: column; }
                 .field-label { font-size: 0.65rem; margin-bottom: 4px; text-transform: uppercase; }
-                .field input {
+                .field input, .field select {
                     border: 1px solid #000;
                     background: #fff;
                     padding: 6px 8px;
@@ -119,7 +119,7 @@ pub async fn index_page() -> impl IntoResponse {
                     font-family: 'Space Mono', monospace;
                     font-size: 0.8rem;
                 }
-                .field input:focus { outline: 2px solid #000; outline-offset: -2px; }
+                .field input:focus, .field select:focus { outline: 2px solid #000; outline-offset: -2px; }
 
                 .checkbox-field {
                     display: flex;
@@ -131,6 +131,64 @@ pub async fn index_page() -> impl IntoResponse {
                 }
                 .checkbox-field input { margin-right: 10px; cursor: pointer; }
 
+                .pipeline-text {
+                    border: 1px solid #000;
+                    width: 100%;
+                    height: 180px;
+                    padding: 6px 8px;
+                    margin-bottom: 8px;
+                    font-family: 'Space Mono', monospace;
+                    font-size: 0.7rem;
+                    resize: vertical;
+                }
+                .pipeline-hint {
+                    font-size: 0.65rem;
+                    margin-bottom: 8px;
+                    word-break: break-all;
+                }
+                .pipeline-error {
+                    font-size: 0.7rem;
+                    margin-top: 6px;
+                    white-space: pre-wrap;
+                }
+
+                .range-card {
+                    border: 1px solid #000;
+                    background: #fff;
+                    padding: 8px;
+                    margin-bottom: 10px;
+                }
+                .range-head {
+                    display: flex;
+                    justify-content: space-between;
+                    margin-bottom: 8px;
+                }
+                .range-head select {
+                    border: 1px solid #000;
+                    background: #fff;
+                    font-family: 'Space Mono', monospace;
+                    font-size: 0.7rem;
+                    text-transform: uppercase;
+                }
+                .remove-btn, .add-btn {
+                    background: #000;
+                    color: #fff;
+                    border: none;
+                    font-family: 'Space Mono', monospace;
+                    font-size: 0.7rem;
+                    cursor: pointer;
+                }
+                .remove-btn { padding: 2px 8px; }
+                .add-btn { width: 100%; padding: 6px; text-transform: uppercase; }
+
+                .stat-row {
+                    display: flex;
+                    justify-content: space-between;
+                    font-size: 0.75rem;
+                    margin-bottom: 4px;
+                }
+                .stat-row.total { font-weight: 700; }
+
                 .save-area {
                     padding: 12px 20px 14px;
                     border-top: 2px solid #000;
@@ -185,7 +243,7 @@ pub async fn index_page() -> impl IntoResponse {
                    These are toggled via JS based on the count of active streams
                 */
 
-                /* Mode 3: Main (Detect) takes left half, Mask/Contours stack on right */
+                /* Mode 3: Main (first feed, circles when shown) takes left half, the others stack on right */
                 .content-area.layout-3 {
                     grid-template-columns: 2fr 1fr;
                     grid-template-rows: 1fr 1fr;
@@ -193,10 +251,7 @@ pub async fn index_page() -> impl IntoResponse {
                         "main sub1"
                         "main sub2";
                 }
-                /* Need to map specific IDs to areas in Layout 3 */
-                .layout-3 #feed_detect   { grid-area: main; }
-                .layout-3 #feed_mask     { grid-area: sub1; }
-                .layout-3 #feed_contours { grid-area: sub2; }
+                /* Areas are assigned to the visible feeds in order by updateLayout */
 
                 /* Mode 2: Split screen (50/50 vertical) */
                 .content-area.layout-2 {
@@ -205,6 +260,12 @@ pub async fn index_page() -> impl IntoResponse {
                     /* Areas not strictly needed, flow will handle it, but for safety: */
                 }
 
+                /* Mode 4+: Even grid */
+                .content-area.layout-many {
+                    grid-template-columns: repeat(auto-fit, minmax(320px, 1fr));
+                    grid-auto-rows: 1fr;
+                }
+
                 /* Mode 1: Full screen */
                 .content-area.layout-1 {
                     grid-template-columns: 1fr;
@@ -274,32 +335,42 @@ pub async fn index_page() -> impl IntoResponse {
                     <div class="sidebar">
                         <div class="controls-area">
                             <div class="section">
-                                <div class="section-head">Active Streams</div>
-                                <div class="checkbox-field" onclick="toggleStream('detect')">
-                                    <input type="checkbox" id="chk_detect" checked value="on"> <div>Main: Detect</div>
-                                </div>
-                                <div class="checkbox-field" onclick="toggleStream('mask')">
-                                    <input type="checkbox" id="chk_mask" checked value="on"> <div>Sub: Mask</div>
-                                </div>
-                                <div class="checkbox-field" onclick="toggleStream('contours')">
-                                    <input type="checkbox" id="chk_contours" checked value="on"> <div>Sub: Contours</div>
+                                <div class="section-head">Pipeline</div>
+                                <div class="field-group">
+                                    <div class="field"><div class="field-label">Name</div>
+                                        <select id="pipeline_name"></select>
+                                    </div>
                                 </div>
                             </div>
 
                             <div class="section">
-                                <div class="section-head">HSV Range</div>
-                                <div class="field-group">
-                                    <div class="field"><div class="field-label">H Low</div><input type="number" id="h_low"></div>
-                                    <div class="field"><div class="field-label">H High</div><input type="number" id="h_high"></div>
-                                </div>
-                                <div class="field-group">
-                                    <div class="field"><div class="field-label">S Low</div><input type="number" id="s_low"></div>
-                                    <div class="field"><div class="field-label">S High</div><input type="number" id="s_high"></div>
-                                </div>
+                                <div class="section-head">Active Streams</div>
+                                <div id="stream_list"></div>
+                            </div>
+
+                            <div class="section">
+                                <div class="section-head">Stages</div>
+                                <textarea id="pipeline_stages" class="pipeline-text" spellcheck="false"></textarea>
+                                <div class="pipeline-hint">yolo: {"type":"yolo","model":"models/balls.onnx","classes":[{"id":0,"label":"ball"}]}</div>
+                                <button class="add-btn" id="apply_pipeline_btn">Apply Stages</button>
+                                <div id="pipeline_msg" class="pipeline-error"></div>
+                            </div>
+
+                            <div class="section">
+                                <div class="section-head">Color Ranges</div>
                                 <div class="field-group">
-                                    <div class="field"><div class="field-label">V Low</div><input type="number" id="v_low"></div>
-                                    <div class="field"><div class="field-label">V High</div><input type="number" id="v_high"></div>
+                                    <div class="field"><div class="field-label">Space</div>
+                                        <select id="color_space">
+                                            <option value="hsv">HSV</option>
+                                            <option value="yuv">YUV</option>
+                                            <option value="ycbcr">YCbCr</option>
+                                            <option value="lab">Lab</option>
+                                            <option value="chromaticity">rg Chroma</option>
+                                        </select>
+                                    </div>
                                 </div>
+                                <div id="range_list"></div>
+                                <button class="add-btn" id="add_range_btn">+ Add Range</button>
                             </div>
 
                             <div class="section">
@@ -308,12 +379,33 @@ pub async fn index_page() -> impl IntoResponse {
                                     <div class="field"><div class="field-label">Area</div><input type="number" id="min_area"></div>
                                     <div class="field"><div class="field-label">Length</div><input type="number" id="min_length"></div>
                                 </div>
+                                <div class="field-group">
+                                    <div class="field"><div class="field-label">Circ</div><input type="number" step="0.05" id="min_circularity"></div>
+                                    <div class="field"><div class="field-label">Solid</div><input type="number" step="0.05" id="min_solidity"></div>
+                                </div>
+                                <div class="field-group">
+                                    <div class="field"><div class="field-label">Poly Eps</div><input type="number" step="0.5" id="polygon_epsilon"></div>
+                                </div>
                             </div>
 
                             <div class="section">
                                 <div class="section-head">Circles</div>
                                 <div class="field-group">
-                                    <div class="field"><div class="field-label">Vote</div><input type="number" id="vote_thresh"></div>
+                                    <div class="field"><div class="field-label">Iters</div><input type="number" id="ransac_iterations"></div>
+                                    <div class="field"><div class="field-label">Band</div><input type="number" step="0.5" id="ransac_band"></div>
+                                </div>
+                                <div class="field-group">
+                                    <div class="field"><div class="field-label">Voting</div>
+                                        <select id="voting">
+                                            <option value="full">Full</option>
+                                            <option value="gradient">Gradient</option>
+                                        </select>
+                                    </div>
+                                    <div class="field"><div class="field-label">Spread</div><input type="number" step="0.5" id="gradient_spread"></div>
+                                    <div class="field"><div class="field-label">Refine</div><input type="number" step="0.5" id="refine_band"></div>
+                                </div>
+                                <div class="field-group">
+                                    <div class="field"><div class="field-label">Vote Frac</div><input type="number" step="0.05" min="0" max="1" id="min_vote_fraction"></div>
                                     <div class="field"><div class="field-label">Step</div><input type="number" id="radius_step"></div>
                                 </div>
                                 <div class="field-group">
@@ -321,6 +413,11 @@ pub async fn index_page() -> impl IntoResponse {
                                     <div class="field"><div class="field-label">R Max</div><input type="number" id="max_radius"></div>
                                 </div>
                             </div>
+
+                            <div class="section">
+                                <div class="section-head">Latency</div>
+                                <div id="latency_stats"></div>
+                            </div>
                         </div>
 
                         <div class="save-area">
@@ -329,22 +426,7 @@ pub async fn index_page() -> impl IntoResponse {
                         </div>
                     </div>
 
-                    <div class="content-area layout-3" id="grid_container">
-                        <div class="feed" id="feed_detect">
-                            <div class="feed-title">DETECT (FINAL)</div>
-                            <img src="/stream/circles" data-stream-url="/stream/circles" alt="Circles">
-                        </div>
-
-                        <div class="feed" id="feed_mask">
-                            <div class="feed-title">MASK</div>
-                            <img src="/stream/mask" data-stream-url="/stream/mask" alt="Mask">
-                        </div>
-
-                        <div class="feed" id="feed_contours">
-                            <div class="feed-title">CONTOURS</div>
-                            <img src="/stream/contours" data-stream-url="/stream/contours" alt="Contours">
-                        </div>
-                    </div>
+                    <div class="content-area layout-0" id="grid_container"></div>
                 </div>
             </div>
 
@@ -362,22 +444,21 @@ pub async fn index_page() -> impl IntoResponse {
                 // --- Layout Logic ---
 
                 function updateLayout() {
-                    const feeds = ['detect', 'mask', 'contours'];
-                    let activeCount = 0;
+                    const visible = Array.from(gridContainer.querySelectorAll('.feed:not(.hidden)'));
+                    const count = visible.length;
 
-                    // 1. Calculate Active Streams
-                    feeds.forEach(type => {
-                        const isChecked = document.getElementById('chk_' + type).checked;
-                        if(isChecked) activeCount++;
+                    // Layout 3 places the feeds by area, first one as main
+                    const areas = ['main', 'sub1', 'sub2'];
+                    visible.forEach((feed, i) => {
+                        feed.style.gridArea = count === 3 ? areas[i] : '';
                     });
 
-                    // 2. Apply Grid Class to Container
-                    gridContainer.className = 'content-area layout-' + activeCount;
+                    gridContainer.className = 'content-area ' + (count > 3 ? 'layout-many' : 'layout-' + count);
                 }
 
-                function toggleStream(type) {
-                    const checkbox = document.getElementById('chk_' + type);
-                    const container = document.getElementById('feed_' + type);
+                function toggleStream(id) {
+                    const checkbox = document.getElementById('chk_' + id);
+                    const container = document.getElementById('feed_' + id);
                     const img = container.querySelector('img');
 
                     if (checkbox.checked) {
@@ -402,25 +483,204 @@ pub async fn index_page() -> impl IntoResponse {
                     updateLayout();
                 }
 
+                // --- Stream Logic ---
+
+                // One stream per pipeline stage. Streams kept across a pipeline change keep
+                // their selection; the first load shows the final circles, mask and edges.
+                async function loadStreams() {
+                    try {
+                        const res = await fetch(base() + '/streams');
+                        const streams = await res.json();
+
+                        const checked = Array.from(document.querySelectorAll('#stream_list input:checked'));
+                        let shown = new Set(checked.map(chk => chk.dataset.id));
+                        if (shown.size === 0) {
+                            const last = {};
+                            streams.forEach(stream => last[stream.output] = stream.id);
+                            shown = new Set(['circles', 'mask', 'edges'].map(output => last[output]).filter(Boolean));
+                        }
+
+                        // Circle streams first so the detections take the main area
+                        const ordered = streams.filter(stream => stream.output === 'circles')
+                            .concat(streams.filter(stream => stream.output !== 'circles'));
+
+                        const list = document.getElementById('stream_list');
+                        list.innerHTML = '';
+                        gridContainer.querySelectorAll('img').forEach(img => img.removeAttribute('src'));
+                        gridContainer.innerHTML = '';
+                        ordered.forEach(stream => {
+                            const url = base() + '/stream/' + stream.id;
+                            const field = document.createElement('label');
+                            field.className = 'checkbox-field';
+                            field.innerHTML = `<input type="checkbox" id="chk_${stream.id}"> <div>${stream.id} (${stream.output})</div>`;
+                            const checkbox = field.querySelector('input');
+                            checkbox.dataset.id = stream.id;
+                            checkbox.checked = shown.has(stream.id);
+                            checkbox.addEventListener('change', () => toggleStream(stream.id));
+                            list.appendChild(field);
+
+                            const feed = document.createElement('div');
+                            feed.className = 'feed';
+                            feed.id = 'feed_' + stream.id;
+                            feed.innerHTML = `<div class="feed-title">${stream.id}</div><img alt="${stream.id}">`;
+                            feed.querySelector('img').setAttribute('data-stream-url', url);
+                            gridContainer.appendChild(feed);
+                            toggleStream(stream.id);
+                        });
+                        updateLayout();
+                    } catch (e) { console.error("Stream load error", e); }
+                }
+
+                // --- Pipeline Logic ---
+
+                const pipelineSelect = document.getElementById('pipeline_name');
+                const base = () => '/pipelines/' + encodeURIComponent(pipelineSelect.value);
+
+                async function loadPipelines() {
+                    try {
+                        const res = await fetch('/pipelines');
+                        const names = await res.json();
+                        pipelineSelect.innerHTML = '';
+                        names.forEach(name => {
+                            const option = document.createElement('option');
+                            option.value = name;
+                            option.textContent = name;
+                            pipelineSelect.appendChild(option);
+                        });
+                        selectPipeline();
+                    } catch (e) { console.error("Pipelines load error", e); }
+                }
+
+                // Shows the selected pipeline's settings and its default streams
+                function selectPipeline() {
+                    document.getElementById('stream_list').innerHTML = '';
+                    document.getElementById('pipeline_msg').textContent = '';
+                    loadConfig();
+                    loadStages();
+                    loadStreams();
+                }
+
+                async function loadStages() {
+                    try {
+                        const res = await fetch(base() + '/stages');
+                        const stages = await res.json();
+                        const lines = stages.map(stage => '  ' + JSON.stringify(stage));
+                        document.getElementById('pipeline_stages').value = '[\n' + lines.join(',\n') + '\n]';
+                    } catch (e) { console.error("Stages load error", e); }
+                }
+
+                async function applyStages() {
+                    const msg = document.getElementById('pipeline_msg');
+                    let stages;
+                    try {
+                        stages = JSON.parse(document.getElementById('pipeline_stages').value);
+                    } catch (e) {
+                        msg.textContent = 'Invalid JSON: ' + e.message;
+                        return;
+                    }
+
+                    try {
+                        const response = await fetch(base() + '/stages', {
+                            method: 'POST',
+                            headers: { 'Content-Type': 'application/json' },
+                            body: JSON.stringify(stages)
+                        });
+                        if (!response.ok) {
+                            msg.textContent = await response.text();
+                            return;
+                        }
+                        msg.textContent = 'Applied';
+                        // Give the vision loop a frame to rebuild before listing its streams
+                        setTimeout(loadStreams, 500);
+                    } catch (e) {
+                        msg.textContent = 'Failed to apply';
+                    }
+                }
+
+                // --- Color Range Logic ---
+
+                function addRange(kind, range) {
+                    const card = document.createElement('div');
+                    card.className = 'range-card';
+                    card.innerHTML = `
+                        <div class="range-head">
+                            <select class="range-kind">
+                                <option value="include">Include</option>
+                                <option value="exclude">Exclude</option>
+                            </select>
+                            <button class="remove-btn">X</button>
+                        </div>
+                        <div class="field-group">
+                            <div class="field"><div class="field-label">H Low</div><input type="number" class="h_low"></div>
+                            <div class="field"><div class="field-label">H High</div><input type="number" class="h_high"></div>
+                        </div>
+                        <div class="field-group">
+                            <div class="field"><div class="field-label">S Low</div><input type="number" class="s_low"></div>
+                            <div class="field"><div class="field-label">S High</div><input type="number" class="s_high"></div>
+                        </div>
+                        <div class="field-group">
+                            <div class="field"><div class="field-label">V Low</div><input type="number" class="v_low"></div>
+                            <div class="field"><div class="field-label">V High</div><input type="number" class="v_high"></div>
+                        </div>`;
+
+                    const set = (cls, value) => card.querySelector('.' + cls).value = value;
+                    card.querySelector('.range-kind').value = kind;
+                    set('h_low', range.lower[0]);
+                    set('s_low', range.lower[1]);
+                    set('v_low', range.lower[2]);
+                    set('h_high', range.upper[0]);
+                    set('s_high', range.upper[1]);
+                    set('v_high', range.upper[2]);
+
+                    card.querySelector('.remove-btn').addEventListener('click', () => card.remove());
+                    document.getElementById('range_list').appendChild(card);
+                }
+
+                function collectRanges(kind) {
+                    return Array.from(document.querySelectorAll('.range-card'))
+                        .filter(card => card.querySelector('.range-kind').value === kind)
+                        .map(card => {
+                            const get = (cls) => Math.floor(parseFloat(card.querySelector('.' + cls).value) || 0);
+                            return {
+                                lower: [get('h_low'), get('s_low'), get('v_low')],
+                                upper: [get('h_high'), get('s_high'), get('v_high')]
+                            };
+                        });
+                }
+
+                document.getElementById('add_range_btn').addEventListener('click', () => {
+                    addRange('include', { lower: [0, 0, 0], upper: [255, 255, 255] });
+                });
+
                 // --- Config Logic ---
 
+                // Last config from the server; fields without controls are sent back unchanged
+                let loadedConfig = {};
+
                 async function loadConfig() {
                     try {
-                        const res = await fetch('/config');
+                        const res = await fetch(base() + '/config');
                         const cfg = await res.json();
+                        loadedConfig = cfg;
                         // Mapping fields...
-                        document.getElementById('h_low').value = cfg.color_lower[0];
-                        document.getElementById('s_low').value = cfg.color_lower[1];
-                        document.getElementById('v_low').value = cfg.color_lower[2];
-                        document.getElementById('h_high').value = cfg.color_upper[0];
-                        document.getElementById('s_high').value = cfg.color_upper[1];
-                        document.getElementById('v_high').value = cfg.color_upper[2];
+                        document.getElementById('color_space').value = cfg.color_space;
+                        document.getElementById('range_list').innerHTML = '';
+                        cfg.include.forEach(range => addRange('include', range));
+                        cfg.exclude.forEach(range => addRange('exclude', range));
                         document.getElementById('min_area').value = cfg.min_area;
                         document.getElementById('min_length').value = cfg.min_contour_length;
+                        document.getElementById('min_circularity').value = cfg.min_circularity;
+                        document.getElementById('min_solidity').value = cfg.min_solidity;
+                        document.getElementById('polygon_epsilon').value = cfg.polygon_epsilon;
                         document.getElementById('min_radius').value = cfg.min_radius;
                         document.getElementById('max_radius').value = cfg.max_radius;
                         document.getElementById('radius_step').value = cfg.radius_step;
-                        document.getElementById('vote_thresh').value = cfg.vote_thresh;
+                        document.getElementById('min_vote_fraction').value = cfg.min_vote_fraction;
+                        document.getElementById('ransac_iterations').value = cfg.ransac_iterations;
+                        document.getElementById('ransac_band').value = cfg.ransac_band;
+                        document.getElementById('voting').value = cfg.voting;
+                        document.getElementById('gradient_spread').value = cfg.gradient_spread;
+                        document.getElementById('refine_band').value = cfg.refine_band;
                     } catch (e) { console.error("Config load error", e); }
                 }
 
@@ -432,18 +692,28 @@ pub async fn index_page() -> impl IntoResponse {
                     btn.disabled = true;
 
                     const data = {
-                        color_lower: [val('h_low'), val('s_low'), val('v_low')],
-                        color_upper: [val('h_high'), val('s_high'), val('v_high')],
+                        ...loadedConfig,
+                        color_space: document.getElementById('color_space').value,
+                        include: collectRanges('include'),
+                        exclude: collectRanges('exclude'),
                         min_area: val('min_area'),
                         min_contour_length: Math.floor(val('min_length')),
+                        min_circularity: val('min_circularity'),
+                        min_solidity: val('min_solidity'),
+                        polygon_epsilon: val('polygon_epsilon'),
                         min_radius: Math.floor(val('min_radius')),
                         max_radius: Math.floor(val('max_radius')),
                         radius_step: Math.floor(val('radius_step')),
-                        vote_thresh: Math.floor(val('vote_thresh'))
+                        min_vote_fraction: val('min_vote_fraction'),
+                        ransac_iterations: Math.floor(val('ransac_iterations')),
+                        ransac_band: val('ransac_band'),
+                        voting: document.getElementById('voting').value,
+                        gradient_spread: val('gradient_spread'),
+                        refine_band: val('refine_band')
                     };
 
                     try {
-                        const response = await fetch('/config', {
+                        const response = await fetch(base() + '/config', {
                             method: 'POST',
                             headers: { 'Content-Type': 'application/json' },
                             body: JSON.stringify(data)
@@ -451,6 +721,8 @@ pub async fn index_page() -> impl IntoResponse {
                         if (response.ok) {
                             status.classList.add('visible');
                             setTimeout(() => status.classList.remove('visible'), 2000);
+                        } else {
+                            alert("Not saved: " + await response.text());
                         }
                     } catch (e) {
                         alert("Failed to save");
@@ -460,8 +732,28 @@ pub async fn index_page() -> impl IntoResponse {
                     }
                 }
 
+                // --- Latency Logic ---
+
+                async function loadLatency() {
+                    try {
+                        const res = await fetch(base() + '/latency');
+                        const report = await res.json();
+                        const rows = report.stages.map(stage =>
+                            `<div class="stat-row"><span>${stage.name}</span><span>${stage.ms.toFixed(1)} ms</span></div>`
+                        );
+                        rows.unshift(
+                            `<div class="stat-row total"><span>total</span><span>${report.total_ms.toFixed(1)} ms</span></div>`,
+                            `<div class="stat-row"><span>processing</span><span>${report.processing_ms.toFixed(1)} ms</span></div>`
+                        );
+                        document.getElementById('latency_stats').innerHTML = rows.join('');
+                    } catch (e) { console.error("Latency load error", e); }
+                }
+
                 document.getElementById('save_btn').addEventListener('click', updateConfig);
-                loadConfig();
+                document.getElementById('apply_pipeline_btn').addEventListener('click', applyStages);
+                pipelineSelect.addEventListener('change', selectPipeline);
+                loadPipelines();
+                setInterval(loadLatency, 500);
             </script>
         </body>
         </html>
@@ -0,0 +1,77 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+// Marks for time spent getting the frame rather than processing it, which the processing
+// scale has no say over
+const CAPTURE_STAGES: [&str; 2] = ["decode", "queue"];
+
+// Tracks a single frame from capture through every pipeline stage
+pub struct FrameTimer {
+    captured_at: Instant,
+    last_mark: Instant,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl FrameTimer {
+    pub fn new(captured_at: Instant) -> Self {
+        Self {
+            captured_at,
+            last_mark: captured_at,
+            stages: Vec::with_capacity(8),
+        }
+    }
+
+    pub fn captured_at(&self) -> Instant {
+        self.captured_at
+    }
+
+    // Record the time spent since the previous mark (or capture) under `stage`
+    pub fn mark(&mut self, stage: &'static str) {
+        let now = Instant::now();
+        self.stages.push((stage, now - self.last_mark));
+        self.last_mark = now;
+    }
+
+    // Snapshot of the frame's timings, with total latency measured up to now
+    pub fn report(&self, frame_id: u64) -> LatencyReport {
+        let total_ms = duration_ms(self.captured_at.elapsed());
+        let capture_ms: f64 = self
+            .stages
+            .iter()
+            .filter(|(name, _)| CAPTURE_STAGES.contains(name))
+            .map(|&(_, duration)| duration_ms(duration))
+            .sum();
+        LatencyReport {
+            frame_id,
+            total_ms,
+            processing_ms: total_ms - capture_ms,
+            stages: self
+                .stages
+                .iter()
+                .map(|&(name, duration)| StageTiming {
+                    name,
+                    ms: duration_ms(duration),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct LatencyReport {
+    pub frame_id: u64,
+    pub total_ms: f64,
+    // Total without decoding or waiting for the main pipeline
+    pub processing_ms: f64,
+    pub stages: Vec<StageTiming>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageTiming {
+    pub name: &'static str,
+    pub ms: f64,
+}
+
+fn duration_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ndarray::Array2;
+use vision_detection::filter::{BlurChain, BlurOp};
+use vision_detection::synthetic::Scene;
+
+// Processing resolution of a 1280x800 camera at scale 0.5
+const WIDTH: usize = 640;
+const HEIGHT: usize = 400;
+
+fn frame() -> Array2<[u8; 3]> {
+    let mut scene = Scene::new(WIDTH, HEIGHT);
+    scene.noise = 12;
+    scene.add_cluster(200.0, 150.0, 40.0, 5, [250, 200, 0]);
+    let mut frame = Array2::from_elem((HEIGHT, WIDTH), [0; 3]);
+    scene.render(0, &mut frame);
+    frame
+}
+
+fn blur(c: &mut Criterion) {
+    let source = frame();
+    let ops = [
+        ("gaussian_sigma_1", BlurOp::Gaussian { sigma: 1.0 }),
+        ("gaussian_sigma_2", BlurOp::Gaussian { sigma: 2.0 }),
+        ("box_radius_2", BlurOp::Box { radius: 2 }),
+        ("median_radius_1", BlurOp::Median { radius: 1 }),
+        ("median_radius_2", BlurOp::Median { radius: 2 }),
+    ];
+
+    let mut group = c.benchmark_group("blur_640x400");
+    for (name, op) in ops {
+        let mut chain = BlurChain::new(vec![op]);
+        let mut frame = source.clone();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                frame.assign(&source);
+                chain.apply(&mut frame);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, blur);
+criterion_main!(benches);
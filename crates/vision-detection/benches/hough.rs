@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ndarray::Array2;
+use vision_detection::ball::HoughAccumulator;
+use vision_detection::circle::precompute_circle_points;
+use vision_detection::contour::find_contours;
+use vision_detection::synthetic::{Occluder, Scene};
+
+// The default.toml synthetic scene at scale 0.5: a lone ball, two overlapping ones and
+// an occluder cutting into the second
+const WIDTH: usize = 640;
+const HEIGHT: usize = 400;
+
+fn scene() -> Scene {
+    let mut scene = Scene::new(WIDTH, HEIGHT);
+    scene.noise = 8;
+    scene.gradient = 0.3;
+    scene.add_cluster(150.0, 150.0, 30.0, 1, [250, 210, 20]);
+    scene.add_cluster(400.0, 250.0, 37.5, 1, [250, 210, 20]);
+    scene.add_cluster(450.0, 260.0, 37.5, 1, [250, 210, 20]);
+    scene.occluders.push(Occluder {
+        x: 380.0,
+        y: 210.0,
+        width: 30.0,
+        height: 100.0,
+        color: [20, 20, 20],
+    });
+    scene
+}
+
+// Thresholded ball mask and its traced contours as the edge map
+fn mask_and_edges() -> (Array2<u8>, Array2<u8>) {
+    let mut frame = Array2::from_elem((HEIGHT, WIDTH), [0; 3]);
+    scene().render(0, &mut frame);
+    let mask = frame.mapv(|[r, g, b]| {
+        if r > 150 && g > 120 && b < 100 {
+            255
+        } else {
+            0
+        }
+    });
+    let mut edges = Array2::zeros((HEIGHT, WIDTH));
+    for contour in find_contours(mask.view(), 50, 100.0) {
+        for p in contour.points {
+            edges[(p.y as usize, p.x as usize)] = 255;
+        }
+    }
+    (mask, edges)
+}
+
+fn voting(c: &mut Criterion) {
+    let (mask, edges) = mask_and_edges();
+    let cache = precompute_circle_points(20, 100, 4);
+    let mut accumulator = HoughAccumulator::new();
+
+    let mut group = c.benchmark_group("hough_640x400");
+    group.bench_function("full", |b| {
+        b.iter(|| accumulator.hough_transform(edges.view(), &cache, 0.25))
+    });
+    group.bench_function("gradient", |b| {
+        b.iter(|| {
+            accumulator.hough_transform_gradient(
+                edges.view(),
+                mask.view(),
+                &cache,
+                0.25,
+                4f32.to_radians(),
+            )
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, voting);
+criterion_main!(benches);
@@ -1,14 +1,310 @@
-use crate::circle::Circle;
-use ndarray::{Array3, ArrayView2, ArrayViewMut2, Axis};
+use crate::circle::{disc_border, Circle};
+use crate::gradient::sobel_at;
+use ndarray::{Array2, Array3, ArrayView2, ArrayViewMut2, Axis};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HoughVoting {
+    // Every edge pixel votes for every center on a full circle at each radius
+    Full,
+    // Every edge pixel votes only along its gradient, both ways, on an arc of
+    // +/- `spread` radians to absorb orientation error
+    Gradient { spread: f32 },
+}
+
+// Vote counts for every (radius, y, x), kept across frames. Only cells that received
+// votes are scanned for peaks and cleared afterwards, so the cost per frame follows the
+// number of votes rather than the size of the parameter space.
+#[derive(Default)]
+pub struct HoughAccumulator {
+    votes: Array3<u16>,
+    // Flat y * width + x index of every cell that went from 0 to 1 vote, per radius
+    touched: Vec<Vec<u32>>,
+    // Votes the center of a whole ball collects, by radius and gradient spread bits (None
+    // for full voting)
+    whole_ball: HashMap<(u32, Option<u32>), f32>,
+}
+
+impl HoughAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn prepare(&mut self, radii: usize, height: usize, width: usize) {
+        if self.votes.dim() != (radii, height, width) {
+            self.votes = Array3::zeros((radii, height, width));
+            self.touched = vec![Vec::new(); radii];
+        }
+    }
+
+    pub fn hough_transform(
+        &mut self,
+        contour_arr: ArrayView2<u8>,
+        circle_cache: &HashMap<u32, Vec<(i32, i32)>>,
+        min_fraction: f32,
+    ) -> Vec<Circle> {
+        let (height, width) = contour_arr.dim();
+        let radii = sorted_radii(circle_cache);
+        self.prepare(radii.len(), height, width);
+        let max_votes = self.whole_ball_votes(&radii, circle_cache, None);
+
+        let mut edge_pixels: Vec<(usize, usize)> = contour_arr
+            .indexed_iter()
+            .filter_map(|((y, x), &val)| if val == 255 { Some((y, x)) } else { None })
+            .collect();
+        edge_pixels.sort_unstable();
+
+        // Voting
+        let height = height as i32;
+        let width = width as i32;
+
+        self.votes
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip(self.touched.par_iter_mut())
+            .zip(radii.par_iter())
+            .for_each(|((mut radius_frame, touched), &radius)| {
+                let circle_points = &circle_cache[&radius];
+                hough_transform_set_radius_acum(
+                    &edge_pixels,
+                    height,
+                    width,
+                    circle_points,
+                    &mut radius_frame,
+                    touched,
+                );
+            });
+        self.find_peaks(&radii, &max_votes, min_fraction)
+    }
+
+    // Gradient-directed voting: the mask's Sobel orientation at each edge pixel points at
+    // the center, so each pixel casts a handful of votes per radius instead of a full circle
+    pub fn hough_transform_gradient(
+        &mut self,
+        contour_arr: ArrayView2<u8>,
+        mask: ArrayView2<u8>,
+        circle_cache: &HashMap<u32, Vec<(i32, i32)>>,
+        min_fraction: f32,
+        spread: f32,
+    ) -> Vec<Circle> {
+        let (height, width) = contour_arr.dim();
+        let radii = sorted_radii(circle_cache);
+        self.prepare(radii.len(), height, width);
+        let max_votes = self.whole_ball_votes(&radii, circle_cache, Some(spread));
+
+        // Edge pixels with their unit gradient
+        let edges: Vec<(usize, usize, f32, f32)> = contour_arr
+            .indexed_iter()
+            .filter(|&(_, &val)| val == 255)
+            .filter_map(|((y, x), _)| {
+                let (gx, gy) = sobel_at(mask, y, x);
+                let norm = gx.hypot(gy);
+                (norm > 0.0).then(|| (y, x, gx / norm, gy / norm))
+            })
+            .collect();
+
+        self.votes
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip(self.touched.par_iter_mut())
+            .zip(radii.par_iter())
+            .for_each(|((mut radius_frame, touched), &radius)| {
+                let rotations = arc_rotations(radius, spread);
+                for &(y, x, ux, uy) in &edges {
+                    for (cx, cy) in gradient_votes(x as f32, y as f32, ux, uy, &rotations) {
+                        if cx >= 0.0 && cy >= 0.0 && (cx as usize) < width && (cy as usize) < height
+                        {
+                            let cell = &mut radius_frame[(cy as usize, cx as usize)];
+                            if *cell == 0 {
+                                touched.push((cy as usize * width + cx as usize) as u32);
+                            }
+                            *cell = cell.saturating_add(1);
+                        }
+                    }
+                }
+            });
+        self.find_peaks(&radii, &max_votes, min_fraction)
+    }
+
+    // Votes the peak of a whole ball collects at each radius. The border of a digital
+    // disc, which is what a fully visible ball's contour traces, is voted the same way as
+    // a frame, so a whole ball reads confidence 1.0 in either voting mode.
+    fn whole_ball_votes(
+        &mut self,
+        radii: &[u32],
+        circle_cache: &HashMap<u32, Vec<(i32, i32)>>,
+        spread: Option<f32>,
+    ) -> Vec<f32> {
+        radii
+            .iter()
+            .map(|&radius| {
+                let key = (radius, spread.map(f32::to_bits));
+                *self.whole_ball.entry(key).or_insert_with(|| {
+                    let votes = match spread {
+                        None => full_votes_on_disc(radius, &circle_cache[&radius]),
+                        Some(spread) => gradient_votes_on_disc(radius, spread),
+                    };
+                    votes.max(1) as f32
+                })
+            })
+            .collect()
+    }
+
+    // Collects cells with at least `min_fraction` of their radius's `max_votes` and resets
+    // every touched cell to zero
+    fn find_peaks(&mut self, radii: &[u32], max_votes: &[f32], min_fraction: f32) -> Vec<Circle> {
+        let width = self.votes.dim().2;
+        let mut candidates: Vec<(usize, Circle)> = self
+            .votes
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip(self.touched.par_iter_mut())
+            .zip(radii.par_iter().zip(max_votes))
+            .enumerate()
+            .flat_map_iter(|(r_idx, ((mut radius_frame, touched), (&radius, &max)))| {
+                let votes = radius_frame.as_slice_mut().unwrap();
+                let found: Vec<(usize, Circle)> = touched
+                    .drain(..)
+                    .filter_map(|idx| {
+                        let count = std::mem::take(&mut votes[idx as usize]) as u32;
+                        let confidence = count as f32 / max;
+                        (confidence >= min_fraction).then(|| {
+                            let idx = idx as usize;
+                            (
+                                r_idx,
+                                Circle {
+                                    y: (idx / width) as u32,
+                                    x: (idx % width) as u32,
+                                    radius,
+                                    votes: count,
+                                    confidence: confidence.min(1.0),
+                                    class: None,
+                                },
+                            )
+                        })
+                    })
+                    .collect();
+                found
+            })
+            .collect();
+
+        // Strongest relative support first; ties resolve by radius, then raster order
+        candidates.sort_unstable_by(|(ra, a), (rb, b)| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then(b.votes.cmp(&a.votes))
+                .then((ra, a.y, a.x).cmp(&(rb, b.y, b.x)))
+        });
+        suppress_overlaps(candidates.into_iter().map(|(_, c)| c))
+    }
+}
+
+fn sorted_radii(circle_cache: &HashMap<u32, Vec<(i32, i32)>>) -> Vec<u32> {
+    let mut keys: Vec<u32> = circle_cache.keys().copied().collect();
+    keys.sort_unstable();
+    keys
+}
+
+// Cells next to the center that may collect the most votes, since rounding the circle
+// points and gradients shifts some votes by a pixel
+const PEAK_OFFSETS: [(i32, i32); 9] = [
+    (0, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+// Votes the strongest cell around a disc's center gets from its border, with every
+// circle point whose edge pixel lies on the border voting
+fn full_votes_on_disc(radius: u32, circle_points: &[(i32, i32)]) -> usize {
+    let border: HashSet<(i32, i32)> = disc_border(radius as f32).into_iter().collect();
+    PEAK_OFFSETS
+        .iter()
+        .map(|&(sx, sy)| {
+            circle_points
+                .iter()
+                .filter(|&&(dx, dy)| border.contains(&(dx + sx, dy + sy)))
+                .count()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Gradient votes the strongest cell around a disc's center gets from its border
+fn gradient_votes_on_disc(radius: u32, spread: f32) -> usize {
+    // Room for the Sobel kernel around the disc
+    let center = radius as i32 + 3;
+    let size = 2 * center as usize + 1;
+    let mask = Array2::from_shape_fn((size, size), |(y, x)| {
+        let (dx, dy) = (x as i32 - center, y as i32 - center);
+        if dx * dx + dy * dy <= (radius * radius) as i32 {
+            255
+        } else {
+            0
+        }
+    });
+
+    let rotations = arc_rotations(radius, spread);
+    let mut counts = [0; PEAK_OFFSETS.len()];
+    for (dx, dy) in disc_border(radius as f32) {
+        let (x, y) = (center + dx, center + dy);
+        let (gx, gy) = sobel_at(mask.view(), y as usize, x as usize);
+        let norm = gx.hypot(gy);
+        if norm == 0.0 {
+            continue;
+        }
+        for (cx, cy) in gradient_votes(x as f32, y as f32, gx / norm, gy / norm, &rotations) {
+            let offset = (cx as i32 - center, cy as i32 - center);
+            if let Some(i) = PEAK_OFFSETS.iter().position(|&o| o == offset) {
+                counts[i] += 1;
+            }
+        }
+    }
+    counts.into_iter().max().unwrap_or(0)
+}
+
+// Arc of +/- `spread` radians sampled about one pixel apart at `radius`, as (cos, sin)
+// pairs scaled by the radius
+fn arc_rotations(radius: u32, spread: f32) -> Vec<(f32, f32)> {
+    let r = radius.max(1) as f32;
+    let steps = (r * spread).round() as i32;
+    (-steps..=steps)
+        .map(|k| {
+            let (sin, cos) = (k as f32 / r).sin_cos();
+            (cos * r, sin * r)
+        })
+        .collect()
+}
+
+// Rounded centers voted for by an edge pixel at (x, y) with unit gradient (ux, uy), on
+// both sides of the edge
+fn gradient_votes(
+    x: f32,
+    y: f32,
+    ux: f32,
+    uy: f32,
+    rotations: &[(f32, f32)],
+) -> impl Iterator<Item = (f32, f32)> + '_ {
+    rotations.iter().flat_map(move |&(cos, sin)| {
+        let dx = ux * cos - uy * sin;
+        let dy = ux * sin + uy * cos;
+        [(x + dx, y + dy), (x - dx, y - dy)].map(|(cx, cy)| (cx.round(), cy.round()))
+    })
+}
 
 fn hough_transform_set_radius_acum(
     edge_pixels: &[(usize, usize)],
     height: i32,
     width: i32,
     circle_points: &[(i32, i32)],
-    accumulator_matrix: &mut ArrayViewMut2<'_, usize>,
+    accumulator_matrix: &mut ArrayViewMut2<'_, u16>,
+    touched: &mut Vec<u32>,
 ) {
     let accum_ptr = accumulator_matrix.as_mut_ptr();
     let strides = accumulator_matrix.strides();
@@ -18,7 +314,7 @@ fn hough_transform_set_radius_acum(
     for &(dx, dy) in circle_points {
         // dy <= y <= height + dy
         let min_y = dy.max(0) as usize;
-        let max_y = (height + dy).min(height as i32) as usize;
+        let max_y = (height + dy).min(height) as usize;
 
         if min_y >= max_y {
             continue;
@@ -35,82 +331,46 @@ fn hough_transform_set_radius_acum(
                 unsafe {
                     let offset =
                         (center_y as isize * stride_row) + (center_x_i32 as isize * stride_col);
-                    *accum_ptr.offset(offset) += 1;
+                    let cell = accum_ptr.offset(offset);
+                    if *cell == 0 {
+                        touched.push((center_y * width + center_x_i32) as u32);
+                    }
+                    *cell = (*cell).saturating_add(1);
                 }
             }
         }
     }
 }
 
+// One-off transform with a fresh accumulator; prefer a persistent HoughAccumulator
 pub fn hough_transform(
     contour_arr: ArrayView2<u8>,
     circle_cache: &HashMap<u32, Vec<(i32, i32)>>,
-    vote_thresh: u32,
+    min_fraction: f32,
 ) -> Vec<Circle> {
-    let (height, width) = contour_arr.dim();
-
-    let radii: Vec<u32> = {
-        let mut keys: Vec<u32> = circle_cache.keys().copied().collect();
-        keys.sort_unstable();
-        keys
-    };
-    let radii_num: usize = radii.len();
-
-    let mut edge_pixels: Vec<(usize, usize)> = contour_arr
-        .indexed_iter()
-        .filter_map(|((y, x), &val)| if val == 255 { Some((y, x)) } else { None })
-        .collect();
-    edge_pixels.sort_unstable();
-
-    // Parameter space
-    let mut accumulator_matrix: Array3<usize> = Array3::zeros((radii_num, height, width));
-
-    // Voting
-    let height = height as i32;
-    let width = width as i32;
-
-    accumulator_matrix
-        .axis_iter_mut(Axis(0))
-        .into_par_iter()
-        .zip(radii.par_iter())
-        .for_each(|(mut radius_frame, &radius)| {
-            let circle_points = &circle_cache[&radius];
-            hough_transform_set_radius_acum(
-                &edge_pixels,
-                height,
-                width,
-                circle_points,
-                &mut radius_frame,
-            );
-        });
-    let circles = max_find(accumulator_matrix, radii, vote_thresh);
-    circles
+    HoughAccumulator::new().hough_transform(contour_arr, circle_cache, min_fraction)
 }
 
-fn max_find(accumulator_matrix: Array3<usize>, radii: Vec<u32>, vote_thresh: u32) -> Vec<Circle> {
-    let mut result = Vec::<Circle>::new();
-    let threshold = vote_thresh as usize;
-
-    let mut candidates: Vec<Circle> = (0..accumulator_matrix.shape()[0])
-        .into_par_iter()
-        .flat_map(|r_idx| {
-            let slice = accumulator_matrix.index_axis(ndarray::Axis(0), r_idx);
-            let radius = radii[r_idx];
-
-            slice
-                .indexed_iter()
-                .filter(|&(_, &votes)| votes > threshold)
-                .map(move |((y, x), &votes)| Circle {
-                    y: y as u32,
-                    x: x as u32,
-                    radius,
-                    votes: votes as u32,
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
+// One-off gradient transform with a fresh accumulator
+pub fn hough_transform_gradient(
+    contour_arr: ArrayView2<u8>,
+    mask: ArrayView2<u8>,
+    circle_cache: &HashMap<u32, Vec<(i32, i32)>>,
+    min_fraction: f32,
+    spread: f32,
+) -> Vec<Circle> {
+    HoughAccumulator::new().hough_transform_gradient(
+        contour_arr,
+        mask,
+        circle_cache,
+        min_fraction,
+        spread,
+    )
+}
 
-    candidates.sort_by(|a, b| b.votes.cmp(&a.votes));
+// Keeps candidates in order, dropping any that overlap an already kept circle
+pub(crate) fn suppress_overlaps(candidates: impl Iterator<Item = Circle>) -> Vec<Circle> {
+    let mut result = Vec::<Circle>::new();
 
     for candidate in candidates {
         let mut is_duplicate = false;
@@ -135,3 +395,125 @@ fn max_find(accumulator_matrix: Array3<usize>, radii: Vec<u32>, vote_thresh: u32
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circle::precompute_circle_points;
+    use crate::contour::find_contours;
+    use crate::synthetic::{Occluder, Scene};
+
+    const SPREAD: f32 = 0.07;
+
+    // Traced contours of `mask`, as the contours stage draws them
+    fn edges(mask: &Array2<u8>) -> Array2<u8> {
+        let mut edges = Array2::zeros(mask.dim());
+        for contour in find_contours(mask.view(), 0, 0.0) {
+            for p in contour.points {
+                edges[(p.y as usize, p.x as usize)] = 255;
+            }
+        }
+        edges
+    }
+
+    fn disc(radius: u32) -> Array2<u8> {
+        let c = radius as i32 + 10;
+        let size = 2 * c as usize + 1;
+        Array2::from_shape_fn((size, size), |(y, x)| {
+            let (dx, dy) = (x as i32 - c, y as i32 - c);
+            if dx * dx + dy * dy <= (radius * radius) as i32 {
+                255
+            } else {
+                0
+            }
+        })
+    }
+
+    // Rounded circle points and gradients can move the peak a pixel off the center
+    #[test]
+    fn whole_disc_reads_full_confidence_in_both_modes() {
+        let mut accumulator = HoughAccumulator::new();
+        for radius in [12, 25, 41, 60] {
+            let mask = disc(radius);
+            let edges = edges(&mask);
+            let cache = precompute_circle_points(radius, radius + 1, 1);
+            let center = radius + 10;
+
+            let full = accumulator.hough_transform(edges.view(), &cache, 0.5);
+            let gradient = accumulator.hough_transform_gradient(
+                edges.view(),
+                mask.view(),
+                &cache,
+                0.5,
+                SPREAD,
+            );
+            for circle in [&full[0], &gradient[0]] {
+                assert_eq!(circle.radius, radius);
+                assert!(circle.x.abs_diff(center) <= 1 && circle.y.abs_diff(center) <= 1);
+                assert_eq!(circle.confidence, 1.0);
+            }
+        }
+    }
+
+    // Full voting rounds a partial arc onto its best cell more generously than a whole
+    // circle, so it reads a little above the visible fraction
+    #[test]
+    fn half_a_disc_reads_well_below_a_whole_one() {
+        let radius = 40;
+        let mut mask = disc(radius);
+        mask.slice_mut(ndarray::s![.., ..(radius as usize + 10)])
+            .fill(0);
+        let edges = edges(&mask);
+        let cache = precompute_circle_points(radius, radius + 1, 1);
+        let mut accumulator = HoughAccumulator::new();
+
+        let full = accumulator.hough_transform(edges.view(), &cache, 0.2);
+        let gradient =
+            accumulator.hough_transform_gradient(edges.view(), mask.view(), &cache, 0.2, SPREAD);
+        assert!((0.4..0.6).contains(&gradient[0].confidence));
+        assert!((0.4..0.8).contains(&full[0].confidence));
+    }
+
+    // The default.toml synthetic scene at scale 0.5
+    #[test]
+    fn finds_synthetic_balls() {
+        let mut scene = Scene::new(640, 400);
+        scene.noise = 8;
+        scene.gradient = 0.3;
+        scene.add_cluster(150.0, 150.0, 30.0, 1, [250, 210, 20]);
+        scene.add_cluster(400.0, 250.0, 37.5, 1, [250, 210, 20]);
+        scene.add_cluster(450.0, 260.0, 37.5, 1, [250, 210, 20]);
+        scene.occluders.push(Occluder {
+            x: 380.0,
+            y: 210.0,
+            width: 30.0,
+            height: 100.0,
+            color: [20, 20, 20],
+        });
+        let mut frame = Array2::from_elem((400, 640), [0; 3]);
+        let truth = scene.render(0, &mut frame);
+        let mask = frame.mapv(|[r, g, b]| {
+            if r > 150 && g > 120 && b < 100 {
+                255
+            } else {
+                0
+            }
+        });
+        let edges = edges(&mask);
+        let cache = precompute_circle_points(20, 100, 4);
+        let mut accumulator = HoughAccumulator::new();
+
+        let full = accumulator.hough_transform(edges.view(), &cache, 0.4);
+        let gradient =
+            accumulator.hough_transform_gradient(edges.view(), mask.view(), &cache, 0.3, SPREAD);
+        for circles in [full, gradient] {
+            assert_eq!(circles.len(), 3);
+            for ball in &truth {
+                assert!(circles.iter().any(|c| {
+                    (c.x as f32 - ball.x).hypot(c.y as f32 - ball.y) < 3.0
+                        && (c.radius as f32 - ball.radius).abs() < 4.0
+                }));
+            }
+        }
+    }
+}
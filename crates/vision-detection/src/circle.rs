@@ -6,6 +6,51 @@ pub struct Circle {
     pub y: u32,
     pub radius: u32,
     pub votes: u32,
+    // Votes as a fraction of what a whole ball of this radius collects, 0..=1, on the same
+    // scale for every detector
+    pub confidence: f32,
+    // Model class id, from detectors that classify what they find
+    pub class: Option<u32>,
+}
+
+// The pixels a whole ball's traced contour runs through, relative to its center: pixels
+// of the digital disc x² + y² <= r² with a 4-neighbour outside it. About 4√2 r of them,
+// in row order.
+pub fn disc_border(radius: f32) -> Vec<(i32, i32)> {
+    let r2 = radius * radius;
+    let reach = radius.max(0.0).floor() as i32;
+    // Half-width of the disc in row y, -1 outside it
+    let half_width = |y: i32| {
+        let rest = r2 - (y * y) as f32;
+        if rest < 0.0 {
+            return -1;
+        }
+        let mut w = rest.sqrt().floor() as i32;
+        while ((w + 1) * (w + 1)) as f32 <= rest {
+            w += 1;
+        }
+        while w >= 0 && (w * w) as f32 > rest {
+            w -= 1;
+        }
+        w
+    };
+
+    let mut border = Vec::new();
+    for y in -reach..=reach {
+        let w = half_width(y);
+        if w < 0 {
+            continue;
+        }
+        // Pixels past the shorter neighbouring row, and the row ends, touch the outside
+        let covered = half_width(y - 1).min(half_width(y + 1)).min(w - 1);
+        if covered < 0 {
+            border.extend((-w..=w).map(|x| (x, y)));
+        } else {
+            border.extend((-w..=-(covered + 1)).map(|x| (x, y)));
+            border.extend((covered + 1..=w).map(|x| (x, y)));
+        }
+    }
+    border
 }
 
 pub fn precompute_circle_points(
@@ -33,3 +78,37 @@ fn get_circle_points(radius: u32) -> Vec<(i32, i32)> {
     }
     points
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disc_border_matches_brute_force() {
+        for radius in [0.0, 1.0, 2.5, 7.0, 19.3, 40.0] {
+            let r = radius as i32 + 1;
+            let inside = |x: i32, y: i32| (x * x + y * y) as f32 <= radius * radius;
+            let expected: Vec<(i32, i32)> = (-r..=r)
+                .flat_map(|y| (-r..=r).map(move |x| (x, y)))
+                .filter(|&(x, y)| {
+                    inside(x, y)
+                        && [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                            .iter()
+                            .any(|&(dx, dy)| !inside(x + dx, y + dy))
+                })
+                .collect();
+            assert_eq!(disc_border(radius), expected, "radius {radius}");
+        }
+    }
+
+    #[test]
+    fn disc_border_is_about_4_sqrt_2_r() {
+        for radius in [10.0, 30.0, 100.0] {
+            let ratio = disc_border(radius).len() as f32 / radius;
+            assert!(
+                (ratio - 4.0 * std::f32::consts::SQRT_2).abs() < 0.1,
+                "{ratio}"
+            );
+        }
+    }
+}
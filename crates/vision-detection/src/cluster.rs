@@ -0,0 +1,340 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use ndarray::{Array2, ArrayView2, Axis};
+use rayon::prelude::*;
+
+use crate::components::{BoundingBox, Components};
+
+// Seed for one ball: a peak of the distance transform. `radius` is the distance from the
+// peak to the nearest background pixel, about the ball's radius when it is unoccluded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Marker {
+    pub x: usize,
+    pub y: usize,
+    pub radius: f32,
+}
+
+// A connected blob holding more than one ball
+#[derive(Clone, Debug)]
+pub struct Pile {
+    // Label of the blob in `Components::labels`
+    pub label: u32,
+    pub bbox: BoundingBox,
+    // Mean pixel position as (x, y)
+    pub centroid: (f32, f32),
+    // Balls separated by their own marker
+    pub resolved: u32,
+    // Balls the blob's area can hold at the size of its largest marker, never below
+    // `resolved`
+    pub estimate: u32,
+}
+
+// Exact Euclidean distance from each mask pixel to the nearest background pixel, 0 on
+// background. Pixels outside the frame do not count as background, so balls cut off by
+// the frame edge keep their full depth. Separable squared-distance transform after
+// Felzenszwalb and Huttenlocher, columns first then rows.
+pub fn distance_transform(mask: ArrayView2<u8>) -> Array2<f32> {
+    let (height, width) = mask.dim();
+    // Further than any real distance, and still exact in f64 once squared
+    let far = ((height + width) as f64).powi(2);
+    let mut squared = Array2::<f64>::zeros((height, width));
+
+    squared
+        .axis_iter_mut(Axis(1))
+        .into_par_iter()
+        .zip(mask.axis_iter(Axis(1)))
+        .for_each(|(mut column, mask_column)| {
+            let f: Vec<f64> = mask_column
+                .iter()
+                .map(|&m| if m != 0 { far } else { 0.0 })
+                .collect();
+            let mut scratch = Envelope::new(height);
+            for (out, &d) in column.iter_mut().zip(scratch.transform(&f)) {
+                *out = d;
+            }
+        });
+
+    squared
+        .axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .for_each(|mut row| {
+            let f: Vec<f64> = row.to_vec();
+            let mut scratch = Envelope::new(width);
+            for (out, &d) in row.iter_mut().zip(scratch.transform(&f)) {
+                *out = d;
+            }
+        });
+
+    squared.mapv(|d| d.sqrt() as f32)
+}
+
+// Lower envelope of the parabolas (q - p)² + f[p] for the 1D transform
+struct Envelope {
+    // Parabola apexes on the envelope, and where each one takes over
+    vertices: Vec<usize>,
+    bounds: Vec<f64>,
+    output: Vec<f64>,
+}
+
+impl Envelope {
+    fn new(len: usize) -> Self {
+        Self {
+            vertices: vec![0; len],
+            bounds: vec![0.0; len + 1],
+            output: vec![0.0; len],
+        }
+    }
+
+    fn transform(&mut self, f: &[f64]) -> &[f64] {
+        let (v, z) = (&mut self.vertices, &mut self.bounds);
+        let intersect = |q: usize, p: usize| {
+            let (qf, pf) = (q as f64, p as f64);
+            ((f[q] + qf * qf) - (f[p] + pf * pf)) / (2.0 * (qf - pf))
+        };
+
+        let mut k = 0;
+        v[0] = 0;
+        z[0] = f64::NEG_INFINITY;
+        z[1] = f64::INFINITY;
+        for q in 1..f.len() {
+            let mut s = intersect(q, v[k]);
+            while s <= z[k] {
+                k -= 1;
+                s = intersect(q, v[k]);
+            }
+            k += 1;
+            v[k] = q;
+            z[k] = s;
+            z[k + 1] = f64::INFINITY;
+        }
+
+        k = 0;
+        for (q, out) in self.output.iter_mut().enumerate() {
+            while z[k + 1] < q as f64 {
+                k += 1;
+            }
+            let offset = q as f64 - v[k] as f64;
+            *out = offset * offset + f[v[k]];
+        }
+        &self.output
+    }
+}
+
+// Local maxima of `distance` at least `min_radius` deep, deepest first. A peak inside the
+// inscribed circle of a deeper one lies on the same ball and is dropped, which also
+// collapses the plateaus and ridges a single ball produces.
+pub fn find_markers(distance: ArrayView2<f32>, min_radius: f32) -> Vec<Marker> {
+    let (height, width) = distance.dim();
+    let min_radius = min_radius.max(f32::MIN_POSITIVE);
+
+    let mut peaks: Vec<Marker> = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let (y0, y1) = (y.saturating_sub(1), (y + 2).min(height));
+            (0..width).filter_map(move |x| {
+                let d = distance[(y, x)];
+                if d < min_radius {
+                    return None;
+                }
+                let (x0, x1) = (x.saturating_sub(1), (x + 2).min(width));
+                let is_peak = (y0..y1).all(|ny| (x0..x1).all(|nx| distance[(ny, nx)] <= d));
+                is_peak.then_some(Marker { x, y, radius: d })
+            })
+        })
+        .collect();
+    peaks.sort_unstable_by(|a, b| {
+        b.radius
+            .total_cmp(&a.radius)
+            .then((a.y, a.x).cmp(&(b.y, b.x)))
+    });
+
+    let mut markers: Vec<Marker> = Vec::new();
+    for peak in peaks {
+        let covered = markers.iter().any(|m| {
+            let dx = peak.x as f32 - m.x as f32;
+            let dy = peak.y as f32 - m.y as f32;
+            dx * dx + dy * dy < m.radius * m.radius
+        });
+        if !covered {
+            markers.push(peak);
+        }
+    }
+    markers
+}
+
+// Splits the foreground of `distance` into one region per marker by flooding outwards
+// from the markers, deepest pixels first, so neighbouring regions meet along the narrow
+// necks between balls. Region labels are marker indices plus one; background and blobs
+// without a marker stay 0.
+pub fn watershed(distance: ArrayView2<f32>, markers: &[Marker]) -> Array2<u32> {
+    let (height, width) = distance.dim();
+    let mut labels = Array2::<u32>::zeros((height, width));
+
+    // Max-heap on depth, which is never negative so its bit pattern orders like the value.
+    // The sequence number keeps equal depths first in, first out.
+    let mut queue = BinaryHeap::new();
+    let mut sequence = 0u64;
+    for (i, marker) in markers.iter().enumerate() {
+        labels[(marker.y, marker.x)] = i as u32 + 1;
+        queue.push((
+            marker.radius.to_bits(),
+            Reverse(sequence),
+            marker.y,
+            marker.x,
+        ));
+        sequence += 1;
+    }
+
+    while let Some((_, _, y, x)) = queue.pop() {
+        let label = labels[(y, x)];
+        let neighbours = [
+            (y > 0).then(|| (y - 1, x)),
+            (y + 1 < height).then_some((y + 1, x)),
+            (x > 0).then(|| (y, x - 1)),
+            (x + 1 < width).then_some((y, x + 1)),
+        ];
+        for (ny, nx) in neighbours.into_iter().flatten() {
+            if labels[(ny, nx)] == 0 && distance[(ny, nx)] > 0.0 {
+                labels[(ny, nx)] = label;
+                queue.push((distance[(ny, nx)].to_bits(), Reverse(sequence), ny, nx));
+                sequence += 1;
+            }
+        }
+    }
+    labels
+}
+
+// Blobs estimated to hold more than one ball. Where balls stack or overlap in the image
+// their markers merge, so the count falls back to the blob's area over that of one ball,
+// sized by the blob's deepest marker.
+pub fn find_piles(components: &Components, markers: &[Marker]) -> Vec<Pile> {
+    let mut resolved = vec![0u32; components.components.len() + 1];
+    let mut deepest = vec![0f32; components.components.len() + 1];
+    for marker in markers {
+        let label = components.labels[(marker.y, marker.x)] as usize;
+        resolved[label] += 1;
+        deepest[label] = deepest[label].max(marker.radius);
+    }
+
+    components
+        .components
+        .iter()
+        .filter_map(|component| {
+            let label = component.label as usize;
+            if resolved[label] == 0 {
+                return None;
+            }
+            let ball_area = std::f32::consts::PI * deepest[label] * deepest[label];
+            let by_area = (component.pixel_count as f32 / ball_area).round() as u32;
+            let estimate = by_area.max(resolved[label]);
+            (estimate > 1).then_some(Pile {
+                label: component.label,
+                bbox: component.bbox,
+                centroid: component.centroid,
+                resolved: resolved[label],
+                estimate,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{label_components, Connectivity};
+    use crate::rng::Rng;
+    use crate::synthetic::Scene;
+
+    const YELLOW: [u8; 3] = [250, 210, 20];
+
+    #[test]
+    fn distance_transform_matches_brute_force() {
+        let mut rng = Rng::new(3);
+        for (height, width) in [(1, 1), (1, 9), (13, 1), (17, 23), (30, 30)] {
+            let mut mask =
+                Array2::from_shape_fn((height, width), |_| (rng.next_f32() < 0.8) as u8 * 255);
+            mask[(rng.below(height), rng.below(width))] = 0;
+            let background: Vec<(usize, usize)> = mask
+                .indexed_iter()
+                .filter(|&(_, &m)| m == 0)
+                .map(|(p, _)| p)
+                .collect();
+
+            let distance = distance_transform(mask.view());
+            for ((y, x), &d) in distance.indexed_iter() {
+                let expected = background
+                    .iter()
+                    .map(|&(by, bx)| (by as f32 - y as f32).hypot(bx as f32 - x as f32))
+                    .fold(f32::INFINITY, f32::min);
+                assert!((d - expected).abs() < 1e-4, "({x}, {y}): {d} vs {expected}");
+            }
+        }
+    }
+
+    // Mask and ground truth of `count` balls of `radius` touching in a row
+    fn row_of_balls(count: usize, radius: f32) -> (Array2<u8>, Vec<(f32, f32)>) {
+        let mut scene = Scene::new(80 + 2 * radius as usize * count, 120);
+        for i in 0..count {
+            scene.add_cluster(40.0 + radius * (1 + 2 * i) as f32, 60.0, radius, 1, YELLOW);
+        }
+        let mut frame = Array2::from_elem((scene.height, scene.width), [0; 3]);
+        let truth = scene.render(0, &mut frame);
+        let mask = frame.mapv(|[r, _, _]| if r > 100 { 255 } else { 0 });
+        (mask, truth.iter().map(|t| (t.x, t.y)).collect())
+    }
+
+    #[test]
+    fn splits_touching_balls() {
+        for count in [2, 3] {
+            let radius = 20.0;
+            let (mask, centers) = row_of_balls(count, radius);
+            let distance = distance_transform(mask.view());
+
+            let mut markers = find_markers(distance.view(), radius / 2.0);
+            assert_eq!(markers.len(), count);
+            markers.sort_by_key(|m| m.x);
+            for (marker, &(x, y)) in markers.iter().zip(&centers) {
+                assert!((marker.x as f32 + 0.5 - x).hypot(marker.y as f32 + 0.5 - y) < 1.5);
+                assert!((marker.radius - radius).abs() < 1.5, "{}", marker.radius);
+            }
+
+            // Each region is one ball, cut at the neck
+            let regions = watershed(distance.view(), &markers);
+            for (i, marker) in markers.iter().enumerate() {
+                let label = i as u32 + 1;
+                let area = regions.iter().filter(|&&l| l == label).count() as f32;
+                let ball = std::f32::consts::PI * radius * radius;
+                assert!((area / ball - 1.0).abs() < 0.1, "{area}");
+                let (x0, x1) = (marker.x as f32 - radius, marker.x as f32 + radius);
+                assert!(regions
+                    .indexed_iter()
+                    .filter(|&(_, &l)| l == label)
+                    .all(|((_, x), _)| (x0 - 2.0..=x1 + 2.0).contains(&(x as f32))));
+            }
+            assert!(regions
+                .iter()
+                .zip(&mask)
+                .all(|(&l, &m)| (l == 0) == (m == 0)));
+
+            let components = label_components(mask.view(), Connectivity::Eight);
+            assert_eq!(components.components.len(), 1);
+            let piles = find_piles(&components, &markers);
+            assert_eq!(piles.len(), 1);
+            assert_eq!(
+                (piles[0].resolved, piles[0].estimate),
+                (count as u32, count as u32)
+            );
+        }
+    }
+
+    #[test]
+    fn single_ball_is_not_a_pile() {
+        let (mask, _) = row_of_balls(1, 20.0);
+        let distance = distance_transform(mask.view());
+        let markers = find_markers(distance.view(), 10.0);
+        assert_eq!(markers.len(), 1);
+        let components = label_components(mask.view(), Connectivity::Eight);
+        assert!(find_piles(&components, &markers).is_empty());
+    }
+}
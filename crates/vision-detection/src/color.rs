@@ -1,12 +1,22 @@
+use rayon::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ColorRange {
     pub lower: [u8; 3],
     pub upper: [u8; 3],
 }
 
 impl ColorRange {
+    // The hue interval wraps through 0 when `lower[0] > upper[0]`, so reds that straddle
+    // the end of the hue circle can be expressed as a single range
     pub fn in_range(&self, h: u8, s: u8, v: u8) -> bool {
-        h >= self.lower[0]
-            && h <= self.upper[0]
+        let hue_ok = if self.lower[0] <= self.upper[0] {
+            h >= self.lower[0] && h <= self.upper[0]
+        } else {
+            h >= self.lower[0] || h <= self.upper[0]
+        };
+
+        hue_ok
             && s >= self.lower[1]
             && s <= self.upper[1]
             && v >= self.lower[2]
@@ -14,6 +24,71 @@ impl ColorRange {
     }
 }
 
+// Maps an RGB triple into another three-channel byte color space
+pub type ColorConversion = fn(u8, u8, u8) -> (u8, u8, u8);
+
+// Union of `include` ranges minus the union of `exclude` ranges
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColorRanges {
+    pub include: Vec<ColorRange>,
+    pub exclude: Vec<ColorRange>,
+}
+
+impl ColorRanges {
+    pub fn in_range(&self, h: u8, s: u8, v: u8) -> bool {
+        self.include.iter().any(|range| range.in_range(h, s, v))
+            && !self.exclude.iter().any(|range| range.in_range(h, s, v))
+    }
+}
+
+// Bits kept per RGB channel when indexing the lookup table. 7 bits gives a 256 KiB
+// bitset, small enough to stay cache resident while masking.
+const LUT_BITS: u32 = 7;
+const LUT_SHIFT: u32 = 8 - LUT_BITS;
+
+// Precomputed RGB -> in-range bitset, so thresholding costs one memory lookup per pixel
+// regardless of the color space conversion behind it.
+pub struct ColorLut {
+    bits: Vec<u64>,
+}
+
+impl ColorLut {
+    // Builds the table by classifying the center of every quantized RGB bin
+    pub fn new<F>(classify: F) -> Self
+    where
+        F: Fn(u8, u8, u8) -> bool + Sync,
+    {
+        let entries = 1usize << (3 * LUT_BITS);
+        let mut bits = vec![0u64; entries / 64];
+        let center = (1u32 << LUT_SHIFT) >> 1;
+        let channel = |q: usize| ((q as u32) << LUT_SHIFT | center) as u8;
+
+        bits.par_iter_mut()
+            .enumerate()
+            .for_each(|(word_idx, word)| {
+                for bit in 0..64 {
+                    let idx = word_idx * 64 + bit;
+                    let r = channel(idx >> (2 * LUT_BITS));
+                    let g = channel((idx >> LUT_BITS) & ((1 << LUT_BITS) - 1));
+                    let b = channel(idx & ((1 << LUT_BITS) - 1));
+                    if classify(r, g, b) {
+                        *word |= 1 << bit;
+                    }
+                }
+            });
+
+        Self { bits }
+    }
+
+    #[inline]
+    pub fn contains(&self, r: u8, g: u8, b: u8) -> bool {
+        let idx = ((r as usize >> LUT_SHIFT) << (2 * LUT_BITS))
+            | ((g as usize >> LUT_SHIFT) << LUT_BITS)
+            | (b as usize >> LUT_SHIFT);
+        self.bits[idx / 64] & (1 << (idx % 64)) != 0
+    }
+}
+
 // Converts an RGB triple to HSV components scaled to bytes.
 pub fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
     let r = r as f32 / 255.0;
@@ -43,3 +118,261 @@ pub fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
 
     (h_byte, s_byte, v_byte)
 }
+
+// Converts a BT.601 limited-range YUV triple (as produced by YUYV cameras) to RGB.
+pub fn yuv_to_rgb(y: u8, u: u8, v: u8) -> (u8, u8, u8) {
+    let c = y as i32 - 16;
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+
+    let r = (298 * c + 409 * e + 128) >> 8;
+    let g = (298 * c - 100 * d - 208 * e + 128) >> 8;
+    let b = (298 * c + 516 * d + 128) >> 8;
+
+    (
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+    )
+}
+
+// Converts an RGB triple to BT.601 limited-range YUV, the inverse of `yuv_to_rgb`.
+pub fn rgb_to_yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+    (y as u8, u as u8, v as u8)
+}
+
+// Converts an RGB triple to full-range (JPEG) BT.601 YCbCr.
+pub fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+
+    (
+        y.round().clamp(0.0, 255.0) as u8,
+        cb.round().clamp(0.0, 255.0) as u8,
+        cr.round().clamp(0.0, 255.0) as u8,
+    )
+}
+
+// Converts an sRGB triple to CIELab (D65 white) scaled to bytes like OpenCV's 8-bit
+// Lab: L * 255 / 100, a + 128, b + 128.
+pub fn rgb_to_lab(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    // Normalized by the D65 reference white
+    let x = (0.412453 * r + 0.357580 * g + 0.180423 * b) / 0.950456;
+    let y = 0.212671 * r + 0.715160 * g + 0.072169 * b;
+    let z = (0.019334 * r + 0.119193 * g + 0.950227 * b) / 1.088754;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    let l = 116.0 * fy - 16.0;
+    let a = 500.0 * (fx - fy);
+    let b = 200.0 * (fy - fz);
+
+    (
+        (l * 255.0 / 100.0).round().clamp(0.0, 255.0) as u8,
+        (a + 128.0).round().clamp(0.0, 255.0) as u8,
+        (b + 128.0).round().clamp(0.0, 255.0) as u8,
+    )
+}
+
+// Converts an RGB triple to illumination-normalized chromaticity: r = R / (R + G + B) and
+// g = G / (R + G + B) scaled to bytes, plus mean intensity so dark pixels can still be
+// rejected. Black maps to the neutral point (85, 85, 0).
+pub fn rgb_to_chromaticity(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let sum = r as u32 + g as u32 + b as u32;
+    if sum == 0 {
+        return (85, 85, 0);
+    }
+
+    let chroma = |c: u8| ((c as u32 * 255 + sum / 2) / sum) as u8;
+    (chroma(r), chroma(g), ((sum + 1) / 3) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Rgb = (u8, u8, u8);
+
+    const RED: Rgb = (255, 0, 0);
+    const GREEN: Rgb = (0, 255, 0);
+    const BLUE: Rgb = (0, 0, 255);
+    const WHITE: Rgb = (255, 255, 255);
+    const GRAY: Rgb = (128, 128, 128);
+    const BLACK: Rgb = (0, 0, 0);
+
+    fn check(convert: ColorConversion, expected: &[(Rgb, (u8, u8, u8))]) {
+        for &((r, g, b), out) in expected {
+            assert_eq!(convert(r, g, b), out, "converting {:?}", (r, g, b));
+        }
+    }
+
+    // OpenCV cvtColor with COLOR_RGB2YCrCb, whose output is Y, Cr, Cb. Its Cr and Cb are
+    // swapped below to match the Y, Cb, Cr order of `rgb_to_ycbcr`.
+    #[test]
+    fn ycbcr_matches_opencv() {
+        check(
+            rgb_to_ycbcr,
+            &[
+                (RED, (76, 85, 255)),
+                (GREEN, (150, 44, 21)),
+                (BLUE, (29, 255, 107)),
+                (WHITE, (255, 128, 128)),
+                (GRAY, (128, 128, 128)),
+                (BLACK, (0, 128, 128)),
+            ],
+        );
+    }
+
+    // OpenCV cvtColor with COLOR_RGB2Lab on 8-bit input
+    #[test]
+    fn lab_matches_opencv() {
+        check(
+            rgb_to_lab,
+            &[
+                (RED, (136, 208, 195)),
+                (GREEN, (224, 42, 211)),
+                (BLUE, (82, 207, 20)),
+                (WHITE, (255, 128, 128)),
+                (GRAY, (137, 128, 128)),
+                (BLACK, (0, 128, 128)),
+            ],
+        );
+    }
+
+    // OpenCV cvtColor with COLOR_RGB2HSV_FULL, which spreads hue over 0..=255
+    #[test]
+    fn hsv_matches_opencv() {
+        check(
+            rgb_to_hsv,
+            &[
+                (RED, (0, 255, 255)),
+                (GREEN, (85, 255, 255)),
+                (BLUE, (170, 255, 255)),
+                (WHITE, (0, 0, 255)),
+                (GRAY, (0, 0, 128)),
+                (BLACK, (0, 0, 0)),
+            ],
+        );
+    }
+
+    #[test]
+    fn chromaticity_of_primaries_and_grays() {
+        check(
+            rgb_to_chromaticity,
+            &[
+                (RED, (255, 0, 85)),
+                (GREEN, (0, 255, 85)),
+                (BLUE, (0, 0, 85)),
+                (WHITE, (85, 85, 255)),
+                (GRAY, (85, 85, 128)),
+                (BLACK, (85, 85, 0)),
+            ],
+        );
+    }
+
+    #[test]
+    fn chromaticity_ignores_brightness() {
+        let (r, g, _) = rgb_to_chromaticity(200, 100, 50);
+        assert_eq!(rgb_to_chromaticity(100, 50, 25), (r, g, 58));
+        assert_eq!(rgb_to_chromaticity(40, 20, 10), (r, g, 23));
+    }
+
+    // The integer BT.601 approximations lose at most 3 levels through limited range
+    #[test]
+    fn yuv_round_trips() {
+        for r in (0..=255).step_by(5) {
+            for g in (0..=255).step_by(5) {
+                for b in (0..=255).step_by(5) {
+                    let (y, u, v) = rgb_to_yuv(r, g, b);
+                    assert!((16..=235).contains(&y));
+                    assert!((16..=240).contains(&u) && (16..=240).contains(&v));
+                    let (r2, g2, b2) = yuv_to_rgb(y, u, v);
+                    let error = r.abs_diff(r2).max(g.abs_diff(g2)).max(b.abs_diff(b2));
+                    assert!(
+                        error <= 3,
+                        "{:?} came back as {:?}",
+                        (r, g, b),
+                        (r2, g2, b2)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn yuv_to_rgb_clamps_out_of_gamut_input() {
+        assert_eq!(yuv_to_rgb(0, 128, 128), (0, 0, 0));
+        assert_eq!(yuv_to_rgb(255, 128, 128), (255, 255, 255));
+        assert_eq!(yuv_to_rgb(255, 255, 255), (255, 125, 255));
+        assert_eq!(yuv_to_rgb(0, 0, 0), (0, 135, 0));
+    }
+
+    #[test]
+    fn hue_wraps_below_a_full_turn() {
+        // Just short of red from the blue side
+        assert_eq!(rgb_to_hsv(255, 0, 1).0, 255);
+        assert_eq!(rgb_to_hsv(255, 0, 6).0, 254);
+    }
+
+    #[test]
+    fn ranges_wrap_through_zero_hue() {
+        let red = ColorRange {
+            lower: [240, 100, 100],
+            upper: [10, 255, 255],
+        };
+        assert!(red.in_range(250, 200, 200));
+        assert!(red.in_range(0, 200, 200));
+        assert!(red.in_range(10, 100, 255));
+        assert!(!red.in_range(11, 200, 200));
+        assert!(!red.in_range(0, 99, 200));
+
+        let ranges = ColorRanges {
+            include: vec![red],
+            exclude: vec![ColorRange {
+                lower: [0, 0, 250],
+                upper: [255, 255, 255],
+            }],
+        };
+        assert!(ranges.in_range(0, 200, 200));
+        assert!(!ranges.in_range(0, 200, 250));
+    }
+
+    #[test]
+    fn lut_matches_its_classifier_on_bin_centers() {
+        let classify = |r: u8, g: u8, b: u8| r > 128 && g < 64 && b % 4 == 1;
+        let lut = ColorLut::new(classify);
+        for r in (1..=255).step_by(2) {
+            for g in (1..=255).step_by(2) {
+                for b in (1..=255).step_by(2) {
+                    assert_eq!(lut.contains(r, g, b), classify(r, g, b));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use ndarray::{Array2, ArrayView2};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Component {
+    // Value of this component's pixels in `Components::labels`
+    pub label: u32,
+    pub pixel_count: u32,
+    // Pixels inside the outer border: the component plus any holes and whatever lies
+    // inside them
+    pub area: f32,
+    pub bbox: BoundingBox,
+    // Mean pixel position as (x, y)
+    pub centroid: (f32, f32),
+}
+
+pub struct Components {
+    // 0 for background, otherwise the label of the pixel's component
+    pub labels: Array2<u32>,
+    // Sorted by label, in raster order of each component's first pixel
+    pub components: Vec<Component>,
+}
+
+// Per-region totals gathered while relabeling, for foreground and background regions alike
+struct Region {
+    foreground: bool,
+    first: (usize, usize),
+    touches_border: bool,
+    pixel_count: u32,
+    sum_x: u64,
+    sum_y: u64,
+    min: (usize, usize),
+    max: (usize, usize),
+}
+
+// Two-pass connected-component labeling with a union-find over provisional labels.
+// Background is labeled too, with the complementary connectivity, so holes can be
+// attributed to the component that encloses them when computing `area`.
+pub fn label_components(mask: ArrayView2<u8>, connectivity: Connectivity) -> Components {
+    let (height, width) = mask.dim();
+    let mut labels = Array2::<u32>::zeros((height, width));
+    let mut parents: Vec<u32> = Vec::new();
+
+    // First pass: provisional labels, recording equivalences between them
+    let mask = mask.as_standard_layout();
+    let pixels = mask.as_slice().unwrap();
+    let provisional = labels.as_slice_mut().unwrap();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let foreground = pixels[i] != 0;
+            let eight = foreground == (connectivity == Connectivity::Eight);
+
+            // West, north, north-west and north-east neighbours, already labeled
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (y > 0).then(|| i - width),
+                (eight && y > 0 && x > 0).then(|| i - width - 1),
+                (eight && y > 0 && x + 1 < width).then(|| i - width + 1),
+            ];
+
+            let mut label = None;
+            for n in neighbours.into_iter().flatten() {
+                if (pixels[n] != 0) != foreground {
+                    continue;
+                }
+                match label {
+                    None => label = Some(provisional[n]),
+                    Some(l) if l != provisional[n] => union(&mut parents, l, provisional[n]),
+                    Some(_) => {}
+                }
+            }
+            provisional[i] = label.unwrap_or_else(|| {
+                parents.push(parents.len() as u32);
+                parents.len() as u32 - 1
+            });
+        }
+    }
+
+    // Unions keep the smaller label as root, so every parent precedes its children and one
+    // forward sweep points each label straight at its root
+    for label in 0..parents.len() {
+        parents[label] = parents[parents[label] as usize];
+    }
+
+    // Second pass: resolve provisional labels to regions, numbered in raster order of their
+    // first pixel, and gather their statistics
+    let mut region_of: Vec<u32> = vec![u32::MAX; parents.len()];
+    let mut regions: Vec<Region> = Vec::new();
+    for ((y, x), label) in labels.indexed_iter_mut() {
+        let root = parents[*label as usize] as usize;
+        if region_of[root] == u32::MAX {
+            region_of[root] = regions.len() as u32;
+            regions.push(Region {
+                foreground: mask[(y, x)] != 0,
+                first: (y, x),
+                touches_border: false,
+                pixel_count: 0,
+                sum_x: 0,
+                sum_y: 0,
+                min: (y, x),
+                max: (y, x),
+            });
+        }
+        *label = region_of[root];
+
+        let region = &mut regions[*label as usize];
+        region.touches_border |= y == 0 || x == 0 || y + 1 == height || x + 1 == width;
+        region.pixel_count += 1;
+        region.sum_x += x as u64;
+        region.sum_y += y as u64;
+        region.min = (region.min.0.min(y), region.min.1.min(x));
+        region.max = (region.max.0.max(y), region.max.1.max(x));
+    }
+
+    // A region not touching the border is enclosed by the region just above its first
+    // pixel. Enclosed regions start after their encloser, so a reverse sweep adds each
+    // region's filled area to its encloser after it has itself been completed.
+    let mut filled: Vec<u32> = regions.iter().map(|r| r.pixel_count).collect();
+    for (i, region) in regions.iter().enumerate().rev() {
+        if !region.touches_border {
+            let (y, x) = region.first;
+            let encloser = labels[(y - 1, x)] as usize;
+            filled[encloser] += filled[i];
+        }
+    }
+
+    let mut output_label = vec![0u32; regions.len()];
+    let mut components: Vec<Component> = Vec::new();
+    for (i, region) in regions.iter().enumerate() {
+        if !region.foreground {
+            continue;
+        }
+        let label = components.len() as u32 + 1;
+        output_label[i] = label;
+        components.push(Component {
+            label,
+            pixel_count: region.pixel_count,
+            area: filled[i] as f32,
+            bbox: BoundingBox {
+                x: region.min.1 as u32,
+                y: region.min.0 as u32,
+                width: (region.max.1 - region.min.1 + 1) as u32,
+                height: (region.max.0 - region.min.0 + 1) as u32,
+            },
+            centroid: (
+                region.sum_x as f32 / region.pixel_count as f32,
+                region.sum_y as f32 / region.pixel_count as f32,
+            ),
+        });
+    }
+    labels.par_mapv_inplace(|region| output_label[region as usize]);
+
+    Components { labels, components }
+}
+
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        // Path halving
+        let grandparent = parents[parents[label as usize] as usize];
+        parents[label as usize] = grandparent;
+        label = grandparent;
+    }
+    label
+}
+
+fn union(parents: &mut [u32], a: u32, b: u32) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    if a < b {
+        parents[b as usize] = a;
+    } else {
+        parents[a as usize] = b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn mask(rows: &[&str]) -> Array2<u8> {
+        Array2::from_shape_fn((rows.len(), rows[0].len()), |(y, x)| {
+            (rows[y].as_bytes()[x] == b'#') as u8
+        })
+    }
+
+    #[test]
+    fn merges_provisional_labels_of_one_shape() {
+        // Both arms of the U get their own label on the first pass
+        let components = label_components(
+            mask(&["#...#.", "#...#.", "#####.", "......", ".....#"]).view(),
+            Connectivity::Four,
+        );
+        assert_eq!(components.components.len(), 2);
+        let u = &components.components[0];
+        assert_eq!((u.label, u.pixel_count), (1, 9));
+        assert_eq!(
+            u.bbox,
+            BoundingBox {
+                x: 0,
+                y: 0,
+                width: 5,
+                height: 3
+            }
+        );
+        assert_eq!(components.labels[(0, 4)], 1);
+        assert_eq!(components.labels[(4, 5)], 2);
+        assert_eq!(components.labels[(1, 2)], 0);
+    }
+
+    #[test]
+    fn diagonal_neighbours_depend_on_connectivity() {
+        let diagonal = mask(&["#..", ".#.", "..#"]);
+        assert_eq!(
+            label_components(diagonal.view(), Connectivity::Four)
+                .components
+                .len(),
+            3
+        );
+        let eight = label_components(diagonal.view(), Connectivity::Eight);
+        assert_eq!(eight.components.len(), 1);
+        assert_eq!(eight.components[0].centroid, (1.0, 1.0));
+    }
+
+    #[test]
+    fn area_includes_holes_and_islands() {
+        let components = label_components(
+            mask(&["#######", "#.....#", "#.##..#", "#.....#", "#######"]).view(),
+            Connectivity::Eight,
+        );
+        let ring = &components.components[0];
+        assert_eq!(ring.pixel_count, 20);
+        assert_eq!(ring.area, 35.0);
+        let island = &components.components[1];
+        assert_eq!((island.pixel_count, island.area), (2, 2.0));
+    }
+
+    // Reference labeling by flood fill from every unlabeled foreground pixel in raster order
+    fn flood_fill(mask: &Array2<u8>, connectivity: Connectivity) -> Array2<u32> {
+        let (height, width) = mask.dim();
+        let mut labels = Array2::zeros((height, width));
+        let mut next = 0;
+        for start in (0..height).flat_map(|y| (0..width).map(move |x| (y, x))) {
+            if mask[start] == 0 || labels[start] != 0 {
+                continue;
+            }
+            next += 1;
+            labels[start] = next;
+            let mut stack = vec![start];
+            while let Some((y, x)) = stack.pop() {
+                for dy in -1..=1isize {
+                    for dx in -1..=1isize {
+                        if connectivity == Connectivity::Four && dy != 0 && dx != 0 {
+                            continue;
+                        }
+                        let (ny, nx) = (y as isize + dy, x as isize + dx);
+                        if ny < 0 || nx < 0 || ny >= height as isize || nx >= width as isize {
+                            continue;
+                        }
+                        let n = (ny as usize, nx as usize);
+                        if mask[n] != 0 && labels[n] == 0 {
+                            labels[n] = next;
+                            stack.push(n);
+                        }
+                    }
+                }
+            }
+        }
+        labels
+    }
+
+    #[test]
+    fn matches_flood_fill_on_random_masks() {
+        let mut rng = Rng::new(7);
+        for _ in 0..20 {
+            let mask = Array2::from_shape_fn((37, 53), |_| (rng.next_f32() < 0.45) as u8);
+            for connectivity in [Connectivity::Four, Connectivity::Eight] {
+                let components = label_components(mask.view(), connectivity);
+                let expected = flood_fill(&mask, connectivity);
+                assert_eq!(components.labels, expected);
+                let count = *expected.iter().max().unwrap() as usize;
+                assert_eq!(components.components.len(), count);
+                let pixels: u32 = components.components.iter().map(|c| c.pixel_count).sum();
+                assert_eq!(pixels as usize, mask.iter().filter(|&&v| v != 0).count());
+            }
+        }
+    }
+}
@@ -1,10 +1,24 @@
-use ndarray::ArrayView2;
-use std::collections::VecDeque;
+use ndarray::{Array2, ArrayView2};
+use std::f64::consts::PI;
 use std::ops::{Add, Sub};
 
+use crate::components::BoundingBox;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContourKind {
+    // Boundary between a foreground component and the background around it
+    Outer,
+    // Boundary between a foreground component and a background hole inside it
+    Hole,
+}
+
 pub struct Contour {
     pub points: Vec<Point>,
     pub area: f32,
+    pub kind: ContourKind,
+    // Index of the enclosing contour in the returned list: holes belong to an outer
+    // contour, and outer contours sit inside a hole (or at the top level)
+    pub parent: Option<usize>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -33,96 +47,246 @@ impl Sub for Point {
     }
 }
 
+// 8-neighbour offsets as (dy, dx), counterclockwise starting from east
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+fn direction(from: (usize, usize), to: (usize, usize)) -> usize {
+    let offset = (
+        to.0 as isize - from.0 as isize,
+        to.1 as isize - from.1 as isize,
+    );
+    NEIGHBOURS.iter().position(|&n| n == offset).unwrap()
+}
+
+fn step(from: (usize, usize), dir: usize) -> (usize, usize) {
+    let (dy, dx) = NEIGHBOURS[dir % 8];
+    (
+        (from.0 as isize + dy) as usize,
+        (from.1 as isize + dx) as usize,
+    )
+}
+
+// A border found while scanning, identified by its label in the label image
+struct Border {
+    kind: ContourKind,
+    parent: i32,
+    points: Vec<Point>,
+}
+
+// Suzuki–Abe border following. Every border is traced exactly once: traced pixels are
+// marked in a label image so later scan positions on the same border do not start a new
+// trace. The mask is padded with a background frame, so components touching the image
+// edge are traced like any other. Contours are returned in raster order of their first
+// pixel, which puts every parent before its children; contours filtered out by
+// `min_length` or `min_area` are skipped in the hierarchy.
 pub fn find_contours(mask: ArrayView2<u8>, min_length: u32, min_area: f32) -> Vec<Contour> {
     let (height, width) = mask.dim();
-    let min_length = min_length as usize;
-
-    let get_position_if_non_zero_pixel = |mask: ArrayView2<u8>, curr: Point| {
-        let in_bounds =
-            curr.x > -1 && curr.x < width as i32 && curr.y > -1 && curr.y < height as i32;
 
-        if in_bounds && mask[(curr.y as usize, curr.x as usize)] != 0 {
-            Some(curr)
-        } else {
-            None
+    // 0 = background, 1 = unvisited foreground, +/-n = pixel on border n
+    let mut labels = Array2::<i32>::zeros((height + 2, width + 2));
+    for ((y, x), &v) in mask.indexed_iter() {
+        if v != 0 {
+            labels[(y + 1, x + 1)] = 1;
         }
-    };
+    }
 
-    let mut diffs = VecDeque::from(vec![
-        Point::new(-1, 0),  // w
-        Point::new(-1, -1), // nw
-        Point::new(0, -1),  // n
-        Point::new(1, -1),  // ne
-        Point::new(1, 0),   // e
-        Point::new(1, 1),   // se
-        Point::new(0, 1),   // s
-        Point::new(-1, 1),  // sw
-    ]);
+    // Label 1 is the image frame, which acts as the outermost hole
+    let mut borders: Vec<Border> = vec![Border {
+        kind: ContourKind::Hole,
+        parent: 0,
+        points: Vec::new(),
+    }];
 
-    let mut contours: Vec<Contour> = Vec::new();
-
-    for y in 0..height {
-        for x in 0..width {
-            if mask[(y, x)] == 0 {
-                continue;
-            }
+    for y in 1..=height {
+        let mut last_label = 1;
+        for x in 1..=width {
+            let value = labels[(y, x)];
+            let start = if value == 1 && labels[(y, x - 1)] == 0 {
+                Some((ContourKind::Outer, (y, x - 1)))
+            } else if value >= 1 && labels[(y, x + 1)] == 0 {
+                if value > 1 {
+                    last_label = value;
+                }
+                Some((ContourKind::Hole, (y, x + 1)))
+            } else {
+                None
+            };
 
-            if mask[(y, x)] == 255 && x > 0 && mask[(y, x - 1)] == 0 {
-                let adj = Point::new((x - 1) as i32, y as i32);
-                let curr = Point::new(x as i32, y as i32);
-                let mut contour_points: Vec<Point> = Vec::new();
-
-                rotate_to_value(&mut diffs, adj - curr);
-
-                if let Some(pos1) = diffs
-                    .iter()
-                    .find_map(|diff| get_position_if_non_zero_pixel(mask, *diff + curr))
-                {
-                    let mut pos2 = pos1;
-                    let mut pos3 = curr;
-
-                    loop {
-                        contour_points.push(Point::new(pos3.x, pos3.y));
-                        rotate_to_value(&mut diffs, pos2 - pos3);
-                        let pos4 = diffs
-                            .iter()
-                            .rev()
-                            .find_map(|diff| get_position_if_non_zero_pixel(mask, *diff + pos3))
-                            .unwrap();
-
-                        if pos4 == curr && pos3 == pos1 {
-                            break;
-                        }
-
-                        pos2 = pos3;
-                        pos3 = pos4;
-                    }
+            if let Some((kind, from)) = start {
+                let label = borders.len() as i32 + 1;
+                let last = &borders[last_label as usize - 1];
+                let parent = if kind == last.kind {
+                    last.parent
                 } else {
-                    contour_points.push(curr);
-                }
-                let area = contour_area(&contour_points);
-                if contour_points.len() >= min_length && area > min_area {
-                    contours.push(Contour {
-                        points: contour_points,
-                        area: area,
-                    })
+                    last_label
                 };
+                let points = follow_border(&mut labels, (y, x), from, label);
+                borders.push(Border {
+                    kind,
+                    parent,
+                    points,
+                });
+            }
+
+            let value = labels[(y, x)];
+            if value != 0 && value != 1 {
+                last_label = value.abs();
             }
         }
     }
 
+    // Map each border label to its nearest ancestor that survived filtering
+    let min_length = min_length as usize;
+    let mut kept: Vec<Option<usize>> = vec![None; borders.len() + 1];
+    let mut contours: Vec<Contour> = Vec::new();
+    for (i, border) in borders.into_iter().enumerate().skip(1) {
+        let label = i + 1;
+        let parent = kept[border.parent as usize];
+        let area = contour_area(&border.points);
+        if border.points.len() >= min_length && area > min_area {
+            kept[label] = Some(contours.len());
+            contours.push(Contour {
+                points: border.points,
+                area,
+                kind: border.kind,
+                parent,
+            });
+        } else {
+            kept[label] = parent;
+        }
+    }
+
     contours
 }
 
-fn rotate_to_value(values: &mut VecDeque<Point>, value: Point) {
-    let rotate_pos = values
-        .iter()
-        .position(|x| x.x == value.x && x.y == value.y)
+// Traces the border through `start`, entering from the background pixel `from`, and
+// marks it with `label`. Positions are in padded label-image coordinates.
+fn follow_border(
+    labels: &mut Array2<i32>,
+    start: (usize, usize),
+    from: (usize, usize),
+    label: i32,
+) -> Vec<Point> {
+    let to_point = |(y, x): (usize, usize)| Point::new(x as i32 - 1, y as i32 - 1);
+
+    // Clockwise from `from`, find the first foreground neighbour
+    let from_dir = direction(start, from);
+    let Some(first) = (0..8)
+        .map(|i| step(start, from_dir + 8 - i))
+        .find(|&p| labels[p] != 0)
+    else {
+        // Isolated pixel
+        labels[start] = -label;
+        return vec![to_point(start)];
+    };
+
+    let mut points = Vec::new();
+    let mut prev = first;
+    let mut curr = start;
+    loop {
+        points.push(to_point(curr));
+
+        // Counterclockwise from the previous pixel, find the next foreground neighbour,
+        // noting whether the background pixel to the east was passed on the way
+        let prev_dir = direction(curr, prev);
+        let mut east_is_background = false;
+        let mut next = prev;
+        for i in 1..=8 {
+            let dir = (prev_dir + i) % 8;
+            let candidate = step(curr, dir);
+            if labels[candidate] != 0 {
+                next = candidate;
+                break;
+            }
+            if dir == 0 {
+                east_is_background = true;
+            }
+        }
+
+        if east_is_background {
+            labels[curr] = -label;
+        } else if labels[curr] == 1 {
+            labels[curr] = label;
+        }
+
+        if next == start && curr == first {
+            break;
+        }
+        prev = curr;
+        curr = next;
+    }
+
+    points
+}
+
+// Douglas–Peucker simplification of a closed contour: keeps the vertices needed for
+// every dropped point to lie within `epsilon` pixels of the polygon
+pub fn approx_polygon(points: &[Point], epsilon: f32) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    // Split the closed curve at the point farthest from the first one
+    let distance_sq = |a: Point, b: Point| {
+        let d = a - b;
+        d.x as i64 * d.x as i64 + d.y as i64 * d.y as i64
+    };
+    let far = (1..points.len())
+        .max_by_key(|&i| distance_sq(points[0], points[i]))
         .unwrap();
-    values.rotate_left(rotate_pos);
+
+    let n = points.len();
+    let mut keep = vec![false; n];
+    keep[0] = true;
+    keep[far] = true;
+    // Spans (start, end) of indices into the closed curve, end wrapping to 0
+    let mut stack = vec![(0, far), (far, n)];
+    while let Some((start, end)) = stack.pop() {
+        let a = points[start];
+        let b = points[end % n];
+        let (dx, dy) = ((b.x - a.x) as f32, (b.y - a.y) as f32);
+        let length = dx.hypot(dy);
+
+        let mut farthest = None;
+        let mut max_distance = epsilon;
+        for (i, p) in points.iter().enumerate().take(end).skip(start + 1) {
+            let (px, py) = ((p.x - a.x) as f32, (p.y - a.y) as f32);
+            let distance = if length > 0.0 {
+                (px * dy - py * dx).abs() / length
+            } else {
+                px.hypot(py)
+            };
+            if distance > max_distance {
+                max_distance = distance;
+                farthest = Some(i);
+            }
+        }
+
+        if let Some(i) = farthest {
+            keep[i] = true;
+            stack.push((start, i));
+            stack.push((i, end));
+        }
+    }
+
+    points
+        .iter()
+        .zip(&keep)
+        .filter_map(|(&p, &k)| k.then_some(p))
+        .collect()
 }
 
-fn contour_area(contour_points: &Vec<Point>) -> f32 {
+// Polygon area by the shoelace formula
+pub fn contour_area(contour_points: &[Point]) -> f32 {
     let mut area: f32 = 0.0;
     let n = contour_points.len();
     for i in 0..n {
@@ -132,3 +296,689 @@ fn contour_area(contour_points: &Vec<Point>) -> f32 {
     }
     0.5 * area.abs()
 }
+
+// --- Shape descriptors ---
+// All take the contour as a closed polygon through the border pixel centers.
+
+// Length of the closed polygon
+pub fn perimeter(points: &[Point]) -> f32 {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            let d = points[(i + 1) % n] - points[i];
+            ((d.x * d.x + d.y * d.y) as f32).sqrt()
+        })
+        .sum()
+}
+
+// Box around the points, cut off at the image's top and left edges
+pub fn bounding_rect(points: &[Point]) -> BoundingBox {
+    let (mut min, mut max) = (
+        Point::new(i32::MAX, i32::MAX),
+        Point::new(i32::MIN, i32::MIN),
+    );
+    for p in points {
+        min = Point::new(min.x.min(p.x), min.y.min(p.y));
+        max = Point::new(max.x.max(p.x), max.y.max(p.y));
+    }
+    if points.is_empty() {
+        return BoundingBox {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+    }
+    let (x, y) = (min.x.max(0), min.y.max(0));
+    BoundingBox {
+        x: x as u32,
+        y: y as u32,
+        width: (max.x - x + 1).max(0) as u32,
+        height: (max.y - y + 1).max(0) as u32,
+    }
+}
+
+// Convex hull by Andrew's monotone chain, without collinear points
+pub fn convex_hull(points: &[Point]) -> Vec<Point> {
+    let mut sorted = points.to_vec();
+    sorted.sort_unstable_by_key(|p| (p.x, p.y));
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+
+    let cross = |o: Point, a: Point, b: Point| {
+        (a.x - o.x) as i64 * (b.y - o.y) as i64 - (a.y - o.y) as i64 * (b.x - o.x) as i64
+    };
+    let mut hull: Vec<Point> = Vec::with_capacity(sorted.len() + 1);
+    // Lower chain left to right, then upper chain right to left
+    for &p in &sorted {
+        while hull.len() >= 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0 {
+            hull.pop();
+        }
+        hull.push(p);
+    }
+    let lower_len = hull.len() + 1;
+    for &p in sorted.iter().rev().skip(1) {
+        while hull.len() >= lower_len && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0 {
+            hull.pop();
+        }
+        hull.push(p);
+    }
+    // The upper chain ends back at the first point
+    hull.pop();
+    hull
+}
+
+// Contour area over convex hull area: 1 for convex shapes, lower for dented ones
+pub fn solidity(points: &[Point]) -> f32 {
+    let hull_area = contour_area(&convex_hull(points));
+    if hull_area > 0.0 {
+        contour_area(points) / hull_area
+    } else {
+        0.0
+    }
+}
+
+// 4πA/P²: 1 for a perfect circle, lower for elongated or ragged shapes
+pub fn circularity(points: &[Point]) -> f32 {
+    let perimeter = perimeter(points);
+    if perimeter > 0.0 {
+        4.0 * std::f32::consts::PI * contour_area(points) / (perimeter * perimeter)
+    } else {
+        0.0
+    }
+}
+
+// Rectangle rotated by `angle` radians, with `width` along that direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RotatedRect {
+    pub center: (f32, f32),
+    pub width: f32,
+    pub height: f32,
+    pub angle: f32,
+}
+
+// Minimum-area enclosing rectangle. One side of it lies along a hull edge, so every edge
+// direction is tried.
+pub fn min_area_rect(points: &[Point]) -> RotatedRect {
+    let hull = convex_hull(points);
+    let to_f64 = |p: Point| (p.x as f64, p.y as f64);
+    let mut best = match hull.first() {
+        Some(&p) => RotatedRect {
+            center: (p.x as f32, p.y as f32),
+            width: 0.0,
+            height: 0.0,
+            angle: 0.0,
+        },
+        None => RotatedRect {
+            center: (0.0, 0.0),
+            width: 0.0,
+            height: 0.0,
+            angle: 0.0,
+        },
+    };
+    let mut best_area = f64::INFINITY;
+
+    for i in 0..hull.len() {
+        let (x0, y0) = to_f64(hull[i]);
+        let (x1, y1) = to_f64(hull[(i + 1) % hull.len()]);
+        let length = (x1 - x0).hypot(y1 - y0);
+        if length == 0.0 {
+            continue;
+        }
+        let (ux, uy) = ((x1 - x0) / length, (y1 - y0) / length);
+
+        let (mut min_u, mut max_u) = (f64::INFINITY, f64::NEG_INFINITY);
+        let (mut min_v, mut max_v) = (f64::INFINITY, f64::NEG_INFINITY);
+        for &p in &hull {
+            let (x, y) = to_f64(p);
+            let u = x * ux + y * uy;
+            let v = -x * uy + y * ux;
+            (min_u, max_u) = (min_u.min(u), max_u.max(u));
+            (min_v, max_v) = (min_v.min(v), max_v.max(v));
+        }
+
+        let area = (max_u - min_u) * (max_v - min_v);
+        if area < best_area {
+            best_area = area;
+            let (cu, cv) = ((min_u + max_u) / 2.0, (min_v + max_v) / 2.0);
+            best = RotatedRect {
+                center: ((cu * ux - cv * uy) as f32, (cu * uy + cv * ux) as f32),
+                width: (max_u - min_u) as f32,
+                height: (max_v - min_v) as f32,
+                angle: uy.atan2(ux) as f32,
+            };
+        }
+    }
+    best
+}
+
+// Spatial moments (m), central moments (mu) and scale-normalized central moments (nu)
+// of the region enclosed by the polygon, computed from its edges
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Moments {
+    pub m00: f64,
+    pub m10: f64,
+    pub m01: f64,
+    pub m20: f64,
+    pub m11: f64,
+    pub m02: f64,
+    pub m30: f64,
+    pub m21: f64,
+    pub m12: f64,
+    pub m03: f64,
+    pub mu20: f64,
+    pub mu11: f64,
+    pub mu02: f64,
+    pub mu30: f64,
+    pub mu21: f64,
+    pub mu12: f64,
+    pub mu03: f64,
+    pub nu20: f64,
+    pub nu11: f64,
+    pub nu02: f64,
+    pub nu30: f64,
+    pub nu21: f64,
+    pub nu12: f64,
+    pub nu03: f64,
+}
+
+impl Moments {
+    pub fn centroid(&self) -> Option<(f32, f32)> {
+        (self.m00 != 0.0).then(|| ((self.m10 / self.m00) as f32, (self.m01 / self.m00) as f32))
+    }
+
+    // The seven Hu invariants: unchanged by translation, scale and rotation
+    pub fn hu(&self) -> [f64; 7] {
+        let (nu20, nu11, nu02) = (self.nu20, self.nu11, self.nu02);
+        let (nu30, nu21, nu12, nu03) = (self.nu30, self.nu21, self.nu12, self.nu03);
+
+        let (a, b) = (nu30 + nu12, nu21 + nu03);
+        let (c, d) = (nu30 - 3.0 * nu12, 3.0 * nu21 - nu03);
+        let diff = nu20 - nu02;
+        [
+            nu20 + nu02,
+            diff * diff + 4.0 * nu11 * nu11,
+            c * c + d * d,
+            a * a + b * b,
+            c * a * (a * a - 3.0 * b * b) + d * b * (3.0 * a * a - b * b),
+            diff * (a * a - b * b) + 4.0 * nu11 * a * b,
+            d * a * (a * a - 3.0 * b * b) - c * b * (3.0 * a * a - b * b),
+        ]
+    }
+}
+
+// Moments of the polygon's interior via Green's theorem, independent of orientation
+pub fn moments(points: &[Point]) -> Moments {
+    let n = points.len();
+    let mut m = Moments::default();
+    for i in 0..n {
+        let (x0, y0) = (points[i].x as f64, points[i].y as f64);
+        let (x1, y1) = (points[(i + 1) % n].x as f64, points[(i + 1) % n].y as f64);
+        let a = x0 * y1 - x1 * y0;
+        let (sx, sy) = (x0 + x1, y0 + y1);
+
+        m.m00 += a;
+        m.m10 += a * sx;
+        m.m01 += a * sy;
+        m.m20 += a * (x0 * x0 + x0 * x1 + x1 * x1);
+        m.m11 += a * (x0 * (2.0 * y0 + y1) + x1 * (y0 + 2.0 * y1));
+        m.m02 += a * (y0 * y0 + y0 * y1 + y1 * y1);
+        m.m30 += a * sx * (x0 * x0 + x1 * x1);
+        m.m21 += a * (x0 * x0 * (3.0 * y0 + y1) + 2.0 * x0 * x1 * sy + x1 * x1 * (y0 + 3.0 * y1));
+        m.m12 += a * (y0 * y0 * (3.0 * x0 + x1) + 2.0 * y0 * y1 * sx + y1 * y1 * (x0 + 3.0 * x1));
+        m.m03 += a * sy * (y0 * y0 + y1 * y1);
+    }
+    if m.m00 == 0.0 {
+        return Moments::default();
+    }
+
+    let sign = m.m00.signum();
+    m.m00 *= sign / 2.0;
+    m.m10 *= sign / 6.0;
+    m.m01 *= sign / 6.0;
+    m.m20 *= sign / 12.0;
+    m.m11 *= sign / 24.0;
+    m.m02 *= sign / 12.0;
+    m.m30 *= sign / 20.0;
+    m.m21 *= sign / 60.0;
+    m.m12 *= sign / 60.0;
+    m.m03 *= sign / 20.0;
+
+    let (cx, cy) = (m.m10 / m.m00, m.m01 / m.m00);
+    m.mu20 = m.m20 - m.m10 * cx;
+    m.mu11 = m.m11 - m.m10 * cy;
+    m.mu02 = m.m02 - m.m01 * cy;
+    m.mu30 = m.m30 - cx * (3.0 * m.mu20 + cx * m.m10);
+    m.mu21 = m.m21 - cx * (2.0 * m.mu11 + cx * m.m01) - cy * m.mu20;
+    m.mu12 = m.m12 - cy * (2.0 * m.mu11 + cy * m.m10) - cx * m.mu02;
+    m.mu03 = m.m03 - cy * (3.0 * m.mu02 + cy * m.m01);
+
+    let s2 = 1.0 / (m.m00 * m.m00);
+    let s3 = s2 / m.m00.sqrt();
+    m.nu20 = m.mu20 * s2;
+    m.nu11 = m.mu11 * s2;
+    m.nu02 = m.mu02 * s2;
+    m.nu30 = m.mu30 * s3;
+    m.nu21 = m.mu21 * s3;
+    m.nu12 = m.mu12 * s3;
+    m.nu03 = m.mu03 * s3;
+    m
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnclosingCircle {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+}
+
+// Smallest circle containing every point, by Welzl's incremental construction over the
+// hull vertices
+pub fn min_enclosing_circle(points: &[Point]) -> EnclosingCircle {
+    let hull: Vec<(f64, f64)> = convex_hull(points)
+        .iter()
+        .map(|p| (p.x as f64, p.y as f64))
+        .collect();
+    let contains = |c: (f64, f64, f64), p: (f64, f64)| {
+        (p.0 - c.0).hypot(p.1 - c.1) <= c.2 * (1.0 + 1e-9) + 1e-9
+    };
+    let diameter = |a: (f64, f64), b: (f64, f64)| {
+        (
+            (a.0 + b.0) / 2.0,
+            (a.1 + b.1) / 2.0,
+            (a.0 - b.0).hypot(a.1 - b.1) / 2.0,
+        )
+    };
+
+    let mut circle = match hull.first() {
+        Some(&(x, y)) => (x, y, 0.0),
+        None => (0.0, 0.0, 0.0),
+    };
+    for i in 1..hull.len() {
+        if contains(circle, hull[i]) {
+            continue;
+        }
+        circle = (hull[i].0, hull[i].1, 0.0);
+        for j in 0..i {
+            if contains(circle, hull[j]) {
+                continue;
+            }
+            circle = diameter(hull[i], hull[j]);
+            for k in 0..j {
+                if !contains(circle, hull[k]) {
+                    circle = circumcircle(hull[i], hull[j], hull[k])
+                        .unwrap_or_else(|| diameter(hull[i], hull[j]));
+                }
+            }
+        }
+    }
+
+    EnclosingCircle {
+        x: circle.0 as f32,
+        y: circle.1 as f32,
+        radius: circle.2 as f32,
+    }
+}
+
+fn circumcircle(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Option<(f64, f64, f64)> {
+    let (bx, by) = (b.0 - a.0, b.1 - a.1);
+    let (cx, cy) = (c.0 - a.0, c.1 - a.1);
+    let d = 2.0 * (bx * cy - by * cx);
+    if d.abs() < 1e-12 {
+        return None;
+    }
+    let b2 = bx * bx + by * by;
+    let c2 = cx * cx + cy * cy;
+    let ux = (cy * b2 - by * c2) / d;
+    let uy = (bx * c2 - cx * b2) / d;
+    Some((a.0 + ux, a.1 + uy, ux.hypot(uy)))
+}
+
+// Ellipse with semi-axes `axes`, the first (major) one along `angle` radians
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ellipse {
+    pub center: (f32, f32),
+    pub axes: (f32, f32),
+    pub angle: f32,
+}
+
+// Least-squares fit of the conic Ax² + Bxy + Cy² + Dx + Ey = 1 in coordinates centered
+// and scaled around the points' mean. None with fewer than five points or when the best
+// conic is not an ellipse.
+pub fn fit_ellipse(points: &[Point]) -> Option<Ellipse> {
+    if points.len() < 5 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.x as f64).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.y as f64).sum::<f64>() / n;
+    let scale = (points
+        .iter()
+        .map(|p| (p.x as f64 - mean_x).powi(2) + (p.y as f64 - mean_y).powi(2))
+        .sum::<f64>()
+        / n)
+        .sqrt();
+    if scale == 0.0 {
+        return None;
+    }
+
+    // Normal equations of the 5-parameter least-squares problem
+    let mut ata = [[0.0f64; 5]; 5];
+    let mut atb = [0.0f64; 5];
+    for p in points {
+        let x = (p.x as f64 - mean_x) / scale;
+        let y = (p.y as f64 - mean_y) / scale;
+        let row = [x * x, x * y, y * y, x, y];
+        for i in 0..5 {
+            for j in 0..5 {
+                ata[i][j] += row[i] * row[j];
+            }
+            atb[i] += row[i];
+        }
+    }
+    let [a, b, c, d, e] = solve5(ata, atb)?;
+
+    // Center where the conic's gradient vanishes
+    let det = 4.0 * a * c - b * b;
+    if det <= 0.0 {
+        return None;
+    }
+    let x0 = (b * e - 2.0 * c * d) / det;
+    let y0 = (b * d - 2.0 * a * e) / det;
+    let f0 = a * x0 * x0 + b * x0 * y0 + c * y0 * y0 + d * x0 + e * y0 - 1.0;
+
+    // Rotate to remove the cross term
+    let theta = 0.5 * b.atan2(a - c);
+    let (sin, cos) = theta.sin_cos();
+    let a_rot = a * cos * cos + b * cos * sin + c * sin * sin;
+    let c_rot = a * sin * sin - b * cos * sin + c * cos * cos;
+    if -f0 / a_rot <= 0.0 || -f0 / c_rot <= 0.0 {
+        return None;
+    }
+    let (mut major, mut minor) = ((-f0 / a_rot).sqrt(), (-f0 / c_rot).sqrt());
+    let mut angle = theta;
+    if minor > major {
+        (major, minor) = (minor, major);
+        angle += PI / 2.0;
+    }
+
+    Some(Ellipse {
+        center: ((mean_x + x0 * scale) as f32, (mean_y + y0 * scale) as f32),
+        axes: ((major * scale) as f32, (minor * scale) as f32),
+        angle: angle as f32,
+    })
+}
+
+// Gaussian elimination with partial pivoting
+fn solve5(mut m: [[f64; 5]; 5], mut v: [f64; 5]) -> Option<[f64; 5]> {
+    for col in 0..5 {
+        let pivot = (col..5).max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))?;
+        if m[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        v.swap(col, pivot);
+        for row in col + 1..5 {
+            let pivot_row = m[col];
+            let f = m[row][col] / pivot_row[col];
+            for (value, pivot) in m[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= f * pivot;
+            }
+            v[row] -= f * v[col];
+        }
+    }
+    let mut x = [0.0; 5];
+    for row in (0..5).rev() {
+        let tail: f64 = (row + 1..5).map(|k| m[row][k] * x[k]).sum();
+        x[row] = (v[row] - tail) / m[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(
+        mask: &mut Array2<u8>,
+        rows: std::ops::RangeInclusive<usize>,
+        cols: std::ops::RangeInclusive<usize>,
+        value: u8,
+    ) {
+        for y in rows {
+            for x in cols.clone() {
+                mask[(y, x)] = value;
+            }
+        }
+    }
+
+    // A square ring with an island in its hole, a small square and a block in the
+    // bottom-left corner
+    fn nested() -> Array2<u8> {
+        let mut mask = Array2::zeros((30, 30));
+        fill(&mut mask, 2..=17, 2..=17, 255);
+        fill(&mut mask, 6..=13, 6..=13, 0);
+        fill(&mut mask, 9..=10, 9..=10, 255);
+        fill(&mut mask, 2..=5, 22..=25, 255);
+        fill(&mut mask, 25..=29, 0..=4, 255);
+        mask
+    }
+
+    fn summary(contours: &[Contour]) -> Vec<(ContourKind, f32, Option<usize>)> {
+        contours
+            .iter()
+            .map(|c| (c.kind, c.area, c.parent))
+            .collect()
+    }
+
+    #[test]
+    fn traces_nested_hierarchy_in_raster_order() {
+        let contours = find_contours(nested().view(), 0, 0.0);
+        assert_eq!(
+            summary(&contours),
+            [
+                (ContourKind::Outer, 225.0, None),
+                (ContourKind::Outer, 9.0, None),
+                (ContourKind::Hole, 79.0, Some(0)),
+                (ContourKind::Outer, 1.0, Some(2)),
+                (ContourKind::Outer, 16.0, None),
+            ]
+        );
+        assert_eq!(contours[0].points.len(), 60);
+        assert_eq!(contours[0].points[0], Point::new(2, 2));
+        // Hole borders cut the hole corners diagonally
+        assert_eq!(contours[2].points.len(), 32);
+        // The block touching the image edge is traced along the edge
+        assert_eq!(bounding_rect(&contours[4].points).x, 0);
+    }
+
+    #[test]
+    fn min_area_drops_small_contours() {
+        let contours = find_contours(nested().view(), 0, 5.0);
+        assert_eq!(
+            summary(&contours),
+            [
+                (ContourKind::Outer, 225.0, None),
+                (ContourKind::Outer, 9.0, None),
+                (ContourKind::Hole, 79.0, Some(0)),
+                (ContourKind::Outer, 16.0, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_pixels_are_contours() {
+        let mut mask = Array2::zeros((5, 5));
+        mask[(2, 2)] = 1;
+        let contours = find_contours(mask.view(), 0, -1.0);
+        assert_eq!(contours.len(), 1);
+        assert_eq!(contours[0].points, [Point::new(2, 2)]);
+        assert!(find_contours(mask.view(), 2, -1.0).is_empty());
+    }
+
+    fn points(coords: &[(i32, i32)]) -> Vec<Point> {
+        coords.iter().map(|&(x, y)| Point::new(x, y)).collect()
+    }
+
+    // Every pixel along the edges of a polygon, like a traced contour
+    fn outline(corners: &[(i32, i32)]) -> Vec<Point> {
+        let mut outline = Vec::new();
+        for (i, &(x0, y0)) in corners.iter().enumerate() {
+            let (x1, y1) = corners[(i + 1) % corners.len()];
+            let steps = (x1 - x0).abs().max((y1 - y0).abs());
+            for s in 0..steps {
+                let t = s as f32 / steps as f32;
+                outline.push(Point::new(
+                    x0 + ((x1 - x0) as f32 * t).round() as i32,
+                    y0 + ((y1 - y0) as f32 * t).round() as i32,
+                ));
+            }
+        }
+        outline
+    }
+
+    // `samples` points around an ellipse with semi-axes `a` along `angle` and `b`
+    fn ellipse(center: (f64, f64), a: f64, b: f64, angle: f64, samples: usize) -> Vec<Point> {
+        let (sin, cos) = angle.sin_cos();
+        (0..samples)
+            .map(|i| {
+                let t = 2.0 * PI * i as f64 / samples as f64;
+                let (u, v) = (a * t.cos(), b * t.sin());
+                Point::new(
+                    (center.0 + u * cos - v * sin).round() as i32,
+                    (center.1 + u * sin + v * cos).round() as i32,
+                )
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32, what: &str) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{what}: {actual} instead of {expected}"
+        );
+    }
+
+    // Sides as (short, long), and the angle of the sides modulo a quarter turn
+    fn rect_shape(rect: &RotatedRect) -> (f32, f32, f32) {
+        let angle = rect.angle.rem_euclid(std::f32::consts::FRAC_PI_2);
+        (
+            rect.width.min(rect.height),
+            rect.width.max(rect.height),
+            angle,
+        )
+    }
+
+    // 10x5 rectangle, axis-aligned and turned so its long side runs along (6, 8), with
+    // corners in order
+    const AXIS_RECT: [(i32, i32); 4] = [(2, 3), (12, 3), (12, 8), (2, 8)];
+    const TURNED_RECT: [(i32, i32); 4] = [(10, 0), (16, 8), (12, 11), (6, 3)];
+
+    #[test]
+    fn bounding_rect_is_cut_off_at_the_image_edge() {
+        let rect = bounding_rect(&outline(&AXIS_RECT));
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (2, 3, 11, 6));
+
+        let rect = bounding_rect(&points(&[(-3, -2), (4, -2), (4, 5), (-3, 5)]));
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (0, 0, 5, 6));
+
+        let rect = bounding_rect(&points(&[(-5, 2), (-1, 4)]));
+        assert_eq!((rect.x, rect.width, rect.height), (0, 0, 3));
+    }
+
+    #[test]
+    fn hull_and_solidity() {
+        assert_eq!(convex_hull(&outline(&AXIS_RECT)), points(&AXIS_RECT));
+        assert_eq!(solidity(&outline(&AXIS_RECT)), 1.0);
+
+        // An L dents a 10x10 square by 6x6; its hull only cuts the inner corner's triangle
+        let l_shape = [(0, 0), (10, 0), (10, 4), (4, 4), (4, 10), (0, 10)];
+        assert_eq!(
+            convex_hull(&outline(&l_shape)),
+            points(&[(0, 0), (10, 0), (10, 4), (4, 10), (0, 10)])
+        );
+        assert_close(solidity(&points(&l_shape)), 64.0 / 82.0, 1e-6, "solidity");
+    }
+
+    #[test]
+    fn min_area_rect_of_axis_aligned_and_turned_rectangles() {
+        let rect = min_area_rect(&outline(&AXIS_RECT));
+        assert_eq!(rect.center, (7.0, 5.5));
+        assert_eq!(rect_shape(&rect), (5.0, 10.0, 0.0));
+
+        // Rasterized slanted edges stick out of the rectangle, so only its corners
+        let rect = min_area_rect(&points(&TURNED_RECT));
+        let (short, long, angle) = rect_shape(&rect);
+        assert_close(rect.center.0, 11.0, 1e-4, "center x");
+        assert_close(rect.center.1, 5.5, 1e-4, "center y");
+        assert_close(short, 5.0, 1e-4, "short side");
+        assert_close(long, 10.0, 1e-4, "long side");
+        assert_close(angle, 8f32.atan2(6.0), 1e-4, "angle");
+    }
+
+    #[test]
+    fn moments_of_rectangles_and_a_disc() {
+        let m = moments(&points(&AXIS_RECT));
+        assert_eq!(m.m00, 50.0);
+        assert_eq!(m.centroid(), Some((7.0, 5.5)));
+        assert!((m.mu20 - 10f64.powi(3) * 5.0 / 12.0).abs() < 1e-9);
+        assert!((m.mu02 - 10.0 * 5f64.powi(3) / 12.0).abs() < 1e-9);
+        assert!(m.mu11.abs() < 1e-9);
+
+        // Hu invariants survive the rotation and translation, whichever way round the
+        // polygon runs
+        let mut turned: Vec<Point> = points(&TURNED_RECT);
+        turned.reverse();
+        let turned = moments(&turned);
+        assert_eq!(turned.m00, 50.0);
+        assert_eq!(turned.centroid(), Some((11.0, 5.5)));
+        for (axis, turned) in m.hu().iter().zip(turned.hu()) {
+            assert!((axis - turned).abs() < 1e-9, "{axis} vs {turned}");
+        }
+
+        // A disc's first invariant is 1/2π
+        let disc = moments(&ellipse((40.0, 40.0), 30.0, 30.0, 0.0, 360));
+        assert!((disc.hu()[0] - 1.0 / (2.0 * PI)).abs() < 1e-3);
+        assert!(disc.hu()[1].abs() < 1e-5);
+    }
+
+    #[test]
+    fn min_enclosing_circle_of_a_disc_and_a_rectangle() {
+        let circle = min_enclosing_circle(&ellipse((30.0, 25.0), 20.0, 20.0, 0.0, 72));
+        assert_close(circle.x, 30.0, 0.5, "x");
+        assert_close(circle.y, 25.0, 0.5, "y");
+        assert_close(circle.radius, 20.0, 0.5, "radius");
+
+        // A rectangle's circle passes through its corners
+        let circle = min_enclosing_circle(&outline(&AXIS_RECT));
+        assert_close(circle.x, 7.0, 1e-4, "x");
+        assert_close(circle.y, 5.5, 1e-4, "y");
+        assert_close(circle.radius, 125f32.sqrt() / 2.0, 1e-4, "radius");
+    }
+
+    #[test]
+    fn fits_disc_and_turned_ellipse() {
+        let fit = fit_ellipse(&ellipse((30.0, 25.0), 20.0, 20.0, 0.0, 72)).unwrap();
+        assert_close(fit.center.0, 30.0, 0.2, "x");
+        assert_close(fit.center.1, 25.0, 0.2, "y");
+        assert_close(fit.axes.0, 20.0, 0.3, "major");
+        assert_close(fit.axes.1, 20.0, 0.3, "minor");
+
+        let fit = fit_ellipse(&ellipse((50.0, 40.0), 30.0, 15.0, 0.5, 90)).unwrap();
+        assert_close(fit.center.0, 50.0, 0.2, "x");
+        assert_close(fit.center.1, 40.0, 0.2, "y");
+        assert_close(fit.axes.0, 30.0, 0.3, "major");
+        assert_close(fit.axes.1, 15.0, 0.3, "minor");
+        assert_close(
+            fit.angle.rem_euclid(std::f32::consts::PI),
+            0.5,
+            0.02,
+            "angle",
+        );
+
+        // Collinear points are not an ellipse
+        assert!(fit_ellipse(&outline(&[(0, 5), (20, 5)])).is_none());
+    }
+}
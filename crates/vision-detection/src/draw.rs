@@ -0,0 +1,72 @@
+use ndarray::Array2;
+
+use crate::contour::Point;
+
+// Bresenham line from `a` to `b`, skipping pixels outside `dst`
+pub fn draw_line(dst: &mut Array2<u8>, a: Point, b: Point, value: u8) {
+    let (height, width) = dst.dim();
+    let (dx, dy) = ((b.x - a.x).abs(), -(b.y - a.y).abs());
+    let (sx, sy) = ((b.x - a.x).signum(), (b.y - a.y).signum());
+    let mut error = dx + dy;
+    let (mut x, mut y) = (a.x, a.y);
+
+    loop {
+        if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            dst[(y as usize, x as usize)] = value;
+        }
+        if x == b.x && y == b.y {
+            break;
+        }
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += sx;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+}
+
+// Outline of the closed polygon through `points`
+pub fn draw_polygon(dst: &mut Array2<u8>, points: &[Point], value: u8) {
+    for (i, &a) in points.iter().enumerate() {
+        draw_line(dst, a, points[(i + 1) % points.len()], value);
+    }
+}
+
+// Midpoint circle outline around `center`, skipping pixels outside `dst`
+pub fn draw_circle(dst: &mut Array2<u8>, center: Point, radius: i32, value: u8) {
+    let (height, width) = dst.dim();
+    let mut plot = |x: i32, y: i32| {
+        let (x, y) = (center.x + x, center.y + y);
+        if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            dst[(y as usize, x as usize)] = value;
+        }
+    };
+
+    let (mut x, mut y) = (radius, 0);
+    let mut error = 1 - radius;
+    while x >= y {
+        for (px, py) in [
+            (x, y),
+            (y, x),
+            (-y, x),
+            (-x, y),
+            (-x, -y),
+            (-y, -x),
+            (y, -x),
+            (x, -y),
+        ] {
+            plot(px, py);
+        }
+        y += 1;
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
+        }
+    }
+}
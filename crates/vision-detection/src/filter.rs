@@ -0,0 +1,258 @@
+use ndarray::{Array2, ArrayView2, Axis};
+use rayon::prelude::*;
+
+use crate::resize::Pixel;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlurOp {
+    // Separable Gaussian, kernel truncated at 3 sigma
+    Gaussian { sigma: f32 },
+    // Mean over a (2 * radius + 1) square window via an integral image
+    Box { radius: usize },
+    // Per-channel median over a (2 * radius + 1) square window, meant for small radii
+    Median { radius: usize },
+}
+
+// Blur ops applied in order to every frame. Gaussian kernels are built once and the
+// intermediate buffers are reused while the frame size stays the same.
+pub struct BlurChain<T> {
+    ops: Vec<BlurOp>,
+    // One per op, empty for non-Gaussian ops
+    kernels: Vec<Vec<u32>>,
+    scratch: Array2<T>,
+    horizontal: Array2<T>,
+}
+
+impl<T: Pixel + Default> BlurChain<T> {
+    pub fn new(ops: Vec<BlurOp>) -> Self {
+        let kernels = ops
+            .iter()
+            .map(|op| match *op {
+                BlurOp::Gaussian { sigma } if sigma > 0.0 => gaussian_kernel(sigma),
+                _ => Vec::new(),
+            })
+            .collect();
+        Self {
+            ops,
+            kernels,
+            scratch: Array2::from_elem((0, 0), T::default()),
+            horizontal: Array2::from_elem((0, 0), T::default()),
+        }
+    }
+
+    pub fn apply(&mut self, frame: &mut Array2<T>) {
+        if self.scratch.dim() != frame.dim() {
+            self.scratch = Array2::from_elem(frame.dim(), T::default());
+        }
+
+        for (op, kernel) in self.ops.iter().zip(&self.kernels) {
+            match *op {
+                BlurOp::Gaussian { .. } if kernel.is_empty() => continue,
+                BlurOp::Gaussian { .. } => gaussian_blur_with(
+                    frame.view(),
+                    &mut self.scratch,
+                    kernel,
+                    &mut self.horizontal,
+                ),
+                BlurOp::Box { radius } => box_blur(frame.view(), &mut self.scratch, radius),
+                BlurOp::Median { radius } => median_blur(frame.view(), &mut self.scratch, radius),
+            }
+            std::mem::swap(frame, &mut self.scratch);
+        }
+    }
+}
+
+// Fixed-point weights sum to 1 << GAUSS_BITS
+const GAUSS_BITS: u32 = 14;
+
+fn gaussian_kernel(sigma: f32) -> Vec<u32> {
+    let radius = (3.0 * sigma).ceil().max(0.0) as i32;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights
+        .iter()
+        .map(|w| (w / total * (1 << GAUSS_BITS) as f32).round() as u32)
+        .collect()
+}
+
+pub fn gaussian_blur<T: Pixel + Default>(src: ArrayView2<T>, dst: &mut Array2<T>, sigma: f32) {
+    if sigma <= 0.0 {
+        dst.assign(&src);
+        return;
+    }
+
+    let mut horizontal = Array2::from_elem((0, 0), T::default());
+    gaussian_blur_with(src, dst, &gaussian_kernel(sigma), &mut horizontal);
+}
+
+// Gaussian blur with a prebuilt kernel, using `horizontal` for the first pass
+fn gaussian_blur_with<T: Pixel + Default>(
+    src: ArrayView2<T>,
+    dst: &mut Array2<T>,
+    kernel: &[u32],
+    horizontal: &mut Array2<T>,
+) {
+    let radius = (kernel.len() / 2) as isize;
+    let (height, width) = src.dim();
+    let round = 1 << (GAUSS_BITS - 1);
+    let weighted = |sum: [u32; 3]| T::from_channels(sum.map(|s| (s + round) >> GAUSS_BITS));
+
+    // Horizontal pass, replicating edge pixels
+    if horizontal.dim() != src.dim() {
+        *horizontal = Array2::from_elem((height, width), T::default());
+    }
+    horizontal
+        .axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .zip(src.axis_iter(Axis(0)))
+        .for_each(|(mut out_row, src_row)| {
+            for (x, out) in out_row.iter_mut().enumerate() {
+                let mut sum = [0u32; 3];
+                for (k, &w) in kernel.iter().enumerate() {
+                    let sx = (x as isize + k as isize - radius).clamp(0, width as isize - 1);
+                    for (s, c) in sum.iter_mut().zip(src_row[sx as usize].channels()) {
+                        *s += c * w;
+                    }
+                }
+                *out = weighted(sum);
+            }
+        });
+
+    // Vertical pass, accumulating whole rows to stay cache friendly
+    dst.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each_init(
+            || vec![[0u32; 3]; width],
+            |sums, (y, mut out_row)| {
+                sums.fill([0; 3]);
+                for (k, &w) in kernel.iter().enumerate() {
+                    let sy = (y as isize + k as isize - radius).clamp(0, height as isize - 1);
+                    for (sum, &pixel) in sums.iter_mut().zip(horizontal.row(sy as usize)) {
+                        for (s, c) in sum.iter_mut().zip(pixel.channels()) {
+                            *s += c * w;
+                        }
+                    }
+                }
+                for (out, &sum) in out_row.iter_mut().zip(sums.iter()) {
+                    *out = weighted(sum);
+                }
+            },
+        );
+}
+
+pub fn box_blur<T: Pixel>(src: ArrayView2<T>, dst: &mut Array2<T>, radius: usize) {
+    let (height, width) = src.dim();
+
+    // integral[(y, x)] = sum of src over rows < y and columns < x
+    let mut integral = Array2::from_elem((height + 1, width + 1), [0u32; 3]);
+    for y in 0..height {
+        let mut row_sum = [0u32; 3];
+        for x in 0..width {
+            for (s, c) in row_sum.iter_mut().zip(src[(y, x)].channels()) {
+                *s += c;
+            }
+            let above = integral[(y, x + 1)];
+            integral[(y + 1, x + 1)] = [0, 1, 2].map(|c| above[c] + row_sum[c]);
+        }
+    }
+
+    dst.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(y, mut out_row)| {
+            let y0 = y.saturating_sub(radius);
+            let y1 = (y + radius + 1).min(height);
+            for (x, out) in out_row.iter_mut().enumerate() {
+                let x0 = x.saturating_sub(radius);
+                let x1 = (x + radius + 1).min(width);
+                let count = ((y1 - y0) * (x1 - x0)) as u32;
+
+                let (a, b) = (integral[(y1, x1)], integral[(y0, x1)]);
+                let (c, d) = (integral[(y1, x0)], integral[(y0, x0)]);
+                let sum = [0, 1, 2].map(|i| a[i] + d[i] - b[i] - c[i]);
+                *out = T::from_channels(sum.map(|s| (s + count / 2) / count));
+            }
+        });
+}
+
+pub fn median_blur<T: Pixel>(src: ArrayView2<T>, dst: &mut Array2<T>, radius: usize) {
+    let (height, width) = src.dim();
+
+    dst.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(y, mut out_row)| {
+            let y0 = y.saturating_sub(radius);
+            let y1 = (y + radius + 1).min(height);
+            let mut window: [Vec<u32>; 3] = Default::default();
+
+            for (x, out) in out_row.iter_mut().enumerate() {
+                let x0 = x.saturating_sub(radius);
+                let x1 = (x + radius + 1).min(width);
+
+                window.iter_mut().for_each(Vec::clear);
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        for (values, c) in window.iter_mut().zip(src[(sy, sx)].channels()) {
+                            values.push(c);
+                        }
+                    }
+                }
+
+                let mid = window[0].len() / 2;
+                *out = T::from_channels(
+                    window
+                        .each_mut()
+                        .map(|values| *values.select_nth_unstable(mid).1),
+                );
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> Array2<[u8; 3]> {
+        Array2::from_shape_fn((40, 60), |(y, x)| [(x * 4) as u8, (y * 6) as u8, 100])
+    }
+
+    #[test]
+    fn chain_reuses_kernel_across_frames() {
+        let mut chain = BlurChain::new(vec![BlurOp::Gaussian { sigma: 1.5 }]);
+        let mut expected = Array2::from_elem((40, 60), [0; 3]);
+        gaussian_blur(ramp().view(), &mut expected, 1.5);
+
+        for _ in 0..2 {
+            let mut frame = ramp();
+            chain.apply(&mut frame);
+            assert_eq!(frame, expected);
+        }
+    }
+
+    #[test]
+    fn blurs_keep_flat_images_flat() {
+        let flat = Array2::from_elem((20, 30), [7u8, 130, 255]);
+        let mut chain = BlurChain::new(vec![
+            BlurOp::Gaussian { sigma: 2.0 },
+            BlurOp::Box { radius: 3 },
+            BlurOp::Median { radius: 1 },
+        ]);
+        let mut frame = flat.clone();
+        chain.apply(&mut frame);
+        assert_eq!(frame, flat);
+    }
+
+    #[test]
+    fn median_removes_isolated_pixels() {
+        let mut frame = Array2::from_elem((9, 9), 50u8);
+        frame[(4, 4)] = 255;
+        frame[(0, 0)] = 0;
+        let mut out = Array2::zeros((9, 9));
+        median_blur(frame.view(), &mut out, 1);
+        assert!(out.iter().all(|&v| v == 50));
+    }
+}
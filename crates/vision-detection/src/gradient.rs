@@ -0,0 +1,22 @@
+use ndarray::ArrayView2;
+
+// Separable 5x5 Sobel: binomial smoothing across, central derivative along
+const SMOOTH: [i32; 5] = [1, 4, 6, 4, 1];
+const DERIVATIVE: [i32; 5] = [-1, -2, 0, 2, 1];
+
+// Horizontal and vertical 5x5 Sobel responses at (y, x), replicating edge pixels. The
+// wider kernel gives usable orientations even on binary masks.
+pub fn sobel_at(src: ArrayView2<u8>, y: usize, x: usize) -> (f32, f32) {
+    let (height, width) = src.dim();
+    let (mut gx, mut gy) = (0i32, 0i32);
+    for (j, (&smooth_y, &deriv_y)) in SMOOTH.iter().zip(&DERIVATIVE).enumerate() {
+        let sy = (y + j).saturating_sub(2).min(height - 1);
+        for (i, (&smooth_x, &deriv_x)) in SMOOTH.iter().zip(&DERIVATIVE).enumerate() {
+            let sx = (x + i).saturating_sub(2).min(width - 1);
+            let value = src[(sy, sx)] as i32;
+            gx += smooth_y * deriv_x * value;
+            gy += deriv_y * smooth_x * value;
+        }
+    }
+    (gx as f32, gy as f32)
+}
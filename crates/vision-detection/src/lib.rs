@@ -1,4 +1,19 @@
 pub mod ball;
 pub mod circle;
+pub mod cluster;
 pub mod color;
+pub mod components;
 pub mod contour;
+pub mod draw;
+pub mod filter;
+pub mod gradient;
+pub mod morphology;
+pub mod pipeline;
+pub mod ransac;
+pub mod refine;
+pub mod resize;
+mod rng;
+pub mod synthetic;
+pub mod undistort;
+#[cfg(feature = "yolo")]
+pub mod yolo;
@@ -0,0 +1,433 @@
+use ndarray::{Array2, ArrayView2, Axis, Zip};
+use rayon::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernelShape {
+    Rect,
+    Cross,
+    Ellipse,
+}
+
+// Structuring element spanning (2 * radius + 1) pixels in each direction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Kernel {
+    pub shape: KernelShape,
+    pub radius: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MorphOp {
+    Erode(Kernel),
+    Dilate(Kernel),
+    // Erode then dilate: removes speckles smaller than the kernel
+    Open(Kernel),
+    // Dilate then erode: closes gaps and small holes
+    Close(Kernel),
+    // Fills background regions not connected to the image border
+    FillHoles,
+}
+
+// Buffers reused between frames while the mask size stays the same
+#[derive(Default)]
+pub struct MorphScratch {
+    // Result of the previous op, swapped with the mask
+    mask: Array2<u8>,
+    buffers: MorphBuffers,
+}
+
+#[derive(Default)]
+struct MorphBuffers {
+    // Horizontal pass of rect kernels, vertical pass of cross kernels
+    line: Array2<u8>,
+    // Vertical passes run as row passes over the transposed mask
+    transposed: Array2<u8>,
+    // Running foreground counts of the rows and of the columns
+    row_prefix: Array2<u32>,
+    column_prefix: Array2<u32>,
+    // Background reached from the border, and the flood fill's stack
+    outside: Array2<bool>,
+    stack: Vec<(usize, usize)>,
+}
+
+// Reallocates `buffer` only when the size changes; callers overwrite what they read
+fn sized<T: Clone + Default>(buffer: &mut Array2<T>, dim: (usize, usize)) -> &mut Array2<T> {
+    if buffer.dim() != dim {
+        *buffer = Array2::from_elem(dim, T::default());
+    }
+    buffer
+}
+
+// Applies `ops` in order to a binary mask (non-zero is foreground), keeping intermediate
+// buffers in `scratch`. The result is always 0/255.
+pub fn apply_morphology(mask: &mut Array2<u8>, ops: &[MorphOp], scratch: &mut MorphScratch) {
+    let MorphScratch {
+        mask: other,
+        buffers,
+    } = scratch;
+    sized(other, mask.dim());
+
+    for op in ops {
+        match *op {
+            MorphOp::Erode(kernel) => {
+                morph(mask.view(), other, &kernel, true, buffers);
+                std::mem::swap(mask, other);
+            }
+            MorphOp::Dilate(kernel) => {
+                morph(mask.view(), other, &kernel, false, buffers);
+                std::mem::swap(mask, other);
+            }
+            MorphOp::Open(kernel) => {
+                morph(mask.view(), other, &kernel, true, buffers);
+                morph(other.view(), mask, &kernel, false, buffers);
+            }
+            MorphOp::Close(kernel) => {
+                morph(mask.view(), other, &kernel, false, buffers);
+                morph(other.view(), mask, &kernel, true, buffers);
+            }
+            MorphOp::FillHoles => fill_holes_with(mask, buffers),
+        }
+    }
+}
+
+pub fn erode(src: ArrayView2<u8>, dst: &mut Array2<u8>, kernel: &Kernel) {
+    morph(src, dst, kernel, true, &mut MorphBuffers::default());
+}
+
+pub fn dilate(src: ArrayView2<u8>, dst: &mut Array2<u8>, kernel: &Kernel) {
+    morph(src, dst, kernel, false, &mut MorphBuffers::default());
+}
+
+// Pixels outside the image are ignored, so blobs touching the border do not erode from it
+fn morph(
+    src: ArrayView2<u8>,
+    dst: &mut Array2<u8>,
+    kernel: &Kernel,
+    erode: bool,
+    buffers: &mut MorphBuffers,
+) {
+    let r = kernel.radius;
+    if r == 0 {
+        Zip::from(&mut *dst)
+            .and(src)
+            .par_for_each(|d, &s| *d = if s != 0 { 255 } else { 0 });
+        return;
+    }
+
+    let mut line = std::mem::take(&mut buffers.line);
+    sized(&mut line, src.dim());
+    match kernel.shape {
+        // Separable: a horizontal then a vertical line pass, each O(1) per pixel
+        KernelShape::Rect => {
+            line_pass(src, &mut line, r, Axis(1), erode, buffers);
+            line_pass(line.view(), dst, r, Axis(0), erode, buffers);
+        }
+        // Union of a horizontal and a vertical line
+        KernelShape::Cross => {
+            line_pass(src, dst, r, Axis(1), erode, buffers);
+            line_pass(src, &mut line, r, Axis(0), erode, buffers);
+            Zip::from(&mut *dst).and(&line).par_for_each(|d, &v| {
+                *d = if erode { *d & v } else { *d | v };
+            });
+        }
+        KernelShape::Ellipse => {
+            let half_widths: Vec<(isize, usize)> = (-(r as isize)..=r as isize)
+                .map(|dy| {
+                    let t = dy as f32 / r as f32;
+                    (dy, (r as f32 * (1.0 - t * t).sqrt()).round() as usize)
+                })
+                .collect();
+            row_runs(src, dst, &half_widths, erode, &mut buffers.row_prefix);
+        }
+    }
+    buffers.line = line;
+}
+
+// Running foreground counts per row: prefix[(y, x)] = foreground pixels in row y before x
+fn row_prefix(src: ArrayView2<u8>, prefix: &mut Array2<u32>) {
+    let (height, width) = src.dim();
+    Zip::from(sized(prefix, (height, width + 1)).rows_mut())
+        .and(src.rows())
+        .par_for_each(|mut prefix_row, src_row| {
+            let mut count = 0;
+            prefix_row[0] = 0;
+            for (x, &v) in src_row.iter().enumerate() {
+                count += (v != 0) as u32;
+                prefix_row[x + 1] = count;
+            }
+        });
+}
+
+// Min/max filter along a single axis with a window of 2 * radius + 1
+fn line_pass(
+    src: ArrayView2<u8>,
+    dst: &mut Array2<u8>,
+    radius: usize,
+    axis: Axis,
+    erode: bool,
+    buffers: &mut MorphBuffers,
+) {
+    match axis {
+        Axis(1) => row_runs(src, dst, &[(0, radius)], erode, &mut buffers.row_prefix),
+        _ => {
+            // Run the row pass on the transposed views
+            let transposed = sized(&mut buffers.transposed, (src.ncols(), src.nrows()));
+            row_runs(
+                src.t(),
+                transposed,
+                &[(0, radius)],
+                erode,
+                &mut buffers.column_prefix,
+            );
+            dst.assign(&transposed.t());
+        }
+    }
+}
+
+// Structuring element given as horizontal runs: (row offset, half width). A pixel erodes
+// to foreground if every in-bounds run is fully foreground, and dilates to foreground if
+// any run contains foreground. Each run is one prefix-sum lookup.
+fn row_runs(
+    src: ArrayView2<u8>,
+    dst: &mut Array2<u8>,
+    runs: &[(isize, usize)],
+    erode: bool,
+    prefix: &mut Array2<u32>,
+) {
+    let (height, width) = src.dim();
+    row_prefix(src, prefix);
+    let prefix = &*prefix;
+    dst.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(y, mut row)| {
+            for (x, out) in row.iter_mut().enumerate() {
+                let mut result = erode;
+                for &(dy, half_width) in runs {
+                    let sy = y as isize + dy;
+                    if sy < 0 || sy >= height as isize {
+                        continue;
+                    }
+                    let x0 = x.saturating_sub(half_width);
+                    let x1 = (x + half_width + 1).min(width);
+                    let count = prefix[(sy as usize, x1)] - prefix[(sy as usize, x0)];
+
+                    if erode && count < (x1 - x0) as u32 {
+                        result = false;
+                        break;
+                    }
+                    if !erode && count > 0 {
+                        result = true;
+                        break;
+                    }
+                }
+                *out = if result { 255 } else { 0 };
+            }
+        });
+}
+
+// Sets every background pixel that is not 4-connected to the border to foreground
+pub fn fill_holes(mask: &mut Array2<u8>) {
+    fill_holes_with(mask, &mut MorphBuffers::default());
+}
+
+fn fill_holes_with(mask: &mut Array2<u8>, buffers: &mut MorphBuffers) {
+    let (height, width) = mask.dim();
+    if height == 0 || width == 0 {
+        return;
+    }
+    let outside = sized(&mut buffers.outside, (height, width));
+    outside.fill(false);
+    let stack = &mut buffers.stack;
+    stack.clear();
+
+    let mut seed = |y: usize, x: usize, outside: &mut Array2<bool>| {
+        if mask[(y, x)] == 0 && !outside[(y, x)] {
+            outside[(y, x)] = true;
+            stack.push((y, x));
+        }
+    };
+    for x in 0..width {
+        seed(0, x, outside);
+        seed(height - 1, x, outside);
+    }
+    for y in 0..height {
+        seed(y, 0, outside);
+        seed(y, width - 1, outside);
+    }
+
+    while let Some((y, x)) = stack.pop() {
+        let neighbours = [
+            (y.wrapping_sub(1), x),
+            (y + 1, x),
+            (y, x.wrapping_sub(1)),
+            (y, x + 1),
+        ];
+        for (ny, nx) in neighbours {
+            if ny < height && nx < width && mask[(ny, nx)] == 0 && !outside[(ny, nx)] {
+                outside[(ny, nx)] = true;
+                stack.push((ny, nx));
+            }
+        }
+    }
+
+    Zip::from(mask)
+        .and(&*outside)
+        .par_for_each(|m, &o| *m = if o { 0 } else { 255 });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const SHAPES: [KernelShape; 3] = [KernelShape::Rect, KernelShape::Cross, KernelShape::Ellipse];
+
+    fn in_kernel(kernel: &Kernel, dy: isize, dx: isize) -> bool {
+        let r = kernel.radius as isize;
+        match kernel.shape {
+            KernelShape::Rect => true,
+            KernelShape::Cross => dy == 0 || dx == 0,
+            KernelShape::Ellipse => {
+                let t = dy as f32 / r as f32;
+                dx.unsigned_abs() <= (r as f32 * (1.0 - t * t).sqrt()).round() as usize
+            }
+        }
+    }
+
+    // Per-pixel min (erode) or max (dilate) over the in-bounds kernel pixels
+    fn reference(src: &Array2<u8>, kernel: &Kernel, erode: bool) -> Array2<u8> {
+        let (height, width) = src.dim();
+        let r = kernel.radius as isize;
+        Array2::from_shape_fn((height, width), |(y, x)| {
+            let mut result = erode;
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    let (sy, sx) = (y as isize + dy, x as isize + dx);
+                    if !in_kernel(kernel, dy, dx)
+                        || sy < 0
+                        || sx < 0
+                        || sy >= height as isize
+                        || sx >= width as isize
+                    {
+                        continue;
+                    }
+                    let foreground = src[(sy as usize, sx as usize)] != 0;
+                    if erode {
+                        result &= foreground;
+                    } else {
+                        result |= foreground;
+                    }
+                }
+            }
+            if result {
+                255
+            } else {
+                0
+            }
+        })
+    }
+
+    // Blobs and speckles, with foreground running off every border
+    fn random_mask(seed: u64, height: usize, width: usize) -> Array2<u8> {
+        let mut rng = Rng::new(seed);
+        let mut mask = Array2::zeros((height, width));
+        for _ in 0..12 {
+            let (cy, cx, r) = (rng.below(height), rng.below(width), 1 + rng.below(6));
+            for y in cy.saturating_sub(r)..(cy + r + 1).min(height) {
+                for x in cx.saturating_sub(r)..(cx + r + 1).min(width) {
+                    mask[(y, x)] = 255;
+                }
+            }
+        }
+        for _ in 0..40 {
+            let (y, x) = (rng.below(height), rng.below(width));
+            mask[(y, x)] = if mask[(y, x)] == 0 { 1 } else { 0 };
+        }
+        for x in 0..width {
+            mask[(0, x)] = 255;
+        }
+        mask[(height - 1, 0)] = 255;
+        mask
+    }
+
+    #[test]
+    fn erode_and_dilate_match_per_pixel_reference() {
+        for seed in 0..4 {
+            let mask = random_mask(seed, 23 + seed as usize, 31);
+            let mut out = Array2::zeros(mask.dim());
+            for shape in SHAPES {
+                for radius in 0..5 {
+                    let kernel = Kernel { shape, radius };
+                    erode(mask.view(), &mut out, &kernel);
+                    assert_eq!(out, reference(&mask, &kernel, true), "erode {kernel:?}");
+                    dilate(mask.view(), &mut out, &kernel);
+                    assert_eq!(out, reference(&mask, &kernel, false), "dilate {kernel:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chained_ops_match_reference_and_reuse_scratch() {
+        let mut scratch = MorphScratch::default();
+        // The same scratch through two mask sizes and every op
+        for (seed, dim) in [(7, (40, 33)), (8, (17, 50)), (9, (40, 33))] {
+            let mask = random_mask(seed, dim.0, dim.1);
+            for shape in SHAPES {
+                let kernel = Kernel { shape, radius: 2 };
+                let eroded = reference(&mask, &kernel, true);
+                let dilated = reference(&mask, &kernel, false);
+                let cases = [
+                    (MorphOp::Erode(kernel), eroded.clone()),
+                    (MorphOp::Dilate(kernel), dilated.clone()),
+                    (MorphOp::Open(kernel), reference(&eroded, &kernel, false)),
+                    (MorphOp::Close(kernel), reference(&dilated, &kernel, true)),
+                ];
+                for (op, expected) in cases {
+                    let mut result = mask.clone();
+                    apply_morphology(&mut result, &[op], &mut scratch);
+                    assert_eq!(result, expected, "{op:?} on {dim:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fill_holes_fills_only_enclosed_background() {
+        let mut mask = Array2::zeros((9, 12));
+        // A ring with a hole, and a cup open to the right edge
+        for i in 1..6 {
+            mask[(1, i)] = 1;
+            mask[(5, i)] = 1;
+            mask[(i, 1)] = 1;
+            mask[(i, 5)] = 1;
+        }
+        for x in 8..12 {
+            mask[(6, x)] = 1;
+            mask[(8, x)] = 1;
+        }
+        mask[(7, 8)] = 1;
+
+        let mut expected = Array2::zeros(mask.dim());
+        for y in 1..=5 {
+            for x in 1..=5 {
+                expected[(y, x)] = 255;
+            }
+        }
+        for x in 8..12 {
+            expected[(6, x)] = 255;
+            expected[(8, x)] = 255;
+        }
+        expected[(7, 8)] = 255;
+
+        let mut scratch = MorphScratch::default();
+        let mut filled = mask.clone();
+        apply_morphology(&mut filled, &[MorphOp::FillHoles], &mut scratch);
+        assert_eq!(filled, expected);
+        // Scratch left over from the last fill does not leak into the next
+        apply_morphology(&mut filled, &[MorphOp::FillHoles], &mut scratch);
+        assert_eq!(filled, expected);
+        fill_holes(&mut mask);
+        assert_eq!(mask, expected);
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use ndarray::Array2;
+
+use crate::ball::{HoughAccumulator, HoughVoting};
+use crate::circle::Circle;
+use crate::cluster::Pile;
+use crate::contour::Point;
+use crate::filter::{BlurChain, BlurOp};
+use crate::morphology::{apply_morphology, MorphOp, MorphScratch};
+use crate::ransac::{ransac_circles, RansacParams};
+use crate::refine::{refine_circles, RefinedCircle};
+
+// The kinds of data stages hand to each other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    // Frame at processing resolution, filled before the pipeline runs
+    Image,
+    // Binary mask of candidate ball pixels
+    Mask,
+    // Binary edge map for circle detection
+    Edges,
+    // Detected circles, strongest first
+    Circles,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Slot::Image => "image",
+            Slot::Mask => "mask",
+            Slot::Edges => "edges",
+            Slot::Circles => "circles",
+        };
+        f.write_str(name)
+    }
+}
+
+// Working set for one frame. Stages read slots filled upstream and overwrite their own.
+pub struct StageData {
+    pub image: Array2<[u8; 3]>,
+    pub mask: Array2<u8>,
+    pub edges: Array2<u8>,
+    pub circles: Vec<RefinedCircle>,
+    // Simplified contour outlines, from stages that trace contours
+    pub polygons: Vec<Vec<Point>>,
+    // Blobs of several balls, from stages that split clusters
+    pub piles: Vec<Pile>,
+}
+
+impl StageData {
+    pub fn new(height: usize, width: usize) -> Self {
+        Self {
+            image: Array2::from_elem((height, width), [0u8; 3]),
+            mask: Array2::zeros((height, width)),
+            edges: Array2::zeros((height, width)),
+            circles: Vec::new(),
+            polygons: Vec::new(),
+            piles: Vec::new(),
+        }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        self.image.dim()
+    }
+}
+
+// One step of a pipeline. It reads its `inputs` from the stage data and writes `output`,
+// keeping whatever scratch buffers it needs between frames.
+pub trait Stage: Send {
+    // Label for latency reports and logs
+    fn name(&self) -> &'static str;
+    fn inputs(&self) -> &'static [Slot];
+    fn output(&self) -> Slot;
+    fn run(&mut self, data: &mut StageData);
+}
+
+// A circle finder, run as a pipeline stage through `Detect`
+pub trait Detector: Send {
+    fn name(&self) -> &'static str;
+    fn inputs(&self) -> &'static [Slot];
+    fn detect(&mut self, data: &StageData) -> Vec<Circle>;
+}
+
+pub struct Detect<D>(pub D);
+
+impl<D: Detector> Stage for Detect<D> {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        self.0.inputs()
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Circles
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        let circles = self.0.detect(data);
+        data.circles = circles.iter().map(RefinedCircle::from).collect();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineError {
+    // A stage reads a slot no earlier stage writes. `index` is the stage's position in the
+    // chain.
+    MissingInput {
+        index: usize,
+        stage: &'static str,
+        slot: Slot,
+    },
+    // A stage this build was compiled without; `feature` is the cargo feature that adds it
+    MissingFeature {
+        index: usize,
+        stage: &'static str,
+        feature: &'static str,
+    },
+    // A stage's model could not be loaded
+    Model {
+        index: usize,
+        stage: &'static str,
+        error: String,
+    },
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::MissingInput { index, stage, slot } => write!(
+                f,
+                "stage {index} ({stage}) needs {slot}, which no earlier stage produces"
+            ),
+            PipelineError::MissingFeature {
+                index,
+                stage,
+                feature,
+            } => write!(
+                f,
+                "stage {index} ({stage}) needs the {feature} feature, which this build leaves out"
+            ),
+            PipelineError::Model {
+                index,
+                stage,
+                error,
+            } => write!(
+                f,
+                "stage {index} ({stage}) could not load its model: {error}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+// Stages run in order on every frame
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    // Checks that every stage's inputs are produced before it runs. Only the image is
+    // available up front.
+    pub fn new(stages: Vec<Box<dyn Stage>>) -> Result<Self, PipelineError> {
+        let mut available = vec![Slot::Image];
+        for (index, stage) in stages.iter().enumerate() {
+            if let Some(&slot) = stage.inputs().iter().find(|s| !available.contains(s)) {
+                return Err(PipelineError::MissingInput {
+                    index,
+                    stage: stage.name(),
+                    slot,
+                });
+            }
+            available.push(stage.output());
+        }
+        Ok(Self { stages })
+    }
+
+    pub fn stages(&self) -> impl Iterator<Item = &dyn Stage> {
+        self.stages.iter().map(|stage| stage.as_ref())
+    }
+
+    // Runs every stage on `data`, whose image must already hold the frame, calling
+    // `after_stage` with the stage data as each one finishes
+    pub fn run(
+        &mut self,
+        data: &mut StageData,
+        mut after_stage: impl FnMut(&dyn Stage, &StageData),
+    ) {
+        data.circles.clear();
+        data.polygons.clear();
+        data.piles.clear();
+        for stage in &mut self.stages {
+            stage.run(data);
+            after_stage(stage.as_ref(), data);
+        }
+    }
+}
+
+// Smoothing before color masking
+pub struct Blur(BlurChain<[u8; 3]>);
+
+impl Blur {
+    pub fn new(ops: Vec<BlurOp>) -> Self {
+        Self(BlurChain::new(ops))
+    }
+}
+
+impl Stage for Blur {
+    fn name(&self) -> &'static str {
+        "denoise"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Image]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Image
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        self.0.apply(&mut data.image);
+    }
+}
+
+// Mask cleanup before edge extraction
+pub struct Morphology {
+    ops: Vec<MorphOp>,
+    scratch: MorphScratch,
+}
+
+impl Morphology {
+    pub fn new(ops: Vec<MorphOp>) -> Self {
+        Self {
+            ops,
+            scratch: MorphScratch::default(),
+        }
+    }
+}
+
+impl Stage for Morphology {
+    fn name(&self) -> &'static str {
+        "morphology"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Mask]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Mask
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        apply_morphology(&mut data.mask, &self.ops, &mut self.scratch);
+    }
+}
+
+// Hough transform over the whole edge map, keeping its accumulator between frames
+pub struct Hough {
+    accumulator: HoughAccumulator,
+    circle_cache: Arc<HashMap<u32, Vec<(i32, i32)>>>,
+    voting: HoughVoting,
+    min_fraction: f32,
+}
+
+impl Hough {
+    pub fn new(
+        circle_cache: Arc<HashMap<u32, Vec<(i32, i32)>>>,
+        voting: HoughVoting,
+        min_fraction: f32,
+    ) -> Self {
+        Self {
+            accumulator: HoughAccumulator::new(),
+            circle_cache,
+            voting,
+            min_fraction,
+        }
+    }
+}
+
+impl Detector for Hough {
+    fn name(&self) -> &'static str {
+        "circles"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        match self.voting {
+            HoughVoting::Full => &[Slot::Edges],
+            HoughVoting::Gradient { .. } => &[Slot::Edges, Slot::Mask],
+        }
+    }
+
+    fn detect(&mut self, data: &StageData) -> Vec<Circle> {
+        match self.voting {
+            HoughVoting::Full => self.accumulator.hough_transform(
+                data.edges.view(),
+                &self.circle_cache,
+                self.min_fraction,
+            ),
+            HoughVoting::Gradient { spread } => self.accumulator.hough_transform_gradient(
+                data.edges.view(),
+                data.mask.view(),
+                &self.circle_cache,
+                self.min_fraction,
+                spread,
+            ),
+        }
+    }
+}
+
+pub struct Ransac(pub RansacParams);
+
+impl Detector for Ransac {
+    fn name(&self) -> &'static str {
+        "circles"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Edges]
+    }
+
+    fn detect(&mut self, data: &StageData) -> Vec<Circle> {
+        ransac_circles(data.edges.view(), &self.0)
+    }
+}
+
+// Subpixel refit of the detected circles to nearby edge pixels
+pub struct Refine {
+    band: f32,
+}
+
+impl Refine {
+    pub fn new(band: f32) -> Self {
+        Self { band }
+    }
+}
+
+impl Stage for Refine {
+    fn name(&self) -> &'static str {
+        "refine"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Edges, Slot::Circles]
+    }
+
+    fn output(&self) -> Slot {
+        Slot::Circles
+    }
+
+    fn run(&mut self, data: &mut StageData) {
+        data.circles = refine_circles(data.edges.view(), &data.circles, self.band);
+    }
+}
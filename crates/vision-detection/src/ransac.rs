@@ -0,0 +1,270 @@
+use ndarray::ArrayView2;
+use rayon::prelude::*;
+
+use crate::ball::suppress_overlaps;
+use crate::circle::{disc_border, Circle};
+use crate::contour::{find_contours, Point};
+use crate::refine::fit_circle;
+use crate::rng::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RansacParams {
+    // Random three-point candidates tried for each circle
+    pub iterations: u32,
+    // Contour points within this distance of a candidate circle are its inliers
+    pub band: f32,
+    pub min_radius: f32,
+    pub max_radius: f32,
+    // Inliers a circle needs, as a fraction of its digital circumference
+    pub min_fraction: f32,
+}
+
+// Circles sought in one contour before giving up on the points left over
+const MAX_CIRCLES_PER_CONTOUR: usize = 8;
+
+// Fits circles to every contour traced from `edges` independently, so background edges
+// never vote for a ball and each contour only competes with itself. A contour can yield
+// several circles, each found among the points the previous ones did not explain, which
+// also picks out the visible arc of an occluded ball. Strongest first, like the Hough
+// transforms.
+pub fn ransac_circles(edges: ArrayView2<u8>, params: &RansacParams) -> Vec<Circle> {
+    let contours = find_contours(edges, 0, 0.0);
+    let mut candidates: Vec<Circle> = contours
+        .par_iter()
+        .enumerate()
+        .flat_map_iter(|(i, contour)| ransac_contour(&contour.points, params, i as u64))
+        .collect();
+
+    candidates.sort_unstable_by(|a, b| {
+        b.confidence
+            .total_cmp(&a.confidence)
+            .then(b.votes.cmp(&a.votes))
+            .then((a.radius, a.y, a.x).cmp(&(b.radius, b.y, b.x)))
+    });
+    suppress_overlaps(candidates.into_iter())
+}
+
+// Circles in one contour's points. `seed` makes the sampling reproducible.
+pub fn ransac_contour(points: &[Point], params: &RansacParams, seed: u64) -> Vec<Circle> {
+    let mut rng = Rng::new(seed);
+    let mut remaining: Vec<(f64, f64)> = points.iter().map(|p| (p.x as f64, p.y as f64)).collect();
+    let band = params.band as f64;
+    let mut circles = Vec::new();
+    let needed = expected_points(params.min_radius) * params.min_fraction;
+
+    while circles.len() < MAX_CIRCLES_PER_CONTOUR {
+        if remaining.len() < 3 || (remaining.len() as f32) < needed {
+            break;
+        }
+
+        let mut best: Option<((f64, f64, f64), usize)> = None;
+        for _ in 0..params.iterations {
+            let sample = [0; 3].map(|_| remaining[rng.below(remaining.len())]);
+            let Some(candidate) = circumcircle(sample) else {
+                continue;
+            };
+            if !radius_in_range(candidate.2, params) {
+                continue;
+            }
+            let count = count_inliers(&remaining, candidate, band);
+            if best.is_none_or(|(_, best_count)| count > best_count) {
+                best = Some((candidate, count));
+            }
+        }
+        let Some((candidate, _)) = best else {
+            break;
+        };
+
+        // Least-squares refit on the inliers, keeping the sample circle if it degrades
+        let inliers: Vec<(f64, f64)> = remaining
+            .iter()
+            .copied()
+            .filter(|&p| residual(p, candidate) <= band)
+            .collect();
+        let (x, y, r) = fit_circle(&inliers)
+            .filter(|&fit| {
+                radius_in_range(fit.2, params)
+                    && count_inliers(&remaining, fit, band) >= inliers.len()
+            })
+            .unwrap_or(candidate);
+
+        let count = count_inliers(&remaining, (x, y, r), band);
+        let confidence = count as f32 / expected_points(r as f32);
+        if confidence < params.min_fraction {
+            break;
+        }
+        remaining.retain(|&p| residual(p, (x, y, r)) > band);
+        // Balls centered outside the frame are explained but not reported
+        if x >= -0.5 && y >= -0.5 {
+            circles.push(Circle {
+                x: x.round() as u32,
+                y: y.round() as u32,
+                radius: r.round() as u32,
+                votes: count as u32,
+                confidence: confidence.min(1.0),
+                class: None,
+            });
+        }
+    }
+    circles
+}
+
+// Points on the traced contour of a whole ball, the digital disc border the Hough
+// transforms also measure their confidence against
+fn expected_points(radius: f32) -> f32 {
+    disc_border(radius).len().max(1) as f32
+}
+
+fn radius_in_range(radius: f64, params: &RansacParams) -> bool {
+    radius >= params.min_radius as f64 && radius <= params.max_radius as f64
+}
+
+fn residual((px, py): (f64, f64), (x, y, r): (f64, f64, f64)) -> f64 {
+    ((px - x).hypot(py - y) - r).abs()
+}
+
+fn count_inliers(points: &[(f64, f64)], circle: (f64, f64, f64), band: f64) -> usize {
+    points
+        .iter()
+        .filter(|&&p| residual(p, circle) <= band)
+        .count()
+}
+
+// Circle through three points, or None when they are (nearly) collinear
+fn circumcircle([(ax, ay), (bx, by), (cx, cy)]: [(f64, f64); 3]) -> Option<(f64, f64, f64)> {
+    let d = 2.0 * (ax * (by - cy) + bx * (cy - ay) + cx * (ay - by));
+    if d.abs() < 1e-9 {
+        return None;
+    }
+    let (a2, b2, c2) = (ax * ax + ay * ay, bx * bx + by * by, cx * cx + cy * cy);
+    let x = (a2 * (by - cy) + b2 * (cy - ay) + c2 * (ay - by)) / d;
+    let y = (a2 * (cx - bx) + b2 * (ax - cx) + c2 * (bx - ax)) / d;
+    Some((x, y, (ax - x).hypot(ay - y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+
+    const PARAMS: RansacParams = RansacParams {
+        iterations: 200,
+        band: 1.5,
+        min_radius: 5.0,
+        max_radius: 60.0,
+        min_fraction: 0.3,
+    };
+
+    // Traced outline of the union of `discs` given as (x, y, radius)
+    fn outline(width: usize, height: usize, discs: &[(i32, i32, i32)]) -> Vec<Point> {
+        let mask = Array2::from_shape_fn((height, width), |(y, x)| {
+            let inside = discs.iter().any(|&(cx, cy, r)| {
+                let (dx, dy) = (x as i32 - cx, y as i32 - cy);
+                dx * dx + dy * dy <= r * r
+            });
+            inside as u8 * 255
+        });
+        let mut contours = find_contours(mask.view(), 0, 0.0);
+        assert_eq!(contours.len(), 1);
+        contours.remove(0).points
+    }
+
+    fn close_to(circle: &Circle, (x, y, r): (i32, i32, i32)) -> bool {
+        (circle.x as i32 - x).abs() <= 1
+            && (circle.y as i32 - y).abs() <= 1
+            && (circle.radius as i32 - r).abs() <= 1
+    }
+
+    #[test]
+    fn whole_ball_reads_full_confidence() {
+        let circles = ransac_contour(&outline(100, 100, &[(50, 45, 30)]), &PARAMS, 0);
+        assert_eq!(circles.len(), 1);
+        assert!(close_to(&circles[0], (50, 45, 30)));
+        assert!(circles[0].confidence > 0.95);
+    }
+
+    #[test]
+    fn separates_overlapping_balls() {
+        let balls = [(40, 50, 25), (80, 50, 25)];
+        let circles = ransac_contour(&outline(130, 100, &balls), &PARAMS, 0);
+        assert_eq!(circles.len(), 2);
+        for ball in balls {
+            assert!(circles.iter().any(|c| close_to(c, ball)));
+        }
+        // Each keeps the part of its outline the other does not cover
+        for circle in &circles {
+            assert!(
+                (0.7..0.9).contains(&circle.confidence),
+                "{}",
+                circle.confidence
+            );
+        }
+    }
+
+    #[test]
+    fn partial_arc_reads_its_visible_fraction() {
+        let points: Vec<Point> = outline(100, 100, &[(50, 50, 30)])
+            .into_iter()
+            .filter(|p| p.y <= 50)
+            .collect();
+        let circles = ransac_contour(&points, &PARAMS, 0);
+        assert_eq!(circles.len(), 1);
+        assert!(close_to(&circles[0], (50, 50, 30)));
+        assert!((0.45..0.6).contains(&circles[0].confidence));
+    }
+
+    #[test]
+    fn respects_radius_range_and_min_fraction() {
+        let points = outline(100, 100, &[(50, 50, 30)]);
+        let small = RansacParams {
+            max_radius: 20.0,
+            ..PARAMS
+        };
+        assert!(ransac_contour(&points, &small, 0).is_empty());
+
+        let arc: Vec<Point> = points.iter().copied().filter(|p| p.x < 30).collect();
+        let strict = RansacParams {
+            min_fraction: 0.5,
+            ..PARAMS
+        };
+        assert!(ransac_contour(&arc, &strict, 0).is_empty());
+    }
+
+    #[test]
+    fn skips_balls_centered_outside_the_frame() {
+        // The part of a ball's outline inside the frame, for a ball centered at (x, 40)
+        let visible = |x: i32| -> Vec<Point> {
+            disc_border(30.0)
+                .into_iter()
+                .map(|(dx, dy)| Point {
+                    x: x + dx,
+                    y: 40 + dy,
+                })
+                .filter(|p| p.x >= 0)
+                .collect()
+        };
+        assert!(ransac_contour(&visible(-8), &PARAMS, 0).is_empty());
+        let inside = ransac_contour(&visible(2), &PARAMS, 0);
+        assert!(close_to(&inside[0], (2, 40, 30)));
+    }
+
+    #[test]
+    fn same_seed_same_circles() {
+        let points = outline(130, 100, &[(40, 50, 25), (80, 50, 25)]);
+        let run = |seed| {
+            ransac_contour(&points, &PARAMS, seed)
+                .iter()
+                .map(|c| (c.x, c.y, c.radius, c.votes))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(5), run(5));
+    }
+
+    #[test]
+    fn collinear_points_have_no_circumcircle() {
+        assert_eq!(circumcircle([(0.0, 0.0), (1.0, 1.0), (3.0, 3.0)]), None);
+        let (x, y, r) = circumcircle([(0.0, 0.0), (2.0, 0.0), (0.0, 2.0)]).unwrap();
+        assert!((x - 1.0).abs() < 1e-9 && (y - 1.0).abs() < 1e-9);
+        assert!((r - 2f64.sqrt()).abs() < 1e-9);
+    }
+}
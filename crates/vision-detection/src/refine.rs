@@ -0,0 +1,322 @@
+use ndarray::ArrayView2;
+use rayon::prelude::*;
+
+use crate::circle::Circle;
+
+// Circle with a subpixel center and radius. `residual` is the RMS distance in pixels of
+// the inlier edge points from the fitted circle, or None when the circle was not refined.
+// `class` is the detector's model class id, if it has one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RefinedCircle {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub votes: u32,
+    pub confidence: f32,
+    pub residual: Option<f32>,
+    pub inliers: u32,
+    pub class: Option<u32>,
+}
+
+impl From<&Circle> for RefinedCircle {
+    fn from(circle: &Circle) -> Self {
+        Self {
+            x: circle.x as f32,
+            y: circle.y as f32,
+            radius: circle.radius as f32,
+            votes: circle.votes,
+            confidence: circle.confidence,
+            residual: None,
+            inliers: 0,
+            class: circle.class,
+        }
+    }
+}
+
+const MIN_POINTS: usize = 5;
+const REJECTION_PASSES: usize = 3;
+// Residuals beyond this many robust standard deviations (1.4826 * MAD) are outliers
+const REJECTION_SIGMAS: f64 = 2.5;
+// Floor for the outlier cutoff so pixel quantization alone never rejects a point
+const MIN_CUTOFF: f64 = 0.75;
+
+// Refits each detected circle to the edge pixels within `band` pixels of it. Circles
+// whose fit fails, or moves further than `band`, keep their detected values.
+pub fn refine_circles(
+    edges: ArrayView2<u8>,
+    circles: &[RefinedCircle],
+    band: f32,
+) -> Vec<RefinedCircle> {
+    circles
+        .par_iter()
+        .map(|circle| {
+            if band > 0.0 {
+                if let Some(refined) = refine_circle(edges, circle, band) {
+                    return refined;
+                }
+            }
+            *circle
+        })
+        .collect()
+}
+
+pub fn refine_circle(
+    edges: ArrayView2<u8>,
+    circle: &RefinedCircle,
+    band: f32,
+) -> Option<RefinedCircle> {
+    let (height, width) = edges.dim();
+    let (cx, cy, r) = (circle.x, circle.y, circle.radius);
+    let reach = (r + band).ceil() as usize;
+    let (x0, x1) = (
+        (cx.round() as usize).saturating_sub(reach),
+        (cx.round() as usize + reach + 1).min(width),
+    );
+    let (y0, y1) = (
+        (cy.round() as usize).saturating_sub(reach),
+        (cy.round() as usize + reach + 1).min(height),
+    );
+
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            if edges[(y, x)] != 0 && ((x as f32 - cx).hypot(y as f32 - cy) - r).abs() <= band {
+                points.push((x as f64, y as f64));
+            }
+        }
+    }
+
+    let mut fit = None;
+    for _ in 0..REJECTION_PASSES {
+        if points.len() < MIN_POINTS {
+            break;
+        }
+        let (fx, fy, fr) = fit_circle(&points)?;
+        let residuals: Vec<f64> = points
+            .iter()
+            .map(|&(x, y)| ((x - fx).hypot(y - fy) - fr).abs())
+            .collect();
+        fit = Some((fx, fy, fr, rms(&residuals), points.len()));
+
+        let mut sorted = residuals.clone();
+        sorted.sort_unstable_by(f64::total_cmp);
+        let mad = sorted[sorted.len() / 2];
+        let cutoff = (REJECTION_SIGMAS * 1.4826 * mad).max(MIN_CUTOFF);
+        let before = points.len();
+        points = points
+            .into_iter()
+            .zip(&residuals)
+            .filter_map(|(p, &residual)| (residual <= cutoff).then_some(p))
+            .collect();
+        if points.len() == before {
+            break;
+        }
+    }
+
+    let (fx, fy, fr, residual, inliers) = fit?;
+    let moved = (fx as f32 - cx).hypot(fy as f32 - cy);
+    if moved > band || (fr as f32 - r).abs() > band {
+        return None;
+    }
+    Some(RefinedCircle {
+        x: fx as f32,
+        y: fy as f32,
+        radius: fr as f32,
+        votes: circle.votes,
+        confidence: circle.confidence,
+        residual: Some(residual as f32),
+        inliers: inliers as u32,
+        class: circle.class,
+    })
+}
+
+fn rms(values: &[f64]) -> f64 {
+    (values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+// Taubin's algebraic circle fit (Newton iteration on the characteristic polynomial, after
+// Chernov). Unlike the Kasa fit it is not biased towards small radii on partial arcs.
+// Returns (x, y, radius), or None for degenerate input such as collinear points.
+pub fn fit_circle(points: &[(f64, f64)]) -> Option<(f64, f64, f64)> {
+    if points.len() < 3 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+
+    let (mut mxx, mut myy, mut mxy, mut mxz, mut myz, mut mzz) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for &(x, y) in points {
+        let (xi, yi) = (x - mean_x, y - mean_y);
+        let zi = xi * xi + yi * yi;
+        mxy += xi * yi;
+        mxx += xi * xi;
+        myy += yi * yi;
+        mxz += xi * zi;
+        myz += yi * zi;
+        mzz += zi * zi;
+    }
+    let (mxx, myy, mxy, mxz, myz, mzz) = (mxx / n, myy / n, mxy / n, mxz / n, myz / n, mzz / n);
+
+    let mz = mxx + myy;
+    let cov_xy = mxx * myy - mxy * mxy;
+    let var_z = mzz - mz * mz;
+    let a3 = 4.0 * mz;
+    let a2 = -3.0 * mz * mz - mzz;
+    let a1 = var_z * mz + 4.0 * cov_xy * mz - mxz * mxz - myz * myz;
+    let a0 = mxz * (mxz * myy - myz * mxy) + myz * (myz * mxx - mxz * mxy) - var_z * cov_xy;
+
+    // Newton's method from x = 0 towards the smallest root
+    let (mut x, mut y) = (0.0f64, a0);
+    for _ in 0..100 {
+        let dy = a1 + x * (2.0 * a2 + 3.0 * a3 * x);
+        let x_new = x - y / dy;
+        if x_new == x || !x_new.is_finite() {
+            break;
+        }
+        let y_new = a0 + x_new * (a1 + x_new * (a2 + x_new * a3));
+        if y_new.abs() >= y.abs() {
+            break;
+        }
+        (x, y) = (x_new, y_new);
+    }
+
+    let det = x * x - x * mz + cov_xy;
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+    let center_x = (mxz * (myy - x) - myz * mxy) / det / 2.0;
+    let center_y = (myz * (mxx - x) - mxz * mxy) / det / 2.0;
+    let radius = (center_x * center_x + center_y * center_y + mz).sqrt();
+    radius
+        .is_finite()
+        .then_some((center_x + mean_x, center_y + mean_y, radius))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+    use std::f64::consts::PI;
+
+    const CENTER: (f64, f64) = (40.3, 35.6);
+    const RADIUS: f64 = 20.4;
+
+    // Edge pixels along the circle from angle `from` to `to`, in radians
+    fn arc(edges: &mut Array2<u8>, from: f64, to: f64) {
+        for i in 0..=720 {
+            let t = from + (to - from) * i as f64 / 720.0;
+            let x = (CENTER.0 + RADIUS * t.cos()).round() as usize;
+            let y = (CENTER.1 + RADIUS * t.sin()).round() as usize;
+            edges[(y, x)] = 255;
+        }
+    }
+
+    // The detected circle refine starts from, a pixel or so off
+    fn detected() -> RefinedCircle {
+        RefinedCircle {
+            x: 41.0,
+            y: 35.0,
+            radius: 21.0,
+            votes: 40,
+            confidence: 0.5,
+            residual: None,
+            inliers: 0,
+            class: None,
+        }
+    }
+
+    fn assert_near_truth(circle: &RefinedCircle, tolerance: f32) {
+        let error = (circle.x - CENTER.0 as f32)
+            .abs()
+            .max((circle.y - CENTER.1 as f32).abs())
+            .max((circle.radius - RADIUS as f32).abs());
+        assert!(error <= tolerance, "{circle:?} is {error} px off");
+    }
+
+    #[test]
+    fn fits_exact_partial_arc() {
+        // A quarter circle, where a Kasa fit would shrink the radius
+        let points: Vec<(f64, f64)> = (0..=30)
+            .map(|i| {
+                let t = PI / 2.0 * i as f64 / 30.0;
+                (12.3 + 25.5 * t.cos(), -4.7 + 25.5 * t.sin())
+            })
+            .collect();
+        let (x, y, r) = fit_circle(&points).unwrap();
+        assert!((x - 12.3).abs() < 1e-6 && (y + 4.7).abs() < 1e-6 && (r - 25.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn refines_rasterized_half_arc() {
+        let mut edges = Array2::zeros((80, 80));
+        arc(&mut edges, 0.0, PI);
+
+        let refined = refine_circles(edges.view(), &[detected()], 4.0);
+        assert_near_truth(&refined[0], 0.3);
+        assert!(refined[0].residual.unwrap() < 0.5);
+        assert!(refined[0].inliers > 40);
+        assert_eq!((refined[0].votes, refined[0].confidence), (40, 0.5));
+    }
+
+    #[test]
+    fn ignores_outliers() {
+        let mut edges = Array2::zeros((80, 80));
+        arc(&mut edges, -PI / 2.0, PI);
+        let clean = refine_circles(edges.view(), &[detected()], 4.0)[0];
+
+        // A blob at the center and a stripe just outside the band are never looked at,
+        // and stray pixels inside the band are rejected by the residual cutoff
+        for y in 30..40 {
+            for x in 35..45 {
+                edges[(y, x)] = 255;
+            }
+        }
+        for x in 30..50 {
+            edges[((CENTER.1 - RADIUS - 6.0) as usize, x)] = 255;
+        }
+        for (x, y) in [(62, 28), (63, 30), (61, 44)] {
+            edges[(y, x)] = 255;
+        }
+
+        let refined = refine_circles(edges.view(), &[detected()], 4.0)[0];
+        assert_near_truth(&refined, 0.3);
+        assert!((refined.x - clean.x).abs() < 0.2 && (refined.radius - clean.radius).abs() < 0.2);
+        assert!(refined.inliers <= clean.inliers);
+    }
+
+    #[test]
+    fn keeps_the_detected_circle_when_the_fit_is_degenerate() {
+        assert!(fit_circle(&[(0.0, 0.0), (1.0, 1.0)]).is_none());
+        let collinear: Vec<(f64, f64)> = (0..10).map(|i| (i as f64, 2.0 * i as f64)).collect();
+        assert!(fit_circle(&collinear).is_none());
+
+        // Too few edge pixels
+        let mut edges = Array2::zeros((80, 80));
+        for (x, y) in [(61, 35), (60, 40), (58, 45)] {
+            edges[(y, x)] = 255;
+        }
+        assert_eq!(
+            refine_circles(edges.view(), &[detected()], 4.0),
+            [detected()]
+        );
+
+        // A straight edge through the band
+        let mut edges = Array2::zeros((80, 80));
+        for y in 20..50 {
+            edges[(y, 60)] = 255;
+        }
+        assert_eq!(
+            refine_circles(edges.view(), &[detected()], 4.0),
+            [detected()]
+        );
+
+        // No band, no refit
+        let mut edges = Array2::zeros((80, 80));
+        arc(&mut edges, 0.0, PI);
+        assert_eq!(
+            refine_circles(edges.view(), &[detected()], 0.0),
+            [detected()]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use ndarray::{Array2, ArrayView2, ArrayViewMut1, Axis};
+use rayon::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeMode {
+    Nearest,
+    Bilinear,
+    // Averages every source pixel covered by the destination pixel; best for downscaling
+    Area,
+}
+
+// Pixel types the resizers work on, viewed as up to three u32 channels
+pub trait Pixel: Copy + Send + Sync {
+    fn channels(self) -> [u32; 3];
+    fn from_channels(channels: [u32; 3]) -> Self;
+}
+
+impl Pixel for u8 {
+    fn channels(self) -> [u32; 3] {
+        [self as u32, 0, 0]
+    }
+    fn from_channels(channels: [u32; 3]) -> Self {
+        channels[0] as u8
+    }
+}
+
+impl Pixel for [u8; 3] {
+    fn channels(self) -> [u32; 3] {
+        self.map(|c| c as u32)
+    }
+    fn from_channels(channels: [u32; 3]) -> Self {
+        channels.map(|c| c as u8)
+    }
+}
+
+// Resizes `src` to the shape of `dst`, one rayon task per destination row
+pub fn resize<T: Pixel>(src: ArrayView2<T>, dst: &mut Array2<T>, mode: ResizeMode) {
+    if src.dim() == dst.dim() {
+        dst.assign(&src);
+        return;
+    }
+
+    match mode {
+        ResizeMode::Nearest => resize_nearest(src, dst),
+        ResizeMode::Bilinear => resize_bilinear(src, dst),
+        ResizeMode::Area => resize_area(src, dst),
+    }
+}
+
+fn resize_nearest<T: Pixel>(src: ArrayView2<T>, dst: &mut Array2<T>) {
+    let (src_height, src_width) = src.dim();
+    let (dst_height, dst_width) = dst.dim();
+
+    let src_xs: Vec<usize> = (0..dst_width)
+        .map(|x| (x * src_width / dst_width).min(src_width - 1))
+        .collect();
+
+    for_each_row(dst, |y, mut row| {
+        let src_row = src.row((y * src_height / dst_height).min(src_height - 1));
+        for (pixel, &src_x) in row.iter_mut().zip(&src_xs) {
+            *pixel = src_row[src_x];
+        }
+    });
+}
+
+// Top-left source index, its clamped neighbour and the 8-bit weight of the neighbour
+// for each destination coordinate, sampling at pixel centers
+fn bilinear_taps(src_len: usize, dst_len: usize) -> Vec<(usize, usize, u32)> {
+    let ratio = src_len as f32 / dst_len as f32;
+    (0..dst_len)
+        .map(|i| {
+            let pos = ((i as f32 + 0.5) * ratio - 0.5).clamp(0.0, (src_len - 1) as f32);
+            let i0 = pos as usize;
+            let i1 = (i0 + 1).min(src_len - 1);
+            (i0, i1, (pos.fract() * 256.0) as u32)
+        })
+        .collect()
+}
+
+fn resize_bilinear<T: Pixel>(src: ArrayView2<T>, dst: &mut Array2<T>) {
+    let (src_height, src_width) = src.dim();
+    let (dst_height, dst_width) = dst.dim();
+
+    let x_taps = bilinear_taps(src_width, dst_width);
+    let y_taps = bilinear_taps(src_height, dst_height);
+
+    for_each_row(dst, |y, mut row| {
+        let (y0, y1, wy) = y_taps[y];
+        let (top, bottom) = (src.row(y0), src.row(y1));
+
+        for (pixel, &(x0, x1, wx)) in row.iter_mut().zip(&x_taps) {
+            let (tl, tr) = (top[x0].channels(), top[x1].channels());
+            let (bl, br) = (bottom[x0].channels(), bottom[x1].channels());
+            let mut out = [0u32; 3];
+            for c in 0..3 {
+                let upper = tl[c] * (256 - wx) + tr[c] * wx;
+                let lower = bl[c] * (256 - wx) + br[c] * wx;
+                out[c] = (upper * (256 - wy) + lower * wy + (1 << 15)) >> 16;
+            }
+            *pixel = T::from_channels(out);
+        }
+    });
+}
+
+fn resize_area<T: Pixel>(src: ArrayView2<T>, dst: &mut Array2<T>) {
+    let (src_height, src_width) = src.dim();
+    let (dst_height, dst_width) = dst.dim();
+
+    // Integer factor fast path: fixed-size boxes and a constant divisor
+    if src_height % dst_height == 0 && src_width % dst_width == 0 {
+        let fy = src_height / dst_height;
+        let fx = src_width / dst_width;
+        let count = (fx * fy) as u32;
+
+        for_each_row(dst, |y, mut row| {
+            let mut sums = vec![[0u32; 3]; dst_width];
+            for src_row in src.slice(ndarray::s![y * fy..(y + 1) * fy, ..]).rows() {
+                for (sum, chunk) in sums.iter_mut().zip(src_row.exact_chunks(fx)) {
+                    for &pixel in chunk {
+                        add_channels(sum, pixel);
+                    }
+                }
+            }
+            for (pixel, sum) in row.iter_mut().zip(&sums) {
+                *pixel = average(*sum, count);
+            }
+        });
+        return;
+    }
+
+    // Source span [start, end) covered by each destination coordinate
+    let spans = |src_len: usize, dst_len: usize| -> Vec<(usize, usize)> {
+        (0..dst_len)
+            .map(|i| {
+                let start = (i * src_len / dst_len).min(src_len - 1);
+                let end = ((i + 1) * src_len / dst_len).clamp(start + 1, src_len);
+                (start, end)
+            })
+            .collect()
+    };
+    let x_spans = spans(src_width, dst_width);
+    let y_spans = spans(src_height, dst_height);
+
+    for_each_row(dst, |y, mut row| {
+        let (y_start, y_end) = y_spans[y];
+        let mut sums = vec![[0u32; 3]; dst_width];
+        for src_row in src.slice(ndarray::s![y_start..y_end, ..]).rows() {
+            for (sum, &(x_start, x_end)) in sums.iter_mut().zip(&x_spans) {
+                for x in x_start..x_end {
+                    add_channels(sum, src_row[x]);
+                }
+            }
+        }
+        for (x, (pixel, sum)) in row.iter_mut().zip(&sums).enumerate() {
+            let (x_start, x_end) = x_spans[x];
+            let count = ((x_end - x_start) * (y_end - y_start)) as u32;
+            *pixel = average(*sum, count);
+        }
+    });
+}
+
+fn for_each_row<T, F>(dst: &mut Array2<T>, f: F)
+where
+    T: Pixel,
+    F: Fn(usize, ArrayViewMut1<T>) + Sync + Send,
+{
+    dst.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(y, row)| f(y, row));
+}
+
+fn add_channels<T: Pixel>(sum: &mut [u32; 3], pixel: T) {
+    for (s, c) in sum.iter_mut().zip(pixel.channels()) {
+        *s += c;
+    }
+}
+
+fn average<T: Pixel>(sum: [u32; 3], count: u32) -> T {
+    T::from_channels(sum.map(|s| (s + count / 2) / count))
+}
@@ -0,0 +1,21 @@
+// Small xorshift generator so results are reproducible without extra dependencies
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Avoid the all-zero state, which xorshift never leaves
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // Uniform index below `n`, which must not be 0
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        ((self.next_f32() * n as f32) as usize).min(n - 1)
+    }
+}
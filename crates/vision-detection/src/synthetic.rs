@@ -0,0 +1,260 @@
+use ndarray::{Array2, Axis};
+use rayon::prelude::*;
+
+use crate::rng::Rng;
+
+// A ball in the synthetic scene. Position is at frame 0, velocity in pixels per frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SyntheticBall {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub color: [u8; 3],
+    pub velocity: (f32, f32),
+}
+
+// Axis-aligned rectangle drawn over the balls to partially occlude them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Occluder {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: [u8; 3],
+}
+
+// Exact circle a ball was rendered at, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TruthCircle {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+}
+
+// Scene description rendered into RGB frames with matching ground truth
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub width: usize,
+    pub height: usize,
+    pub background: [u8; 3],
+    pub balls: Vec<SyntheticBall>,
+    pub occluders: Vec<Occluder>,
+    // Amplitude of uniform per-channel noise
+    pub noise: u8,
+    // Brightness falloff from left to right edge, 0.0 for flat lighting
+    pub gradient: f32,
+    pub seed: u64,
+}
+
+impl Scene {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            background: [40, 40, 40],
+            balls: Vec::new(),
+            occluders: Vec::new(),
+            noise: 0,
+            gradient: 0.0,
+            seed: 0,
+        }
+    }
+
+    // Adds a tightly packed pile of `count` touching balls around (x, y), row by row
+    pub fn add_cluster(&mut self, x: f32, y: f32, radius: f32, count: usize, color: [u8; 3]) {
+        let per_row = (count as f32).sqrt().ceil() as usize;
+        let row_height = radius * 3f32.sqrt();
+
+        for i in 0..count {
+            let row = i / per_row;
+            let col = i % per_row;
+            let offset = if row % 2 == 1 { radius } else { 0.0 };
+            self.balls.push(SyntheticBall {
+                x: x + col as f32 * 2.0 * radius + offset,
+                y: y + row as f32 * row_height,
+                radius,
+                color,
+                velocity: (0.0, 0.0),
+            });
+        }
+    }
+
+    // Circles of every ball at `frame_index`, after motion. Balls wrap around the frame edges.
+    pub fn ground_truth(&self, frame_index: u64) -> Vec<TruthCircle> {
+        let t = frame_index as f32;
+        self.balls
+            .iter()
+            .map(|ball| TruthCircle {
+                x: (ball.x + ball.velocity.0 * t).rem_euclid(self.width as f32),
+                y: (ball.y + ball.velocity.1 * t).rem_euclid(self.height as f32),
+                radius: ball.radius,
+            })
+            .collect()
+    }
+
+    // Renders frame `frame_index` into `frame` and returns its ground truth
+    pub fn render(&self, frame_index: u64, frame: &mut Array2<[u8; 3]>) -> Vec<TruthCircle> {
+        if frame.dim() != (self.height, self.width) {
+            *frame = Array2::from_elem((self.height, self.width), self.background);
+        }
+
+        let truth = self.ground_truth(frame_index);
+        let width = self.width as f32;
+
+        frame
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(y, mut row)| {
+                let mut rng = Rng::new(self.seed ^ frame_index.rotate_left(32) ^ y as u64);
+                let py = y as f32 + 0.5;
+
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let px = x as f32 + 0.5;
+                    let mut color = self.background.map(|c| c as f32);
+
+                    // Painter's order: later balls overlap earlier ones
+                    for (circle, ball) in truth.iter().zip(&self.balls) {
+                        let reach = circle.radius + 1.0;
+                        if (px - circle.x).abs() > reach || (py - circle.y).abs() > reach {
+                            continue;
+                        }
+                        let dist = ((px - circle.x).powi(2) + (py - circle.y).powi(2)).sqrt();
+                        let coverage = (circle.radius + 0.5 - dist).clamp(0.0, 1.0);
+                        if coverage > 0.0 {
+                            // Darken towards the rim so balls read as spheres
+                            let shade = 1.0 - 0.35 * (dist / circle.radius).min(1.0).powi(2);
+                            for (c, &ball_c) in color.iter_mut().zip(&ball.color) {
+                                *c += (ball_c as f32 * shade - *c) * coverage;
+                            }
+                        }
+                    }
+
+                    for occluder in &self.occluders {
+                        if px >= occluder.x
+                            && px < occluder.x + occluder.width
+                            && py >= occluder.y
+                            && py < occluder.y + occluder.height
+                        {
+                            color = occluder.color.map(|c| c as f32);
+                        }
+                    }
+
+                    let light = 1.0 + self.gradient * (0.5 - px / width);
+                    let noise = self.noise as f32;
+                    *pixel = color.map(|c| {
+                        let jitter = if noise > 0.0 {
+                            rng.next_f32() * 2.0 * noise - noise
+                        } else {
+                            0.0
+                        };
+                        (c * light + jitter).round().clamp(0.0, 255.0) as u8
+                    });
+                }
+            });
+
+        truth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> Scene {
+        let mut scene = Scene::new(160, 120);
+        scene.balls.push(SyntheticBall {
+            x: 40.0,
+            y: 60.0,
+            radius: 20.0,
+            color: [250, 200, 0],
+            velocity: (0.0, 0.0),
+        });
+        scene.balls.push(SyntheticBall {
+            x: 150.0,
+            y: 30.0,
+            radius: 10.0,
+            color: [0, 0, 250],
+            velocity: (5.0, 2.0),
+        });
+        scene
+    }
+
+    // Pixels at least about half covered by the yellow ball, rim shading included
+    fn covered(frame: &Array2<[u8; 3]>) -> usize {
+        frame.iter().filter(|pixel| pixel[0] > 100).count()
+    }
+
+    #[test]
+    fn ground_truth_moves_and_wraps() {
+        let scene = scene();
+        let truth = scene.ground_truth(4);
+        assert_eq!(
+            truth[0],
+            TruthCircle {
+                x: 40.0,
+                y: 60.0,
+                radius: 20.0
+            }
+        );
+        assert_eq!(
+            truth[1],
+            TruthCircle {
+                x: 10.0,
+                y: 38.0,
+                radius: 10.0
+            }
+        );
+    }
+
+    #[test]
+    fn render_draws_balls_at_ground_truth() {
+        let mut scene = scene();
+        scene.balls.truncate(1);
+        let mut frame = Array2::default((0, 0));
+        let truth = scene.render(0, &mut frame);
+
+        assert_eq!(truth, scene.ground_truth(0));
+        assert_eq!(frame.dim(), (120, 160));
+        assert_eq!(frame[(60, 40)], [250, 200, 0]);
+        assert_eq!(frame[(60, 61)], scene.background);
+        assert_eq!(frame[(60, 18)], scene.background);
+        // Antialiased disc covers πr² pixels
+        let area = std::f32::consts::PI * 20.0 * 20.0;
+        assert!((covered(&frame) as f32 - area).abs() < 0.03 * area);
+    }
+
+    #[test]
+    fn occluders_cover_balls() {
+        let mut scene = scene();
+        scene.balls.truncate(1);
+        scene.occluders.push(Occluder {
+            x: 40.0,
+            y: 0.0,
+            width: 40.0,
+            height: 120.0,
+            color: scene.background,
+        });
+        let mut frame = Array2::default((0, 0));
+        scene.render(0, &mut frame);
+
+        assert_eq!(frame[(60, 39)], [250, 200, 0]);
+        assert_eq!(frame[(60, 41)], scene.background);
+        let half = std::f32::consts::PI * 20.0 * 20.0 / 2.0;
+        assert!((covered(&frame) as f32 - half).abs() < 0.05 * half);
+    }
+
+    #[test]
+    fn noise_is_deterministic_per_seed() {
+        let mut scene = scene();
+        scene.noise = 10;
+        let (mut a, mut b) = (Array2::default((0, 0)), Array2::default((0, 0)));
+        scene.render(3, &mut a);
+        scene.render(3, &mut b);
+        assert_eq!(a, b);
+
+        scene.seed = 1;
+        scene.render(3, &mut b);
+        assert_ne!(a, b);
+    }
+}
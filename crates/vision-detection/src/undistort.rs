@@ -0,0 +1,273 @@
+use ndarray::{Array2, ArrayView2, Axis};
+use rayon::prelude::*;
+
+// Pinhole intrinsics with Brown-Conrady distortion (OpenCV's k1, k2, p1, p2, k3 ordering)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraIntrinsics {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    pub dist: [f32; 5],
+}
+
+impl CameraIntrinsics {
+    // Intrinsics for the same lens at a resolution scaled by (sx, sy)
+    pub fn scaled(&self, sx: f32, sy: f32) -> Self {
+        Self {
+            fx: self.fx * sx,
+            fy: self.fy * sy,
+            cx: self.cx * sx,
+            cy: self.cy * sy,
+            dist: self.dist,
+        }
+    }
+
+    // Maps normalized undistorted coordinates to normalized distorted coordinates
+    fn distort_normalized(&self, x: f32, y: f32) -> (f32, f32) {
+        let [k1, k2, p1, p2, k3] = self.dist;
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+        let xd = x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+        let yd = y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+        (xd, yd)
+    }
+
+    // Undistorts a single pixel coordinate by fixed-point iteration, for when only
+    // detected points need correcting rather than the whole image
+    pub fn undistort_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let [k1, k2, p1, p2, k3] = self.dist;
+        let xd = (x - self.cx) / self.fx;
+        let yd = (y - self.cy) / self.fy;
+
+        let (mut xu, mut yu) = (xd, yd);
+        for _ in 0..10 {
+            let r2 = xu * xu + yu * yu;
+            let inv_radial = 1.0 / (1.0 + r2 * (k1 + r2 * (k2 + r2 * k3)));
+            let dx = 2.0 * p1 * xu * yu + p2 * (r2 + 2.0 * xu * xu);
+            let dy = p1 * (r2 + 2.0 * yu * yu) + 2.0 * p2 * xu * yu;
+            xu = (xd - dx) * inv_radial;
+            yu = (yd - dy) * inv_radial;
+        }
+
+        (xu * self.fx + self.cx, yu * self.fy + self.cy)
+    }
+}
+
+// Source location for one output pixel: top-left neighbour plus 8-bit fixed-point weights
+#[derive(Clone, Copy)]
+struct RemapEntry {
+    src_x: u32,
+    src_y: u32,
+    wx: u16,
+    wy: u16,
+}
+
+const OUTSIDE: RemapEntry = RemapEntry {
+    src_x: u32::MAX,
+    src_y: u32::MAX,
+    wx: 0,
+    wy: 0,
+};
+
+// Precomputed undistortion lookup table for a single resolution
+pub struct RemapTable {
+    entries: Array2<RemapEntry>,
+}
+
+impl RemapTable {
+    pub fn new(intrinsics: &CameraIntrinsics, height: usize, width: usize) -> Self {
+        let mut entries = Array2::from_elem((height, width), OUTSIDE);
+
+        entries
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(y, mut row)| {
+                for (x, entry) in row.iter_mut().enumerate() {
+                    let xn = (x as f32 - intrinsics.cx) / intrinsics.fx;
+                    let yn = (y as f32 - intrinsics.cy) / intrinsics.fy;
+                    let (xd, yd) = intrinsics.distort_normalized(xn, yn);
+                    let src_x = xd * intrinsics.fx + intrinsics.cx;
+                    let src_y = yd * intrinsics.fy + intrinsics.cy;
+
+                    if src_x < 0.0
+                        || src_y < 0.0
+                        || src_x > (width - 1) as f32
+                        || src_y > (height - 1) as f32
+                    {
+                        continue;
+                    }
+
+                    *entry = RemapEntry {
+                        src_x: src_x as u32,
+                        src_y: src_y as u32,
+                        wx: (src_x.fract() * 256.0) as u16,
+                        wy: (src_y.fract() * 256.0) as u16,
+                    };
+                }
+            });
+
+        Self { entries }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        self.entries.dim()
+    }
+
+    // Resamples `src` into `dst` with bilinear interpolation. Pixels that map outside the
+    // source image are left black. Returns false, leaving `dst` untouched, when either
+    // frame is at another resolution than the table, which then has to be rebuilt.
+    pub fn apply(&self, src: ArrayView2<[u8; 3]>, dst: &mut Array2<[u8; 3]>) -> bool {
+        if src.dim() != self.dim() || dst.dim() != self.dim() {
+            return false;
+        }
+        let (height, width) = src.dim();
+
+        dst.axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip(self.entries.axis_iter(Axis(0)))
+            .for_each(|(mut dst_row, entry_row)| {
+                for (pixel, entry) in dst_row.iter_mut().zip(entry_row.iter()) {
+                    if entry.src_x == u32::MAX {
+                        *pixel = [0; 3];
+                        continue;
+                    }
+
+                    let x0 = entry.src_x as usize;
+                    let y0 = entry.src_y as usize;
+                    let x1 = (x0 + 1).min(width - 1);
+                    let y1 = (y0 + 1).min(height - 1);
+                    let (wx, wy) = (entry.wx as u32, entry.wy as u32);
+
+                    let tl = src[(y0, x0)];
+                    let tr = src[(y0, x1)];
+                    let bl = src[(y1, x0)];
+                    let br = src[(y1, x1)];
+
+                    for c in 0..3 {
+                        let top = tl[c] as u32 * (256 - wx) + tr[c] as u32 * wx;
+                        let bottom = bl[c] as u32 * (256 - wx) + br[c] as u32 * wx;
+                        pixel[c] = ((top * (256 - wy) + bottom * wy + (1 << 15)) >> 16) as u8;
+                    }
+                }
+            });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+    fn intrinsics(dist: [f32; 5]) -> CameraIntrinsics {
+        CameraIntrinsics {
+            fx: 60.0,
+            fy: 62.0,
+            cx: 31.0,
+            cy: 24.5,
+            dist,
+        }
+    }
+
+    // Pincushion with some tangential distortion, so the output corners fall outside the
+    // source image
+    const PINCUSHION: [f32; 5] = [0.12, 0.03, 0.002, -0.003, 0.0];
+
+    fn distort_pixel(intrinsics: &CameraIntrinsics, x: f32, y: f32) -> (f32, f32) {
+        let (xd, yd) = intrinsics.distort_normalized(
+            (x - intrinsics.cx) / intrinsics.fx,
+            (y - intrinsics.cy) / intrinsics.fy,
+        );
+        (
+            xd * intrinsics.fx + intrinsics.cx,
+            yd * intrinsics.fy + intrinsics.cy,
+        )
+    }
+
+    // Smooth color ramp over undistorted pixel coordinates
+    fn ramp(x: f32, y: f32) -> [u8; 3] {
+        [
+            (x * 3.0).clamp(0.0, 255.0).round() as u8,
+            (y * 4.0).clamp(0.0, 255.0).round() as u8,
+            128,
+        ]
+    }
+
+    #[test]
+    fn undistort_point_inverts_distortion() {
+        for dist in [PINCUSHION, [-0.25, 0.06, -0.001, 0.002, -0.01]] {
+            let intrinsics = intrinsics(dist);
+            for y in (0..HEIGHT).step_by(4) {
+                for x in (0..WIDTH).step_by(4) {
+                    let (x, y) = (x as f32 + 0.3, y as f32 + 0.7);
+                    let (xd, yd) = distort_pixel(&intrinsics, x, y);
+                    let (xu, yu) = intrinsics.undistort_point(xd, yd);
+                    assert!(
+                        (xu - x).abs() < 1e-2 && (yu - y).abs() < 1e-2,
+                        "({x}, {y}) came back as ({xu}, {yu}) with {dist:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn remap_without_distortion_is_identity() {
+        let table = RemapTable::new(&intrinsics([0.0; 5]), HEIGHT, WIDTH);
+        let src = Array2::from_shape_fn((HEIGHT, WIDTH), |(y, x)| ramp(x as f32, y as f32));
+        let mut dst = Array2::from_elem((HEIGHT, WIDTH), [1u8; 3]);
+        assert!(table.apply(src.view(), &mut dst));
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn remap_round_trips_a_distorted_frame() {
+        // Camera frame of a ramp seen through the lens: each pixel shows the ramp at the
+        // undistorted position it images
+        let intrinsics = intrinsics(PINCUSHION);
+        let src = Array2::from_shape_fn((HEIGHT, WIDTH), |(y, x)| {
+            let (xu, yu) = intrinsics.undistort_point(x as f32, y as f32);
+            ramp(xu, yu)
+        });
+        let table = RemapTable::new(&intrinsics, HEIGHT, WIDTH);
+        let mut dst = Array2::from_elem((HEIGHT, WIDTH), [1u8; 3]);
+        assert!(table.apply(src.view(), &mut dst));
+
+        let mut inside = 0;
+        for ((y, x), pixel) in dst.indexed_iter() {
+            let (xd, yd) = distort_pixel(&intrinsics, x as f32, y as f32);
+            if xd < 0.0 || yd < 0.0 || xd > (WIDTH - 1) as f32 || yd > (HEIGHT - 1) as f32 {
+                assert_eq!(*pixel, [0; 3], "outside pixel ({x}, {y}) not black");
+                continue;
+            }
+            inside += 1;
+            let expected = ramp(x as f32, y as f32);
+            for c in 0..3 {
+                assert!(
+                    pixel[c].abs_diff(expected[c]) <= 2,
+                    "({x}, {y}): {pixel:?} instead of {expected:?}"
+                );
+            }
+        }
+        // The corners fall outside, most of the frame does not
+        assert_eq!(dst[(0, 0)], [0; 3]);
+        assert!(inside > WIDTH * HEIGHT * 3 / 4, "{inside} pixels inside");
+    }
+
+    #[test]
+    fn remap_leaves_frames_of_another_size_untouched() {
+        let table = RemapTable::new(&intrinsics(PINCUSHION), HEIGHT, WIDTH);
+        let src = Array2::from_elem((HEIGHT / 2, WIDTH / 2), [200u8; 3]);
+        let mut dst = Array2::from_elem((HEIGHT / 2, WIDTH / 2), [7u8; 3]);
+        assert!(!table.apply(src.view(), &mut dst));
+        assert!(dst.iter().all(|&pixel| pixel == [7; 3]));
+
+        let src = Array2::from_elem((HEIGHT, WIDTH), [200u8; 3]);
+        assert!(!table.apply(src.view(), &mut dst));
+        assert!(dst.iter().all(|&pixel| pixel == [7; 3]));
+    }
+}
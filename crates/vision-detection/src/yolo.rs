@@ -0,0 +1,410 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use ndarray::{Array2, Array4, ArrayView2};
+use tract_onnx::prelude::*;
+
+use crate::circle::Circle;
+use crate::pipeline::{Detector, Slot, StageData};
+use crate::resize::{resize, ResizeMode};
+
+// Gray YOLO models are trained to expect in the letterbox padding
+const PAD_VALUE: u8 = 114;
+
+// Row layout of a YOLO detection head's output, batch dimension removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YoloLayout {
+    // One row per candidate: cx, cy, w, h, objectness, class scores (YOLOv5/v7)
+    Objectness,
+    // One column per candidate: cx, cy, w, h, class scores, no objectness (YOLOv8 and later)
+    Transposed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct YoloParams {
+    pub layout: YoloLayout,
+    // Model class ids reported as balls; the others are ignored
+    pub classes: Vec<u32>,
+    pub min_score: f32,
+    // Boxes overlapping a stronger one by more than this intersection over union are dropped
+    pub max_iou: f32,
+}
+
+// How a frame was fitted into the model input, to map boxes back to frame pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Letterbox {
+    pub scale: f32,
+    pub pad_x: f32,
+    pub pad_y: f32,
+}
+
+impl Letterbox {
+    pub fn to_frame(&self, x: f32, y: f32) -> (f32, f32) {
+        ((x - self.pad_x) / self.scale, (y - self.pad_y) / self.scale)
+    }
+}
+
+// Scales `frame` to fit a `size` square keeping its aspect ratio, pads the rest, and lays
+// it out as a 1x3xSxS tensor of channels in 0..=1. `frame` must be RGB.
+pub fn letterbox(frame: ArrayView2<[u8; 3]>, size: usize) -> (Array4<f32>, Letterbox) {
+    let (height, width) = frame.dim();
+    let scale = size as f32 / height.max(width).max(1) as f32;
+    let scaled_height = ((height as f32 * scale).round() as usize).clamp(1, size);
+    let scaled_width = ((width as f32 * scale).round() as usize).clamp(1, size);
+    let mut scaled = Array2::from_elem((scaled_height, scaled_width), [0u8; 3]);
+    resize(frame, &mut scaled, ResizeMode::Bilinear);
+
+    let top = (size - scaled_height) / 2;
+    let left = (size - scaled_width) / 2;
+    let mut input = Array4::from_elem((1, 3, size, size), PAD_VALUE as f32 / 255.0);
+    for ((y, x), pixel) in scaled.indexed_iter() {
+        for (c, &value) in pixel.iter().enumerate() {
+            input[(0, c, top + y, left + x)] = value as f32 / 255.0;
+        }
+    }
+
+    let letterbox = Letterbox {
+        scale,
+        pad_x: left as f32,
+        pad_y: top as f32,
+    };
+    (input, letterbox)
+}
+
+// Candidate box in frame pixels
+#[derive(Clone, Copy, Debug)]
+struct Detection {
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    score: f32,
+    class: u32,
+}
+
+impl Detection {
+    fn iou(&self, other: &Detection) -> f32 {
+        let w = (self.x1.min(other.x1) - self.x0.max(other.x0)).max(0.0);
+        let h = (self.y1.min(other.y1) - self.y0.max(other.y0)).max(0.0);
+        let intersection = w * h;
+        let area = |d: &Detection| (d.x1 - d.x0) * (d.y1 - d.y0);
+        let union = area(self) + area(other) - intersection;
+        if union > 0.0 {
+            intersection / union
+        } else {
+            0.0
+        }
+    }
+}
+
+// Balls in a detection head's `output`, strongest first like the Hough transforms. Each
+// box becomes the circle inscribed in it, with the model's score as its confidence and
+// its best wanted class; there are no votes. Overlapping boxes are suppressed whatever
+// their class, since two boxes on one ball are still one ball.
+pub fn decode(output: ArrayView2<f32>, letterbox: &Letterbox, params: &YoloParams) -> Vec<Circle> {
+    let rows = match params.layout {
+        YoloLayout::Objectness => output,
+        YoloLayout::Transposed => output.reversed_axes(),
+    };
+    let first_class = match params.layout {
+        YoloLayout::Objectness => 5,
+        YoloLayout::Transposed => 4,
+    };
+
+    let mut candidates: Vec<Detection> = rows
+        .rows()
+        .into_iter()
+        .filter_map(|row| {
+            let objectness = match params.layout {
+                YoloLayout::Objectness => row[4],
+                YoloLayout::Transposed => 1.0,
+            };
+            let (class, class_score) = params
+                .classes
+                .iter()
+                .filter_map(|&class| Some((class, *row.get(first_class + class as usize)?)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))?;
+            let score = objectness * class_score;
+            if score < params.min_score {
+                return None;
+            }
+            let (cx, cy, w, h) = (row[0], row[1], row[2], row[3]);
+            let (x0, y0) = letterbox.to_frame(cx - w / 2.0, cy - h / 2.0);
+            let (x1, y1) = letterbox.to_frame(cx + w / 2.0, cy + h / 2.0);
+            Some(Detection {
+                x0,
+                y0,
+                x1,
+                y1,
+                score,
+                class,
+            })
+        })
+        .collect();
+    candidates.sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut kept: Vec<Detection> = Vec::new();
+    for candidate in candidates {
+        if kept.iter().all(|k| k.iou(&candidate) <= params.max_iou) {
+            kept.push(candidate);
+        }
+    }
+
+    kept.into_iter()
+        .filter_map(|d| {
+            let x = (d.x0 + d.x1) / 2.0;
+            let y = (d.y0 + d.y1) / 2.0;
+            // Balls centered outside the frame are not reported
+            (x >= -0.5 && y >= -0.5).then(|| Circle {
+                x: x.round() as u32,
+                y: y.round() as u32,
+                radius: ((d.x1 - d.x0 + d.y1 - d.y0) / 4.0).round() as u32,
+                votes: 0,
+                confidence: d.score.min(1.0),
+                class: Some(d.class),
+            })
+        })
+        .collect()
+}
+
+// An ONNX detection model optimized for a fixed 1x3xSxS input and run on the CPU
+pub struct YoloModel {
+    plan: Option<TypedRunnableModel<TypedModel>>,
+    input_size: usize,
+}
+
+impl YoloModel {
+    // Loads and optimizes the model at `path`. This takes seconds for the usual models, so
+    // callers keep loaded models around.
+    pub fn load(path: impl AsRef<Path>, input_size: usize) -> TractResult<Self> {
+        let plan = tract_onnx::onnx()
+            .model_for_path(path)?
+            .with_input_fact(0, f32::fact([1, 3, input_size, input_size]).into())?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(Self {
+            plan: Some(plan),
+            input_size,
+        })
+    }
+
+    // A model that detects nothing, for building a pipeline only to check its structure
+    pub fn empty(input_size: usize) -> Self {
+        Self {
+            plan: None,
+            input_size,
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    // Letterboxes the RGB `frame`, runs the model on it and decodes its first output
+    pub fn detect(
+        &self,
+        frame: ArrayView2<[u8; 3]>,
+        params: &YoloParams,
+    ) -> TractResult<Vec<Circle>> {
+        let Some(plan) = &self.plan else {
+            return Ok(Vec::new());
+        };
+        let (input, letterbox) = letterbox(frame, self.input_size);
+        let input = Tensor::from_shape(
+            input.shape(),
+            input
+                .as_slice()
+                .expect("letterbox builds a contiguous tensor"),
+        )?;
+        let outputs = plan.run(tvec!(input.into()))?;
+        // One image in, so the batch dimension is 1
+        let &[1, rows, cols] = outputs[0].shape() else {
+            return Err(TractError::msg(format!(
+                "expected a 1xNxM detection output, got shape {:?}",
+                outputs[0].shape()
+            )));
+        };
+        let output = ArrayView2::from_shape((rows, cols), outputs[0].as_slice::<f32>()?)?;
+        Ok(decode(output, &letterbox, params))
+    }
+}
+
+// YOLO model run on the frame in place of the mask and edge stages. The frame must be RGB.
+pub struct Yolo {
+    model: Arc<YoloModel>,
+    params: YoloParams,
+}
+
+impl Yolo {
+    pub fn new(model: Arc<YoloModel>, params: YoloParams) -> Self {
+        Self { model, params }
+    }
+}
+
+impl Detector for Yolo {
+    fn name(&self) -> &'static str {
+        "yolo"
+    }
+
+    fn inputs(&self) -> &'static [Slot] {
+        &[Slot::Image]
+    }
+
+    fn detect(&mut self, data: &StageData) -> Vec<Circle> {
+        self.model
+            .detect(data.image.view(), &self.params)
+            .unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "YOLO inference failed");
+                Vec::new()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: Letterbox = Letterbox {
+        scale: 1.0,
+        pad_x: 0.0,
+        pad_y: 0.0,
+    };
+
+    fn params(layout: YoloLayout) -> YoloParams {
+        YoloParams {
+            layout,
+            classes: vec![1, 2],
+            min_score: 0.5,
+            max_iou: 0.45,
+        }
+    }
+
+    fn summary(circles: &[Circle]) -> Vec<(u32, u32, u32, Option<u32>)> {
+        circles
+            .iter()
+            .map(|c| (c.x, c.y, c.radius, c.class))
+            .collect()
+    }
+
+    // Boxes as cx, cy, w, h and one score for each of three classes, ordered so the
+    // strongest does not come first
+    const BOXES: [[f32; 7]; 6] = [
+        // Weaker duplicate of the next box, suppressed
+        [104.0, 100.0, 40.0, 40.0, 0.0, 0.6, 0.0],
+        [100.0, 100.0, 40.0, 40.0, 0.0, 0.8, 0.1],
+        // Overlaps the first box, but by less than max_iou
+        [130.0, 100.0, 40.0, 40.0, 0.0, 0.0, 0.75],
+        [300.0, 200.0, 20.0, 20.0, 0.0, 0.0, 0.7],
+        // Class 0 is not wanted
+        [50.0, 50.0, 20.0, 20.0, 0.95, 0.0, 0.0],
+        // Below min_score
+        [400.0, 300.0, 20.0, 20.0, 0.0, 0.4, 0.3],
+    ];
+
+    const EXPECTED: [(u32, u32, u32, Option<u32>); 3] = [
+        (100, 100, 20, Some(1)),
+        (130, 100, 20, Some(2)),
+        (300, 200, 10, Some(2)),
+    ];
+
+    #[test]
+    fn decodes_and_suppresses_transposed_output() {
+        let mut output = Array2::zeros((7, BOXES.len()));
+        for (i, row) in BOXES.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                output[(j, i)] = value;
+            }
+        }
+
+        let circles = decode(output.view(), &IDENTITY, &params(YoloLayout::Transposed));
+        assert_eq!(summary(&circles), EXPECTED);
+        assert_eq!(circles[0].confidence, 0.8);
+        assert_eq!(circles[0].votes, 0);
+    }
+
+    #[test]
+    fn decodes_objectness_output() {
+        // Objectness of 1 keeps the class scores as they are; the extra half-objectness
+        // box would score 0.45 and is dropped
+        let mut output = Array2::zeros((BOXES.len() + 1, 8));
+        for (i, row) in BOXES.iter().enumerate() {
+            output[(i, 4)] = 1.0;
+            for (j, &value) in row.iter().enumerate() {
+                output[(i, if j < 4 { j } else { j + 1 })] = value;
+            }
+        }
+        output.row_mut(BOXES.len()).assign(&ndarray::arr1(&[
+            500.0, 50.0, 20.0, 20.0, 0.5, 0.0, 0.9, 0.0,
+        ]));
+
+        let circles = decode(output.view(), &IDENTITY, &params(YoloLayout::Objectness));
+        assert_eq!(summary(&circles), EXPECTED);
+        assert!((circles[2].confidence - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn decode_maps_boxes_back_through_the_letterbox() {
+        let letterbox = Letterbox {
+            scale: 0.5,
+            pad_x: 0.0,
+            pad_y: 60.0,
+        };
+        // A ball fully in the frame, and one centered above it in the padding
+        let output = ndarray::arr2(&[
+            [55.0, 165.0, 10.0, 10.0, 0.0, 0.9, 0.0],
+            [100.0, 40.0, 10.0, 10.0, 0.0, 0.9, 0.0],
+        ]);
+
+        let circles = decode(output.t(), &letterbox, &params(YoloLayout::Transposed));
+        assert_eq!(summary(&circles), [(110, 210, 10, Some(1))]);
+    }
+
+    #[test]
+    fn letterbox_round_trips() {
+        // 640x400 frame into a 320 input: halved, with 60 rows of padding above and below
+        let mut frame = Array2::from_elem((400, 640), [0u8, 0, 255]);
+        for y in 200..220 {
+            for x in 100..120 {
+                frame[(y, x)] = [255, 0, 0];
+            }
+        }
+
+        let (input, letterbox) = letterbox(frame.view(), 320);
+        assert_eq!(input.dim(), (1, 3, 320, 320));
+        assert_eq!(
+            letterbox,
+            Letterbox {
+                scale: 0.5,
+                pad_x: 0.0,
+                pad_y: 60.0,
+            }
+        );
+
+        let pad = PAD_VALUE as f32 / 255.0;
+        for (y, x) in [(0, 0), (59, 319), (260, 10), (319, 319)] {
+            for c in 0..3 {
+                assert_eq!(input[(0, c, y, x)], pad, "padding at {y},{x}");
+            }
+        }
+        // Background just inside the frame, and the red square's center at (110, 210)
+        assert_eq!([0, 1, 2].map(|c| input[(0, c, 60, 0)]), [0.0, 0.0, 1.0]);
+        assert_eq!(
+            [0, 1, 2].map(|c| input[(0, c, 60 + 105, 55)]),
+            [1.0, 0.0, 0.0]
+        );
+
+        assert_eq!(letterbox.to_frame(55.0, 165.0), (110.0, 210.0));
+        assert_eq!(letterbox.to_frame(0.0, 60.0), (0.0, 0.0));
+        assert_eq!(letterbox.to_frame(320.0, 260.0), (640.0, 400.0));
+    }
+
+    #[test]
+    fn empty_model_detects_nothing() {
+        let frame = Array2::from_elem((48, 64), [255u8, 0, 0]);
+        let model = YoloModel::empty(32);
+        let circles = model
+            .detect(frame.view(), &params(YoloLayout::Transposed))
+            .unwrap();
+        assert!(circles.is_empty());
+    }
+}
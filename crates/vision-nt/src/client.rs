@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::message::{
+    publish_frame, read_values, time_sync_frame, write_value, Value, TIME_SYNC_ID,
+};
+
+// NetworkTables 4 servers listen here unless the address names another port
+const DEFAULT_PORT: u16 = 5810;
+const SUBPROTOCOLS: &str = "v4.1.networktables.first.wpi.edu, networktables.first.wpi.edu";
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const TIME_SYNC_PERIOD: Duration = Duration::from_secs(3);
+// Offset value while the server's clock is unknown
+const UNSYNCED: i64 = i64::MIN;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Publishes topic values to a NetworkTables 4 server. Values are set from any thread
+// without waiting on the network; a background task connects, reconnects when the server
+// goes away, and sends the latest value of each changed topic at the publish rate.
+#[derive(Clone)]
+pub struct NtClient {
+    topics: Arc<Mutex<HashMap<String, Topic>>>,
+    clock: Arc<Clock>,
+}
+
+struct Topic {
+    // Client-chosen id the values are sent under, kept across reconnects
+    id: i64,
+    value: Value,
+    set_at: Instant,
+    changed: bool,
+}
+
+impl NtClient {
+    // Starts publishing to `server` (a host, or host:port) as `identity`. Must be called
+    // from within a tokio runtime.
+    pub fn start(server: &str, identity: &str, publish_rate_hz: u32) -> Self {
+        let client = NtClient {
+            topics: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(Clock::new()),
+        };
+        let address = if server.contains(':') {
+            server.to_string()
+        } else {
+            format!("{server}:{DEFAULT_PORT}")
+        };
+        let url = format!("ws://{address}/nt/{identity}");
+        let period = Duration::from_secs_f64(1.0 / publish_rate_hz.max(1) as f64);
+        tokio::spawn(client.clone().run(url, period));
+        client
+    }
+
+    // Sets several topics at once; they are always sent together. A topic is announced
+    // the first time it is set, and keeps the type of its first value.
+    pub fn set_all<N: AsRef<str>>(&self, values: impl IntoIterator<Item = (N, Value)>) {
+        let set_at = Instant::now();
+        let mut topics = self.topics.lock().unwrap();
+        for (name, value) in values {
+            let name = name.as_ref();
+            match topics.get_mut(name) {
+                Some(topic) if topic.value.type_name() == value.type_name() => {
+                    topic.value = value;
+                    topic.set_at = set_at;
+                    topic.changed = true;
+                }
+                Some(_) => {}
+                None => {
+                    let id = topics.len() as i64 + 1;
+                    let topic = Topic {
+                        id,
+                        value,
+                        set_at,
+                        changed: true,
+                    };
+                    topics.insert(name.to_string(), topic);
+                }
+            }
+        }
+    }
+
+    // `at` on the server's clock in microseconds, the robot's FPGA time when the robot is
+    // the server. None until the first time sync with a server.
+    pub fn server_time_us(&self, at: Instant) -> Option<i64> {
+        self.clock.server_time_us(at)
+    }
+
+    async fn run(self, url: String, period: Duration) {
+        let mut warned = false;
+        loop {
+            match self.connect(&url).await {
+                Ok(socket) => {
+                    tracing::info!(url = %url, "Connected to NetworkTables");
+                    warned = false;
+                    if let Err(e) = self.session(socket, period).await {
+                        tracing::warn!(error = %e, "NetworkTables connection lost");
+                    } else {
+                        tracing::warn!("NetworkTables server closed the connection");
+                    }
+                }
+                // Logged once until the next connection, so a missing robot is not a
+                // warning every second
+                Err(e) if !warned => {
+                    tracing::warn!(url = %url, error = %e, "Cannot reach NetworkTables, retrying");
+                    warned = true;
+                }
+                Err(e) => tracing::debug!(url = %url, error = %e, "Cannot reach NetworkTables"),
+            }
+            self.clock.reset();
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn connect(&self, url: &str) -> Result<Socket, Error> {
+        let mut request = url.into_client_request()?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(SUBPROTOCOLS),
+        );
+        let (socket, _) = tokio_tungstenite::connect_async(request).await?;
+        Ok(socket)
+    }
+
+    async fn session(&self, socket: Socket, period: Duration) -> Result<(), Error> {
+        let (mut sink, mut stream) = socket.split();
+        // A new server knows none of the topics or values
+        for topic in self.topics.lock().unwrap().values_mut() {
+            topic.changed = true;
+        }
+        let mut announced = 0;
+        let mut flush = tokio::time::interval(period);
+        let mut time_sync = tokio::time::interval(TIME_SYNC_PERIOD);
+
+        loop {
+            tokio::select! {
+                _ = time_sync.tick() => {
+                    let frame = time_sync_frame(self.clock.local_us(Instant::now()));
+                    sink.send(Message::binary(frame)).await?;
+                }
+                _ = flush.tick() => {
+                    // Values need server timestamps, so nothing is sent before the first sync
+                    if self.clock.is_synced() {
+                        let (announcements, values) = self.take_changes(&mut announced);
+                        if let Some(announcements) = announcements {
+                            sink.send(Message::text(announcements)).await?;
+                        }
+                        if !values.is_empty() {
+                            sink.send(Message::binary(values)).await?;
+                        }
+                    }
+                }
+                message = stream.next() => match message {
+                    Some(Ok(Message::Binary(frame))) => {
+                        for (id, server_us, value) in read_values(&frame) {
+                            if let (TIME_SYNC_ID, Some(sent_us)) = (id, value.as_i64()) {
+                                self.clock.sync(sent_us, server_us);
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    // Announcements of other topics and properties are of no use to a
+                    // client that only publishes
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                },
+            }
+        }
+    }
+
+    // Publish messages for topics the server has not heard of, with ids past `announced`,
+    // and a frame with the values set since the last flush
+    fn take_changes(&self, announced: &mut i64) -> (Option<String>, Vec<u8>) {
+        let mut topics = self.topics.lock().unwrap();
+        let new_topics: Vec<_> = topics
+            .iter()
+            .filter(|(_, topic)| topic.id > *announced)
+            .map(|(name, topic)| (name.as_str(), topic.id, &topic.value))
+            .collect();
+        let announcements = (!new_topics.is_empty()).then(|| publish_frame(new_topics));
+        *announced = topics.len() as i64;
+
+        let mut values = Vec::new();
+        for topic in topics.values_mut().filter(|topic| topic.changed) {
+            let timestamp = self.clock.server_time_us(topic.set_at).unwrap_or_default();
+            write_value(&mut values, topic.id, timestamp, &topic.value);
+            topic.changed = false;
+        }
+        (announcements, values)
+    }
+}
+
+// Maps this process's clock onto the server's, from the round trip of time sync messages
+struct Clock {
+    started: Instant,
+    // Server time minus local time in microseconds, or UNSYNCED
+    offset_us: AtomicI64,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Clock {
+            started: Instant::now(),
+            offset_us: AtomicI64::new(UNSYNCED),
+        }
+    }
+
+    fn local_us(&self, at: Instant) -> i64 {
+        at.saturating_duration_since(self.started).as_micros() as i64
+    }
+
+    // The server answered the message sent at `sent_us` with its time `server_us`, which
+    // it read about half the round trip ago
+    fn sync(&self, sent_us: i64, server_us: i64) {
+        let now_us = self.local_us(Instant::now());
+        let offset = server_us + (now_us - sent_us) / 2 - now_us;
+        self.offset_us.store(offset, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.offset_us.store(UNSYNCED, Ordering::Relaxed);
+    }
+
+    fn is_synced(&self) -> bool {
+        self.offset_us.load(Ordering::Relaxed) != UNSYNCED
+    }
+
+    fn server_time_us(&self, at: Instant) -> Option<i64> {
+        let offset = self.offset_us.load(Ordering::Relaxed);
+        (offset != UNSYNCED).then(|| self.local_us(at) + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    type ServerSocket = WebSocketStream<TcpStream>;
+
+    const SERVER_TIME_US: i64 = 5_000_000;
+
+    // The handshake callback's error type is tungstenite's, not ours to shrink
+    #[allow(clippy::result_large_err)]
+    async fn accept(listener: &TcpListener) -> ServerSocket {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_hdr_async(stream, |request: &Request, mut response: Response| {
+            assert_eq!(request.uri().path(), "/nt/test");
+            let protocols = request.headers()["Sec-WebSocket-Protocol"]
+                .to_str()
+                .unwrap();
+            assert!(protocols.contains("v4.1.networktables.first.wpi.edu"));
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static("v4.1.networktables.first.wpi.edu"),
+            );
+            Ok(response)
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn receive(socket: &mut ServerSocket) -> Message {
+        tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("client went quiet")
+            .unwrap()
+            .unwrap()
+    }
+
+    // Answers the client's time sync as a server whose clock reads SERVER_TIME_US
+    async fn answer_time_sync(socket: &mut ServerSocket) {
+        let Message::Binary(frame) = receive(socket).await else {
+            panic!("expected a time sync request");
+        };
+        let (id, _, sent) = read_values(&frame).remove(0);
+        assert_eq!(id, TIME_SYNC_ID);
+        let mut reply = Vec::new();
+        write_value(
+            &mut reply,
+            TIME_SYNC_ID,
+            SERVER_TIME_US,
+            &Value::Int(sent.as_i64().unwrap()),
+        );
+        socket.send(Message::binary(reply)).await.unwrap();
+    }
+
+    // Topic names announced in a publish frame, by id
+    async fn announced(socket: &mut ServerSocket) -> HashMap<i64, String> {
+        let Message::Text(text) = receive(socket).await else {
+            panic!("expected topic announcements");
+        };
+        let messages: serde_json::Value = serde_json::from_str(&text).unwrap();
+        messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| {
+                assert_eq!(message["method"], "publish");
+                let params = &message["params"];
+                let id = params["pubuid"].as_i64().unwrap();
+                (id, params["name"].as_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    // Values in a binary frame by topic name, checking their timestamps are on the
+    // server's clock
+    async fn values(
+        socket: &mut ServerSocket,
+        names: &HashMap<i64, String>,
+    ) -> HashMap<String, rmpv::Value> {
+        let Message::Binary(frame) = receive(socket).await else {
+            panic!("expected values");
+        };
+        read_values(&frame)
+            .into_iter()
+            .map(|(id, timestamp, value)| {
+                assert!(
+                    (timestamp - SERVER_TIME_US).abs() < 2_000_000,
+                    "{timestamp}"
+                );
+                (names[&id].clone(), value)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn publishes_and_republishes_after_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = NtClient::start(&listener.local_addr().unwrap().to_string(), "test", 100);
+        client.set_all([
+            ("/test/total_ms", Value::Double(12.5)),
+            ("/test/frame_id", Value::Int(3)),
+        ]);
+        assert_eq!(client.server_time_us(Instant::now()), None);
+
+        let mut socket = accept(&listener).await;
+        answer_time_sync(&mut socket).await;
+        let names = announced(&mut socket).await;
+        assert_eq!(names.len(), 2);
+        let received = values(&mut socket, &names).await;
+        assert_eq!(received["/test/total_ms"].as_f64(), Some(12.5));
+        assert_eq!(received["/test/frame_id"].as_i64(), Some(3));
+        let now = client.server_time_us(Instant::now()).unwrap();
+        assert!((now - SERVER_TIME_US).abs() < 2_000_000, "{now}");
+
+        // Only changed topics are sent, and a new topic is announced on its own
+        client.set_all([
+            ("/test/total_ms", Value::Double(8.0)),
+            (
+                "/test/stages",
+                Value::StringArray(vec!["decode".to_string()]),
+            ),
+        ]);
+        let new_names = announced(&mut socket).await;
+        assert_eq!(new_names.values().collect::<Vec<_>>(), ["/test/stages"]);
+        let names: HashMap<_, _> = names.into_iter().chain(new_names).collect();
+        let received = values(&mut socket, &names).await;
+        assert_eq!(received.len(), 2);
+        assert_eq!(received["/test/total_ms"].as_f64(), Some(8.0));
+
+        // A restarted server gets every topic and its latest value
+        drop(socket);
+        let mut socket = accept(&listener).await;
+        answer_time_sync(&mut socket).await;
+        let names = announced(&mut socket).await;
+        assert_eq!(names.len(), 3);
+        let received = values(&mut socket, &names).await;
+        assert_eq!(received.len(), 3);
+        assert_eq!(received["/test/total_ms"].as_f64(), Some(8.0));
+        assert_eq!(received["/test/frame_id"].as_i64(), Some(3));
+    }
+}
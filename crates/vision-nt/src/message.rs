@@ -0,0 +1,180 @@
+use std::io::Cursor;
+
+use rmpv::Value as Pack;
+use serde_json::json;
+
+// Topic id for time sync: the client sends its own time under it and the server answers
+// with its time, still carrying the client's
+pub const TIME_SYNC_ID: i64 = -1;
+
+// A topic value, with the types this client publishes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Double(f64),
+    Int(i64),
+    String(String),
+    DoubleArray(Vec<f64>),
+    StringArray(Vec<String>),
+}
+
+impl Value {
+    // Type string announced with the topic
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Boolean(_) => "boolean",
+            Value::Double(_) => "double",
+            Value::Int(_) => "int",
+            Value::String(_) => "string",
+            Value::DoubleArray(_) => "double[]",
+            Value::StringArray(_) => "string[]",
+        }
+    }
+
+    // Type id sent with every value
+    fn type_id(&self) -> u8 {
+        match self {
+            Value::Boolean(_) => 0,
+            Value::Double(_) => 1,
+            Value::Int(_) => 2,
+            Value::String(_) => 4,
+            Value::DoubleArray(_) => 17,
+            Value::StringArray(_) => 20,
+        }
+    }
+
+    fn pack(&self) -> Pack {
+        match self {
+            Value::Boolean(value) => Pack::from(*value),
+            Value::Double(value) => Pack::from(*value),
+            Value::Int(value) => Pack::from(*value),
+            Value::String(value) => Pack::from(value.as_str()),
+            Value::DoubleArray(values) => Pack::Array(values.iter().map(|&v| v.into()).collect()),
+            Value::StringArray(values) => {
+                Pack::Array(values.iter().map(|v| v.as_str().into()).collect())
+            }
+        }
+    }
+}
+
+// Text frame announcing topics, each with the id its values are sent under
+pub fn publish_frame<'a>(topics: impl IntoIterator<Item = (&'a str, i64, &'a Value)>) -> String {
+    let messages: Vec<_> = topics
+        .into_iter()
+        .map(|(name, id, value)| {
+            json!({
+                "method": "publish",
+                "params": {
+                    "name": name,
+                    "pubuid": id,
+                    "type": value.type_name(),
+                    "properties": {},
+                },
+            })
+        })
+        .collect();
+    serde_json::Value::Array(messages).to_string()
+}
+
+// Appends one value message to a binary frame; a frame holds any number of them
+pub fn write_value(frame: &mut Vec<u8>, id: i64, timestamp_us: i64, value: &Value) {
+    let message = Pack::Array(vec![
+        id.into(),
+        timestamp_us.into(),
+        value.type_id().into(),
+        value.pack(),
+    ]);
+    rmpv::encode::write_value(frame, &message).expect("writes to a Vec do not fail");
+}
+
+// Time sync request, answered by the server with its current time
+pub fn time_sync_frame(client_time_us: i64) -> Vec<u8> {
+    let mut frame = Vec::new();
+    write_value(&mut frame, TIME_SYNC_ID, 0, &Value::Int(client_time_us));
+    frame
+}
+
+// Value messages in a binary frame as (id, timestamp, value). Reading stops at the first
+// malformed message.
+pub fn read_values(frame: &[u8]) -> Vec<(i64, i64, Pack)> {
+    let mut reader = Cursor::new(frame);
+    let mut values = Vec::new();
+    while (reader.position() as usize) < frame.len() {
+        let Ok(Pack::Array(fields)) = rmpv::decode::read_value(&mut reader) else {
+            break;
+        };
+        let Ok([id, timestamp, _, value]) = <[Pack; 4]>::try_from(fields) else {
+            break;
+        };
+        match (id.as_i64(), timestamp.as_i64()) {
+            (Some(id), Some(timestamp)) => values.push((id, timestamp, value)),
+            _ => break,
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let values = [
+            Value::Boolean(true),
+            Value::Double(12.5),
+            Value::Int(-3),
+            Value::String("main".to_string()),
+            Value::DoubleArray(vec![0.25, 4.0]),
+            Value::StringArray(vec!["decode".to_string(), "resize".to_string()]),
+        ];
+        let mut frame = Vec::new();
+        for (id, value) in values.iter().enumerate() {
+            write_value(&mut frame, id as i64, 1_000 + id as i64, value);
+        }
+
+        let read = read_values(&frame);
+        assert_eq!(read.len(), values.len());
+        for (id, ((read_id, timestamp, packed), value)) in read.iter().zip(&values).enumerate() {
+            assert_eq!((*read_id, *timestamp), (id as i64, 1_000 + id as i64));
+            assert_eq!(*packed, value.pack());
+        }
+    }
+
+    #[test]
+    fn writes_type_ids() {
+        let mut frame = Vec::new();
+        write_value(&mut frame, 7, 0, &Value::DoubleArray(vec![1.0]));
+        let message = rmpv::decode::read_value(&mut Cursor::new(&frame)).unwrap();
+        assert_eq!(message[2].as_u64(), Some(17));
+    }
+
+    #[test]
+    fn stops_at_malformed_messages() {
+        let mut frame = time_sync_frame(42);
+        rmpv::encode::write_value(&mut frame, &Pack::from("not a value")).unwrap();
+        write_value(&mut frame, 3, 0, &Value::Int(1));
+
+        let read = read_values(&frame);
+        assert_eq!(read, [(TIME_SYNC_ID, 0, Pack::from(42))]);
+    }
+
+    #[test]
+    fn announces_topics() {
+        let value = Value::Double(1.0);
+        let frame: serde_json::Value =
+            serde_json::from_str(&publish_frame([("/vision/total_ms", 4, &value)])).unwrap();
+        assert_eq!(
+            frame,
+            json!([{
+                "method": "publish",
+                "params": {
+                    "name": "/vision/total_ms",
+                    "pubuid": 4,
+                    "type": "double",
+                    "properties": {},
+                },
+            }])
+        );
+    }
+}